@@ -1,49 +1,512 @@
+use crate::blockchain::merkle::{leaf_hash, merkle_root};
+use crate::blockchain::state::ValidatorRegistration;
+use crate::consensus::bft::QuorumCertificate;
+use crate::consensus::pouw::{
+    TaskCancel, TaskClaim, TaskCommit, TaskError, TaskResult, TaskReveal, TaskSpec, TaskTypeSpec,
+    WorkerRegistration,
+};
+use crate::consensus::slashing::{DoubleSignEvidence, EvidenceError};
+use crate::cryptography::sha3::{hex_hash, sha3_256, Hash};
+use crate::cryptography::sphincs::Sphincs;
+use crate::wallet::key_manager::NodeKey;
 use serde::{Deserialize, Serialize};
-use sha3::{Digest, Sha3_256};
+use std::fmt;
+
+/// Version of the canonical header encoding produced by `BlockHeader::encode`.
+pub const BLOCK_VERSION: u32 = 1;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
     pub sender: String,
     pub receiver: String,
     pub amount: u64,
+    pub nonce: u64,
+    #[serde(default)]
+    pub kind: TransactionKind,
     pub signature: Vec<u8>,
 }
 
+/// What a transaction does. Every kind bumps the sender's nonce.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransactionKind {
+    /// Moves `amount` from the sender to `receiver`.
+    #[default]
+    Transfer,
+    /// Reports a validator that signed two blocks at one height. Anyone may
+    /// submit it; `receiver` must be empty and `amount` zero.
+    DoubleSign(DoubleSignEvidence),
+    /// Moves `amount` from the sender's balance to its own stake. `receiver`
+    /// must be empty.
+    Stake,
+    /// Moves `amount` of the sender's own stake into its unbonding queue.
+    /// `receiver` must be empty.
+    Unstake,
+    /// Moves `amount` from the sender's balance to a delegation to the
+    /// validator named in `receiver`.
+    Delegate,
+    /// Moves `amount` of the sender's delegation to the validator named in
+    /// `receiver` into its unbonding queue.
+    Undelegate,
+    /// Submits the sender's result for a single-replica PoUW task it has
+    /// claimed. The result is verified at the start of the next block and,
+    /// if accepted, the task's reward is paid to the sender. `receiver` must
+    /// be empty and `amount` zero.
+    TaskResult(TaskResult),
+    /// Claims an open PoUW task for the sender, who then has the task's
+    /// claim period to submit or commit to a result. `receiver` must be
+    /// empty and `amount` zero.
+    TaskClaim(TaskClaim),
+    /// Commits the sender to its result for a replicated PoUW task it has
+    /// claimed, without disclosing it. `receiver` must be empty and `amount`
+    /// zero.
+    TaskCommit(TaskCommit),
+    /// Reveals the result behind the sender's commitment once every worker
+    /// on the task has committed. `receiver` must be empty and `amount` zero.
+    TaskReveal(TaskReveal),
+    /// Creates a PoUW task funded by the sender. `amount` is moved from the
+    /// sender's balance into the task's escrow: the reward plus the spec's
+    /// verifier fee. `receiver` must be empty.
+    TaskCreate(TaskSpec),
+    /// Cancels a task the sender created, refunding its escrow. `receiver`
+    /// must be empty and `amount` zero.
+    TaskCancel(TaskCancel),
+    /// Registers a task type whose WebAssembly program computes, and lets
+    /// every node check, the results of tasks of that type. The sender
+    /// becomes its owner. `receiver` must be empty and `amount` zero.
+    TaskTypeRegister(TaskTypeSpec),
+    /// Enters the sender in the worker registry with the capabilities it
+    /// advertises, or updates its entry. `amount`, which may be zero, is
+    /// moved from the sender's balance to its collateral. `receiver` must be
+    /// empty.
+    WorkerRegister(WorkerRegistration),
+    /// Takes the sender out of the worker registry, refunding its
+    /// collateral. `receiver` must be empty and `amount` zero.
+    WorkerUnregister,
+    /// Sets the share of its delegators' rewards the sender keeps as a
+    /// validator, in basis points. `receiver` must be empty and `amount`
+    /// zero.
+    SetCommission { commission_bps: u64 },
+    /// Registers the sender as a validator whose node signs blocks with the
+    /// given consensus key, or updates its registration. `receiver` must be
+    /// empty and `amount` zero.
+    ValidatorRegister(ValidatorRegistration),
+}
+
+impl TransactionKind {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            TransactionKind::Transfer => out.push(0),
+            TransactionKind::DoubleSign(evidence) => {
+                out.push(1);
+                evidence.encode(out);
+            }
+            TransactionKind::Stake => out.push(2),
+            TransactionKind::Unstake => out.push(3),
+            TransactionKind::Delegate => out.push(4),
+            TransactionKind::Undelegate => out.push(5),
+            TransactionKind::TaskResult(submission) => {
+                out.push(6);
+                submission.encode(out);
+            }
+            TransactionKind::TaskClaim(claim) => {
+                out.push(7);
+                claim.encode(out);
+            }
+            TransactionKind::TaskCommit(commit) => {
+                out.push(8);
+                commit.encode(out);
+            }
+            TransactionKind::TaskReveal(reveal) => {
+                out.push(9);
+                reveal.encode(out);
+            }
+            TransactionKind::TaskCreate(spec) => {
+                out.push(10);
+                spec.encode(out);
+            }
+            TransactionKind::TaskCancel(cancel) => {
+                out.push(11);
+                cancel.encode(out);
+            }
+            TransactionKind::TaskTypeRegister(spec) => {
+                out.push(12);
+                spec.encode(out);
+            }
+            TransactionKind::WorkerRegister(registration) => {
+                out.push(13);
+                registration.encode(out);
+            }
+            TransactionKind::WorkerUnregister => out.push(14),
+            TransactionKind::SetCommission { commission_bps } => {
+                out.push(15);
+                out.extend_from_slice(&commission_bps.to_be_bytes());
+            }
+            TransactionKind::ValidatorRegister(registration) => {
+                out.push(16);
+                registration.encode(out);
+            }
+        }
+    }
+
+    /// The task a worker's claim, result, commitment or reveal is for.
+    pub fn worker_task_id(&self) -> Option<&str> {
+        match self {
+            TransactionKind::TaskClaim(claim) => Some(&claim.task_id),
+            TransactionKind::TaskResult(submission) => Some(&submission.task_id),
+            TransactionKind::TaskCommit(commit) => Some(&commit.task_id),
+            TransactionKind::TaskReveal(reveal) => Some(&reveal.task_id),
+            _ => None,
+        }
+    }
+
+    /// Whether the transaction takes `amount` out of the sender's balance.
+    pub fn debits_balance(&self) -> bool {
+        matches!(
+            self,
+            TransactionKind::Transfer
+                | TransactionKind::Stake
+                | TransactionKind::Delegate
+                | TransactionKind::TaskCreate(_)
+                | TransactionKind::WorkerRegister(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionError {
+    /// The sender is not a hex-encoded SPHINCS+ public key.
+    InvalidSender,
+    EmptyReceiver,
+    ZeroAmount,
+    InvalidSignature,
+    /// The nonce does not match the sender's next expected nonce.
+    InvalidNonce {
+        expected: u64,
+        found: u64,
+    },
+    InsufficientBalance {
+        balance: u64,
+        amount: u64,
+    },
+    BalanceOverflow,
+    /// The transaction kind takes no receiver but one is set.
+    UnexpectedReceiver,
+    /// The transaction kind moves no value but an amount is set.
+    UnexpectedAmount,
+    InvalidEvidence(EvidenceError),
+    /// An unstake or undelegation exceeds what the sender has bonded.
+    InsufficientStake {
+        staked: u64,
+        amount: u64,
+    },
+    /// A delegation names a wallet that is not a registered validator.
+    UnknownValidator,
+    /// The sender has no stake of its own.
+    NoStake,
+    /// The commission is above 10000 basis points, or raises the current
+    /// rate by more than an epoch allows.
+    InvalidCommission {
+        current: u64,
+        requested: u64,
+    },
+    /// A validator registration's consensus key is not a public key or
+    /// belongs to another validator, or its name is too long.
+    InvalidValidatorRegistration,
+    InvalidTask(TaskError),
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::InvalidSender => write!(f, "sender is not a valid public key"),
+            TransactionError::EmptyReceiver => write!(f, "receiver is empty"),
+            TransactionError::ZeroAmount => write!(f, "amount must be greater than zero"),
+            TransactionError::InvalidSignature => write!(f, "signature verification failed"),
+            TransactionError::InvalidNonce { expected, found } => {
+                write!(f, "expected nonce {}, found {}", expected, found)
+            }
+            TransactionError::InsufficientBalance { balance, amount } => {
+                write!(f, "balance {} is below amount {}", balance, amount)
+            }
+            TransactionError::BalanceOverflow => write!(f, "receiver balance would overflow"),
+            TransactionError::UnexpectedReceiver => write!(f, "receiver must be empty"),
+            TransactionError::UnexpectedAmount => write!(f, "amount must be zero"),
+            TransactionError::InvalidEvidence(error) => write!(f, "invalid evidence: {}", error),
+            TransactionError::InsufficientStake { staked, amount } => {
+                write!(f, "stake {} is below amount {}", staked, amount)
+            }
+            TransactionError::UnknownValidator => write!(f, "receiver is not a validator"),
+            TransactionError::NoStake => write!(f, "sender has no stake of its own"),
+            TransactionError::InvalidCommission { current, requested } => write!(
+                f,
+                "commission cannot change from {} to {} basis points this epoch",
+                current, requested
+            ),
+            TransactionError::InvalidValidatorRegistration => {
+                write!(f, "consensus key is invalid or taken, or name is too long")
+            }
+            TransactionError::InvalidTask(error) => write!(f, "invalid task operation: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+impl Transaction {
+    /// Bytes covered by the sender's signature: the hash of the genesis
+    /// block of the network the transaction is meant for, followed by the
+    /// encoding without the signature. A signature made for one network
+    /// therefore does not verify on another.
+    pub fn signing_bytes(&self, genesis_hash: &Hash) -> Vec<u8> {
+        let mut out = genesis_hash.to_vec();
+        self.encode_unsigned(&mut out);
+        out
+    }
+
+    /// Canonical binary encoding: length-prefixed sender and receiver,
+    /// big-endian amount and nonce, a one-byte kind tag followed by the
+    /// kind's payload, and the length-prefixed signature.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_unsigned(&mut out);
+        encode_bytes(&mut out, &self.signature);
+        out
+    }
+
+    fn encode_unsigned(&self, out: &mut Vec<u8>) {
+        encode_bytes(out, self.sender.as_bytes());
+        encode_bytes(out, self.receiver.as_bytes());
+        out.extend_from_slice(&self.amount.to_be_bytes());
+        out.extend_from_slice(&self.nonce.to_be_bytes());
+        self.kind.encode(out);
+    }
+
+    /// Merkle leaf hash of the transaction.
+    pub fn hash(&self) -> Hash {
+        leaf_hash(&self.encode())
+    }
+
+    /// Checks that the transaction is well formed and signed by its sender
+    /// for the network whose genesis block hashes to `genesis_hash`. Wallet
+    /// addresses are hex-encoded SPHINCS+ public keys.
+    pub fn verify(&self, genesis_hash: &Hash) -> Result<(), TransactionError> {
+        let public_key = hex::decode(&self.sender).map_err(|_| TransactionError::InvalidSender)?;
+        if Sphincs::public_key_from_bytes(&public_key).is_none() {
+            return Err(TransactionError::InvalidSender);
+        }
+        match &self.kind {
+            TransactionKind::Transfer | TransactionKind::Delegate | TransactionKind::Undelegate => {
+                if self.receiver.is_empty() {
+                    return Err(TransactionError::EmptyReceiver);
+                }
+                if self.amount == 0 {
+                    return Err(TransactionError::ZeroAmount);
+                }
+            }
+            TransactionKind::Stake | TransactionKind::Unstake => {
+                if !self.receiver.is_empty() {
+                    return Err(TransactionError::UnexpectedReceiver);
+                }
+                if self.amount == 0 {
+                    return Err(TransactionError::ZeroAmount);
+                }
+            }
+            TransactionKind::DoubleSign(evidence) => {
+                if !self.receiver.is_empty() {
+                    return Err(TransactionError::UnexpectedReceiver);
+                }
+                if self.amount != 0 {
+                    return Err(TransactionError::UnexpectedAmount);
+                }
+                evidence
+                    .verify()
+                    .map_err(TransactionError::InvalidEvidence)?;
+            }
+            TransactionKind::TaskResult(_)
+            | TransactionKind::TaskClaim(_)
+            | TransactionKind::TaskCommit(_)
+            | TransactionKind::TaskReveal(_)
+            | TransactionKind::TaskCancel(_)
+            | TransactionKind::WorkerUnregister
+            | TransactionKind::SetCommission { .. } => {
+                if !self.receiver.is_empty() {
+                    return Err(TransactionError::UnexpectedReceiver);
+                }
+                if self.amount != 0 {
+                    return Err(TransactionError::UnexpectedAmount);
+                }
+                let result = match &self.kind {
+                    TransactionKind::TaskResult(submission) => Some(&submission.result),
+                    TransactionKind::TaskReveal(reveal) => Some(&reveal.result),
+                    _ => None,
+                };
+                if result.is_some_and(|result| result.is_empty()) {
+                    return Err(TransactionError::InvalidTask(TaskError::EmptyResult));
+                }
+            }
+            TransactionKind::TaskCreate(spec) => {
+                if !self.receiver.is_empty() {
+                    return Err(TransactionError::UnexpectedReceiver);
+                }
+                spec.verify(self.amount)
+                    .map_err(TransactionError::InvalidTask)?;
+            }
+            TransactionKind::TaskTypeRegister(spec) => {
+                if !self.receiver.is_empty() {
+                    return Err(TransactionError::UnexpectedReceiver);
+                }
+                if self.amount != 0 {
+                    return Err(TransactionError::UnexpectedAmount);
+                }
+                spec.verify().map_err(TransactionError::InvalidTask)?;
+            }
+            TransactionKind::WorkerRegister(registration) => {
+                if !self.receiver.is_empty() {
+                    return Err(TransactionError::UnexpectedReceiver);
+                }
+                registration
+                    .verify()
+                    .map_err(TransactionError::InvalidTask)?;
+            }
+            TransactionKind::ValidatorRegister(registration) => {
+                if !self.receiver.is_empty() {
+                    return Err(TransactionError::UnexpectedReceiver);
+                }
+                if self.amount != 0 {
+                    return Err(TransactionError::UnexpectedAmount);
+                }
+                registration.verify()?;
+            }
+        }
+        if !Sphincs::verify_with_key_bytes(
+            &self.signing_bytes(genesis_hash),
+            &self.signature,
+            &public_key,
+        ) {
+            return Err(TransactionError::InvalidSignature);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub version: u32,
+    pub height: u64,
+    #[serde(with = "hex_hash")]
+    pub previous_hash: Hash,
+    #[serde(with = "hex_hash")]
+    pub merkle_root: Hash,
+    /// Number of transactions, the leaf count of `merkle_root`.
+    pub tx_count: u32,
+    #[serde(with = "hex_hash")]
+    pub state_root: Hash,
+    pub timestamp: u128,
+    pub proposer: String,
+}
+
+impl BlockHeader {
+    /// Canonical binary encoding of the header. All integers are big-endian:
+    ///
+    /// `version (4) | height (8) | previous_hash (32) | merkle_root (32) |
+    ///  tx_count (4) | state_root (32) | timestamp (16) | proposer_len (4) |
+    ///  proposer`
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(132 + self.proposer.len());
+        out.extend_from_slice(&self.version.to_be_bytes());
+        out.extend_from_slice(&self.height.to_be_bytes());
+        out.extend_from_slice(&self.previous_hash);
+        out.extend_from_slice(&self.merkle_root);
+        out.extend_from_slice(&self.tx_count.to_be_bytes());
+        out.extend_from_slice(&self.state_root);
+        out.extend_from_slice(&self.timestamp.to_be_bytes());
+        encode_bytes(&mut out, self.proposer.as_bytes());
+        out
+    }
+
+    pub fn hash(&self) -> Hash {
+        sha3_256(&self.encode())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
-    pub index: u64,
-    pub timestamp: u128,
-    pub previous_hash: String,
-    pub hash: String,
+    pub header: BlockHeader,
+    #[serde(with = "hex_hash")]
+    pub hash: Hash,
     pub transactions: Vec<Transaction>,
+    /// Proposer's detached SPHINCS+ signature over `hash`. Empty for genesis.
+    pub signature: Vec<u8>,
+    /// Precommits finalizing the block, attached once it is committed. Like
+    /// the signature, it is not covered by `hash`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<QuorumCertificate>,
 }
 
 impl Block {
-    pub fn new(index: u64, previous_hash: String, transactions: Vec<Transaction>) -> Self {
+    /// Builds a block on top of `previous_hash` and signs it with `proposer`.
+    /// `state_root` is the root of the state tree after the block is applied.
+    pub fn new(
+        height: u64,
+        previous_hash: Hash,
+        state_root: Hash,
+        transactions: Vec<Transaction>,
+        proposer: &NodeKey,
+    ) -> Self {
         let timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .expect("Time went backwards")
             .as_millis();
 
-        let block_data = format!("{:?}{:?}{:?}", index, &transactions, timestamp);
-        let hash = Sha3_256::digest(block_data.as_bytes());
+        let header = BlockHeader {
+            version: BLOCK_VERSION,
+            height,
+            previous_hash,
+            merkle_root: transactions_root(&transactions),
+            tx_count: transactions.len() as u32,
+            state_root,
+            timestamp,
+            proposer: proposer.id(),
+        };
 
+        let mut block = Block::from_header(header, transactions);
+        block.signature = proposer.sign(&block.hash).expect("Failed to sign block");
+        block
+    }
+
+    /// Builds an unsigned block around an already populated header, computing
+    /// its hash.
+    pub fn from_header(header: BlockHeader, transactions: Vec<Transaction>) -> Self {
         Block {
-            index,
-            timestamp,
-            previous_hash,
-            hash: format!("{:x}", hash),
+            hash: header.hash(),
+            header,
             transactions,
+            signature: vec![],
+            commit: None,
         }
     }
 
-    pub fn genesis() -> Self {
-        Block {
-            index: 0,
-            timestamp: 0,
-            previous_hash: "0".to_string(),
-            hash: "genesis".to_string(),
-            transactions: vec![],
+    /// Checks the proposer's signature over the block hash. The proposer id in
+    /// the header is the hex-encoded SPHINCS+ public key.
+    pub fn verify_signature(&self) -> bool {
+        match hex::decode(&self.header.proposer) {
+            Ok(public_key) => {
+                Sphincs::verify_with_key_bytes(&self.hash, &self.signature, &public_key)
+            }
+            Err(_) => false,
         }
     }
 }
+
+/// Merkle root over the leaf hashes of `transactions`, in block order.
+pub fn transactions_root(transactions: &[Transaction]) -> Hash {
+    let leaves: Vec<Hash> = transactions.iter().map(Transaction::hash).collect();
+    merkle_root(&leaves)
+}
+
+/// Appends `bytes` prefixed with their length as a big-endian `u32`.
+pub(crate) fn encode_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
@@ -1,96 +1,754 @@
-use crate::blockchain::block::Block;
-use crate::blockchain::state::StakingState;
-use crate::consensus::pouw::{Task, TaskQueue};
-use rocksdb::{IteratorMode, DB};
-use serde_json;
+use crate::blockchain::block::{Block, Transaction, TransactionError, TransactionKind};
+use crate::blockchain::genesis::{ConsensusParams, GenesisConfig};
+use crate::blockchain::merkle::{merkle_proof, MerkleProof};
+use crate::blockchain::sparse_merkle::{SparseMerkleProof, SparseMerkleTree};
+use crate::blockchain::state::{
+    state_tree, state_value, AccountState, RewardSummary, StakingState, StateKey, StateSnapshot,
+    UnbondingEntry,
+};
+use crate::blockchain::validation::{
+    check_block_contents, check_block_link, now_ms, BlockValidationError,
+};
+use crate::consensus::bft::{BftError, QuorumCertificate};
+use crate::consensus::engine::{Consensus, ProofOfStake};
+use crate::consensus::fork_choice::ForkChoice;
+use crate::consensus::pouw::{Task, TaskQueue, TaskType, WorkerInfo};
+use crate::consensus::slashing;
+use crate::cryptography::sha3::Hash;
+use crate::storage::database::{BlockchainDB, StateRecord};
+use crate::wallet::key_manager::NodeKey;
+use std::collections::HashMap;
+
+/// Deepest reorganization the node will perform. State snapshots are kept
+/// only for this many recent blocks.
+pub const MAX_REORG_DEPTH: u64 = 64;
+
+/// Upper bound on blocks held while waiting for their parent.
+pub const MAX_ORPHANS: usize = 256;
+
+/// Outcome of handing a block to `Blockchain::add_block`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockStatus {
+    /// The block extended the canonical tip.
+    Extended,
+    /// The block was stored on a side branch that does not outweigh the tip.
+    SideChain,
+    /// The block's branch outweighed the tip and became canonical.
+    Reorganized {
+        depth: u64,
+    },
+    /// The block's parent is unknown; it is held until the parent arrives.
+    Orphan,
+    AlreadyKnown,
+}
+
+/// Canonical blocks taken off the chain during a reorganization, with what is
+/// needed to put them back.
+struct Detached {
+    blocks: Vec<Block>,
+    undo: Vec<(Hash, StateSnapshot)>,
+    state: StateSnapshot, // State at the old tip
+}
 
 #[derive(Debug)]
 pub struct Blockchain {
     pub chain: Vec<Block>,
-    pub db: DB,
+    pub db: BlockchainDB,
+    pub genesis: GenesisConfig,
+    pub accounts: AccountState,
     pub staking_state: StakingState,
     pub task_queue: TaskQueue,
+    pub side_blocks: HashMap<Hash, Block>, // Valid blocks off the canonical chain
+    pub orphans: HashMap<Hash, Block>,     // Blocks whose parent is unknown
+    pub fork_choice: Box<dyn ForkChoice>,
+    pub consensus: Box<dyn Consensus>,
+    pub finalized_height: u64, // Highest block with a quorum certificate
+    pub pending_transactions: Vec<Transaction>, // Signed, waiting for a block
+    undo: HashMap<Hash, StateSnapshot>, // State before each recent canonical block
 }
 
 impl Blockchain {
-    pub fn new(path: &str) -> Self {
-        let db = DB::open_default(path).expect("Failed to open RocksDB");
+    /// Opens the chain at `path` under the proof-of-stake engine.
+    pub fn new(path: &str, genesis: GenesisConfig) -> Result<Self, BlockValidationError> {
+        Self::with_consensus(path, genesis, Box::new(ProofOfStake))
+    }
+
+    pub fn with_consensus(
+        path: &str,
+        genesis: GenesisConfig,
+        consensus: Box<dyn Consensus>,
+    ) -> Result<Self, BlockValidationError> {
+        let db = BlockchainDB::new(path)?;
         let mut blockchain = Blockchain {
             chain: vec![],
             db,
+            accounts: AccountState::new(),
             staking_state: StakingState::new(),
             task_queue: TaskQueue::new(),
+            side_blocks: HashMap::new(),
+            orphans: HashMap::new(),
+            fork_choice: genesis.consensus.fork_choice.build(),
+            consensus,
+            finalized_height: 0,
+            pending_transactions: Vec::new(),
+            undo: HashMap::new(),
+            genesis,
         };
-        blockchain.load_chain();
-        blockchain
+        blockchain.init_genesis_state();
+        blockchain.load_chain()?;
+        Ok(blockchain)
+    }
+
+    /// Seeds balances and the validator set from the genesis configuration.
+    fn init_genesis_state(&mut self) {
+        let (accounts, staking_state) = self.genesis.initial_state();
+        self.accounts = accounts;
+        self.staking_state = staking_state;
     }
 
-    pub fn load_chain(&mut self) {
-        let iter = self.db.iterator(IteratorMode::Start);
-        for item in iter {
-            if let Ok((_, value)) = item {
-                let block: Block = serde_json::from_slice(&value).unwrap();
-                self.chain.push(block);
+    /// Loads and re-validates every stored block, starting from genesis.
+    /// Proposer signatures are checked, but validator membership and slot
+    /// eligibility are only enforced when blocks are first appended. The
+    /// state is restored from the record written with the current tip and
+    /// must match the tip's `state_root`; blocks are replayed, each checked
+    /// against its `state_root`, only if no such record exists. The finalized
+    /// height is that of the last stored block carrying a quorum certificate,
+    /// or the tip if the engine finalizes blocks as they are connected. A
+    /// fresh database is initialised with the genesis block and genesis
+    /// state.
+    pub fn load_chain(&mut self) -> Result<(), BlockValidationError> {
+        self.chain.clear();
+        self.finalized_height = 0;
+        let genesis = self.genesis.genesis_block();
+        let now = now_ms();
+        let stored = self.db.load_state()?;
+
+        for block in self.db.load_blocks()? {
+            match self.chain.last() {
+                None => {
+                    if block.hash != genesis.hash || block.header != genesis.header {
+                        return Err(BlockValidationError::GenesisMismatch);
+                    }
+                }
+                Some(parent) => {
+                    check_block_link(parent, &block, now)?;
+                    check_block_contents(&block, &self.genesis_hash())?;
+                    if stored.is_none() {
+                        self.apply_block(&block)?;
+                        if self.state_root() != block.header.state_root {
+                            return Err(BlockValidationError::StateRootMismatch {
+                                height: block.header.height,
+                            });
+                        }
+                    }
+                }
+            }
+            if block.commit.is_some() {
+                self.finalized_height = block.header.height;
             }
+            self.chain.push(block);
         }
+
         if self.chain.is_empty() {
-            self.chain.push(Block::genesis());
+            self.chain.push(genesis);
+            self.db
+                .commit(&self.chain, 0, 0, Some(&self.state_record(None)))?;
+            return Ok(());
+        }
+
+        match stored {
+            Some(stored) if stored.tip == self.latest_block().hash => {
+                self.accounts = stored.accounts;
+                self.staking_state = stored.staking_state;
+                self.task_queue = stored.task_queue;
+                if self.state_root() != self.latest_block().header.state_root {
+                    return Err(BlockValidationError::StateMismatch);
+                }
+            }
+            Some(_) => return Err(BlockValidationError::StateMismatch),
+            // Databases written before state was persisted keep the replayed state.
+            None => self.db.commit(&[], 0, 0, Some(&self.state_record(None)))?,
+        }
+        if self.consensus.finalizes_on_connect() {
+            self.finalized_height = self.latest_block().header.height;
         }
+        Ok(())
     }
 
-    pub fn add_block(&mut self, block: Block) {
-        self.db
-            .put(
-                block.index.to_string(),
-                serde_json::to_string(&block).unwrap(),
-            )
-            .expect("Failed to save block to RocksDB");
+    /// Accepts a block from any source. Blocks extending the tip are applied
+    /// directly; blocks on another branch are kept in `side_blocks` and trigger
+    /// a reorganization when their branch outweighs the canonical one under
+    /// `fork_choice`; blocks with an unknown parent are held as orphans.
+    pub fn add_block(&mut self, block: Block) -> Result<BlockStatus, BlockValidationError> {
+        if self.is_known(&block.hash) {
+            return Ok(BlockStatus::AlreadyKnown);
+        }
+        check_block_contents(&block, &self.genesis_hash())?;
+
+        let hash = block.hash;
+        let status = if block.header.previous_hash == self.latest_block().hash {
+            self.check_extends_tip(&block)?;
+            self.connect_block(block)?;
+            let tip = self.latest_block();
+            let stored = self.undo.get(&tip.hash);
+            let committed = self.db.commit(
+                std::slice::from_ref(tip),
+                0,
+                0,
+                Some(&self.state_record(stored)),
+            );
+            if let Err(e) = committed {
+                self.disconnect_tip();
+                return Err(e.into());
+            }
+            if self.consensus.finalizes_on_connect() {
+                self.finalized_height = self.latest_block().header.height;
+            }
+            self.prune_forks();
+            BlockStatus::Extended
+        } else if let Some(parent) = self.find_block(&block.header.previous_hash) {
+            if block.header.height <= self.finalized_height {
+                return Err(BlockValidationError::ConflictsWithFinalized {
+                    height: block.header.height,
+                });
+            }
+            check_block_link(parent, &block, now_ms())?;
+            self.side_blocks.insert(hash, block);
+            match self.try_reorganize(hash)? {
+                Some(depth) => BlockStatus::Reorganized { depth },
+                None => BlockStatus::SideChain,
+            }
+        } else {
+            if self.orphans.len() >= MAX_ORPHANS {
+                if let Some(evicted) = self.orphans.keys().next().cloned() {
+                    self.orphans.remove(&evicted);
+                }
+            }
+            self.orphans.insert(hash, block);
+            return Ok(BlockStatus::Orphan);
+        };
+
+        self.connect_orphans(hash);
+        self.prune_pending();
+        Ok(status)
+    }
+
+    /// Checks that `block` would be accepted on top of the tip, by applying
+    /// it to a copy of the state as `connect_block` would.
+    pub fn validate_block(&self, block: &Block) -> Result<(), BlockValidationError> {
+        check_block_contents(block, &self.genesis_hash())?;
+        self.check_extends_tip(block)?;
+
+        let mut accounts = self.accounts.clone();
+        let mut staking_state = self.staking_state.clone();
+        let mut task_queue = self.task_queue.clone();
+        apply_block_state(
+            &mut accounts,
+            &mut staking_state,
+            &mut task_queue,
+            block.header.height,
+            &block.header.previous_hash,
+            &block.header.proposer,
+            &block.transactions,
+            &self.genesis.consensus,
+            self.consensus.as_ref(),
+        )?;
+        if state_tree(&accounts, &staking_state, &task_queue).root() != block.header.state_root {
+            return Err(BlockValidationError::StateRootMismatch {
+                height: block.header.height,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that `block` links to the tip and that its proposer may build
+    /// on it. Its transactions are checked when it is applied.
+    fn check_extends_tip(&self, block: &Block) -> Result<(), BlockValidationError> {
+        check_block_link(self.latest_block(), block, now_ms())?;
+        self.check_proposer(block)
+    }
+
+    /// Checks the rules that depend on current state rather than on the block
+    /// alone: the consensus engine must accept the proposer for the block's
+    /// slot.
+    fn check_proposer(&self, block: &Block) -> Result<(), BlockValidationError> {
+        let header = &block.header;
+        let parent = self.find_block(&header.previous_hash).ok_or(
+            BlockValidationError::PreviousHashMismatch {
+                height: header.height,
+            },
+        )?;
+        self.consensus
+            .check_proposer(&self.staking_state, &self.genesis.consensus, parent, header)
+    }
+
+    /// Consensus key expected to propose the next block.
+    pub fn next_proposer(&self) -> Option<&str> {
+        let tip = self.latest_block();
+        self.consensus
+            .select_proposer(&self.staking_state, &tip.hash, tip.header.height + 1)
+    }
+
+    /// Applies `block` on top of the tip, recording the prior state so it can
+    /// be rolled back. State is left untouched if the block does not apply or
+    /// the resulting state does not match the block's `state_root`.
+    fn connect_block(&mut self, block: Block) -> Result<(), BlockValidationError> {
+        let snapshot = self.snapshot();
+        if let Err(e) = self.apply_block(&block) {
+            self.restore(snapshot);
+            return Err(e);
+        }
+        if self.state_root() != block.header.state_root {
+            self.restore(snapshot);
+            return Err(BlockValidationError::StateRootMismatch {
+                height: block.header.height,
+            });
+        }
+        self.undo.insert(block.hash, snapshot);
         self.chain.push(block);
+        Ok(())
     }
 
-    pub fn validate_block(&self, block: &Block) -> bool {
-        block.hash.starts_with("0")
+    /// Applies the state changes carried by `block`. See `apply_block_state`.
+    fn apply_block(&mut self, block: &Block) -> Result<(), BlockValidationError> {
+        apply_block_state(
+            &mut self.accounts,
+            &mut self.staking_state,
+            &mut self.task_queue,
+            block.header.height,
+            &block.header.previous_hash,
+            &block.header.proposer,
+            &block.transactions,
+            &self.genesis.consensus,
+            self.consensus.as_ref(),
+        )
     }
 
-    pub fn latest_block(&self) -> &Block {
-        self.chain.last().expect("Blockchain is empty")
+    /// Removes the tip and restores the state from before it was applied.
+    fn disconnect_tip(&mut self) {
+        if let Some(block) = self.chain.pop() {
+            if let Some(snapshot) = self.undo.remove(&block.hash) {
+                self.restore(snapshot);
+            }
+        }
+    }
+
+    fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            accounts: self.accounts.clone(),
+            staking_state: self.staking_state.clone(),
+            task_queue: self.task_queue.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: StateSnapshot) {
+        self.accounts = snapshot.accounts;
+        self.staking_state = snapshot.staking_state;
+        self.task_queue = snapshot.task_queue;
+    }
+
+    /// Switches to the branch ending at `tip` if it outweighs the canonical
+    /// chain above their common ancestor. Returns the number of canonical
+    /// blocks that were replaced, or `None` if the current chain is kept.
+    fn try_reorganize(&mut self, tip: Hash) -> Result<Option<u64>, BlockValidationError> {
+        let mut branch = Vec::new();
+        let mut cursor = tip;
+        while let Some(block) = self.side_blocks.get(&cursor) {
+            cursor = block.header.previous_hash;
+            branch.push(block.clone());
+        }
+        branch.reverse();
+
+        let fork_height = branch[0].header.height - 1;
+        if self.get_block(fork_height).map(|b| b.hash) != Some(cursor) {
+            return Ok(None);
+        }
+        // Finalized blocks are never replaced.
+        if fork_height < self.finalized_height {
+            return Ok(None);
+        }
+        let depth = self.latest_block().header.height - fork_height;
+        if depth > MAX_REORG_DEPTH {
+            return Ok(None);
+        }
+
+        let first_replaced = (fork_height + 1) as usize;
+        let current = &self.chain[first_replaced..];
+        let current_weight = self.fork_choice.branch_weight(current, &self.staking_state);
+        let branch_weight = self.fork_choice.branch_weight(&branch, &self.staking_state);
+        if branch_weight <= current_weight {
+            return Ok(None);
+        }
+
+        let snapshot = match current.first() {
+            Some(block) => match self.undo.get(&block.hash) {
+                Some(snapshot) => snapshot.clone(),
+                None => return Ok(None),
+            },
+            None => self.snapshot(),
+        };
+
+        // Roll back to the fork point, then replay the new branch. The current
+        // state and the detached blocks' snapshots are kept so that a failed
+        // switch can be reverted without replaying the original chain.
+        let original = Detached {
+            state: self.snapshot(),
+            undo: self.chain[first_replaced..]
+                .iter()
+                .filter_map(|b| self.undo.remove_entry(&b.hash))
+                .collect(),
+            blocks: self.chain.split_off(first_replaced),
+        };
+        self.restore(snapshot);
+
+        for (applied, block) in branch.iter().enumerate() {
+            let connected = self
+                .check_proposer(block)
+                .and_then(|_| self.connect_block(block.clone()));
+            if let Err(e) = connected {
+                self.abort_reorganize(first_replaced, &branch[..applied], original);
+                self.discard_branch(block.hash);
+                return Err(e);
+            }
+        }
+
+        // Write the new branch, drop heights it does not reach and store the
+        // resulting state in one batch.
+        let new_end = self.chain.len() as u64;
+        let old_end = (first_replaced + original.blocks.len()) as u64;
+        let committed = self.db.commit(
+            &self.chain[first_replaced..],
+            new_end,
+            old_end,
+            Some(&self.state_record(Some(&original.state))),
+        );
+        if let Err(e) = committed {
+            self.abort_reorganize(first_replaced, &branch, original);
+            return Err(e.into());
+        }
+
+        for block in &branch {
+            self.side_blocks.remove(&block.hash);
+        }
+        for block in original.blocks {
+            self.side_blocks.insert(block.hash, block);
+        }
+        self.prune_forks();
+        Ok(Some(depth))
     }
 
-    pub fn stake(&mut self, wallet: String, amount: u64) {
-        self.staking_state.stake(wallet, amount);
+    /// Undoes a partial switch to another branch and puts the `original`
+    /// blocks from `first_replaced` onwards, and their state, back in place.
+    fn abort_reorganize(&mut self, first_replaced: usize, applied: &[Block], original: Detached) {
+        for block in applied {
+            self.undo.remove(&block.hash);
+        }
+        self.chain.truncate(first_replaced);
+        self.chain.extend(original.blocks);
+        self.undo.extend(original.undo);
+        self.restore(original.state);
     }
 
-    pub fn unstake(&mut self, wallet: String, amount: u64) -> bool {
-        self.staking_state.unstake(wallet, amount)
+    /// Drops `hash` and every side block descending from it.
+    fn discard_branch(&mut self, hash: Hash) {
+        let mut pending = vec![hash];
+        while let Some(parent) = pending.pop() {
+            self.side_blocks.remove(&parent);
+            pending.extend(
+                self.side_blocks
+                    .values()
+                    .filter(|b| b.header.previous_hash == parent)
+                    .map(|b| b.hash),
+            );
+        }
+    }
+
+    /// Retries orphans whose parent is `parent`, now that it is known.
+    fn connect_orphans(&mut self, parent: Hash) {
+        let children: Vec<Hash> = self
+            .orphans
+            .values()
+            .filter(|b| b.header.previous_hash == parent)
+            .map(|b| b.hash)
+            .collect();
+        for hash in children {
+            if let Some(orphan) = self.orphans.remove(&hash) {
+                let _ = self.add_block(orphan);
+            }
+        }
     }
 
-    pub fn get_rewards(&self, wallet: &String) -> u64 {
-        self.staking_state.get_rewards(wallet)
+    /// Forgets side blocks and undo snapshots too deep to matter for a reorg.
+    fn prune_forks(&mut self) {
+        let tip = self.latest_block().header.height;
+        let floor = tip.saturating_sub(MAX_REORG_DEPTH);
+        let side_floor = floor.max(self.finalized_height);
+        self.side_blocks.retain(|_, b| b.header.height > side_floor);
+        let keep: Vec<Hash> = self.chain[floor as usize..]
+            .iter()
+            .map(|b| b.hash)
+            .collect();
+        self.undo.retain(|hash, _| keep.contains(hash));
     }
 
-    pub fn add_task(
+    fn is_known(&self, hash: &Hash) -> bool {
+        self.side_blocks.contains_key(hash)
+            || self.orphans.contains_key(hash)
+            || self.chain.iter().rev().any(|b| &b.hash == hash)
+    }
+
+    /// Looks a block up on the canonical chain or among the side blocks.
+    pub fn find_block(&self, hash: &Hash) -> Option<&Block> {
+        self.side_blocks
+            .get(hash)
+            .or_else(|| self.chain.iter().rev().find(|b| &b.hash == hash))
+    }
+
+    /// Finalizes the canonical block certified by `qc` and every block below
+    /// it, storing the certificate with the block. The certificate is checked
+    /// against the active set the block was proposed to, which is known while
+    /// the block is within `MAX_REORG_DEPTH` of the tip; deeper blocks are
+    /// checked against the current set.
+    pub fn finalize(&mut self, qc: QuorumCertificate) -> Result<(), BftError> {
+        let height = qc.height;
+        if height <= self.finalized_height {
+            return Err(BftError::AlreadyFinalized { height });
+        }
+        let block = self
+            .get_block(height)
+            .ok_or(BftError::UnknownBlock { height })?;
+        if block.hash != qc.block_hash {
+            return Err(BftError::NotCanonical { height });
+        }
+        self.consensus
+            .verify_commit(self.staking_state_before(height), &qc)?;
+
+        let mut block = block.clone();
+        block.commit = Some(qc);
+        self.db
+            .commit(std::slice::from_ref(&block), 0, 0, None)
+            .map_err(|e| BftError::Storage(e.to_string()))?;
+        self.chain[height as usize] = block;
+        self.finalized_height = height;
+        self.prune_forks();
+        Ok(())
+    }
+
+    /// The staking state the canonical block at `height` was proposed to.
+    /// It is known while the block is within `MAX_REORG_DEPTH` of the tip;
+    /// for deeper blocks the current state stands in.
+    pub fn staking_state_before(&self, height: u64) -> &StakingState {
+        self.get_block(height)
+            .and_then(|block| self.undo.get(&block.hash))
+            .map_or(&self.staking_state, |snapshot| &snapshot.staking_state)
+    }
+
+    pub fn set_fork_choice(&mut self, fork_choice: Box<dyn ForkChoice>) {
+        self.fork_choice = fork_choice;
+    }
+
+    /// The staking state and tasks as they stand at the current tip, to be
+    /// written over `stored`.
+    fn state_record<'a>(&'a self, stored: Option<&'a StateSnapshot>) -> StateRecord<'a> {
+        StateRecord {
+            tip: self.latest_block().hash,
+            accounts: &self.accounts,
+            staking_state: &self.staking_state,
+            task_queue: &self.task_queue,
+            stored,
+        }
+    }
+
+    /// Builds a signed block on top of the tip carrying `transactions`, with
+    /// the state root the block will produce.
+    pub fn propose_block(
+        &self,
+        transactions: Vec<Transaction>,
+        proposer: &NodeKey,
+    ) -> Result<Block, BlockValidationError> {
+        let tip = self.latest_block();
+        let state_root = self.apply_to_tip(&transactions, &proposer.id())?;
+        Ok(Block::new(
+            tip.header.height + 1,
+            tip.hash,
+            state_root,
+            transactions,
+            proposer,
+        ))
+    }
+
+    /// The state root of a block on top of the tip carrying `transactions`
+    /// and signed with `proposer`'s consensus key, worked out on a copy of
+    /// the state.
+    fn apply_to_tip(
+        &self,
+        transactions: &[Transaction],
+        proposer: &str,
+    ) -> Result<Hash, BlockValidationError> {
+        let tip = self.latest_block();
+        let mut accounts = self.accounts.clone();
+        let mut staking_state = self.staking_state.clone();
+        let mut task_queue = self.task_queue.clone();
+        apply_block_state(
+            &mut accounts,
+            &mut staking_state,
+            &mut task_queue,
+            tip.header.height + 1,
+            &tip.hash,
+            proposer,
+            transactions,
+            &self.genesis.consensus,
+            self.consensus.as_ref(),
+        )?;
+        Ok(state_tree(&accounts, &staking_state, &task_queue).root())
+    }
+
+    /// Of `transactions`, in order, those that apply together on top of the
+    /// tip in a block from `proposer`, with the state root they lead to, and
+    /// those left out with the reason each failed.
+    #[allow(clippy::type_complexity)]
+    fn applicable_transactions(
+        &self,
+        mut transactions: Vec<Transaction>,
+        proposer: &str,
+    ) -> Result<(Vec<Transaction>, Hash, Vec<(Transaction, TransactionError)>), BlockValidationError>
+    {
+        let mut failed = Vec::new();
+        loop {
+            match self.apply_to_tip(&transactions, proposer) {
+                Ok(state_root) => return Ok((transactions, state_root, failed)),
+                Err(BlockValidationError::InvalidTransaction { index, error }) => {
+                    failed.push((transactions.remove(index), error));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The nonce the next transaction from `sender` must carry: its account
+    /// nonce, counting its transactions still waiting for a block.
+    pub fn next_nonce(&self, sender: &str) -> u64 {
+        let pending = self
+            .pending_transactions
+            .iter()
+            .filter(|tx| tx.sender == sender)
+            .count();
+        self.accounts.nonce(sender) + pending as u64
+    }
+
+    /// Adds a signed transaction to the pool the proposer draws its next
+    /// block from. Its signature must verify and it must apply on top of the
+    /// tip after the transactions already waiting.
+    pub fn add_pending_transaction(
         &mut self,
-        id: String,
-        description: String,
-        encrypted_data: Vec<u8>,
-        reward: u64,
-        sphincs_public_key: Vec<u8>,
-        signature: Vec<u8>,
-    ) {
-        let task = Task {
-            id: id.clone(),
-            description,
-            data: encrypted_data,
-            reward,
-            is_complete: false,
-            sphincs_public_key,
-            signature,
-        };
-        self.task_queue.tasks.insert(id, task);
+        transaction: Transaction,
+    ) -> Result<(), TransactionError> {
+        transaction.verify(&self.genesis_hash())?;
+        let hash = transaction.hash();
+        let mut transactions = self.pending_transactions.clone();
+        transactions.push(transaction.clone());
+        // The block-level checks do not depend on the new transaction, so
+        // only its own failure turns it away.
+        if let Ok((_, _, failed)) = self.applicable_transactions(transactions, "") {
+            if let Some((_, error)) = failed.into_iter().find(|(tx, _)| tx.hash() == hash) {
+                return Err(error);
+            }
+        }
+        self.pending_transactions.push(transaction);
+        Ok(())
+    }
+
+    /// Whether a claim, result, commitment or reveal from `sender` for
+    /// `task_id` is waiting for a block.
+    pub fn has_pending_task_transaction(&self, sender: &str, task_id: &str) -> bool {
+        self.pending_transactions
+            .iter()
+            .any(|tx| tx.sender == sender && tx.kind.worker_task_id() == Some(task_id))
+    }
+
+    /// Builds a block on top of the tip from the pending transactions that
+    /// still apply, dropping the others from the pool. Returns `None` if
+    /// `proposer` is not due to propose on the tip yet.
+    pub fn propose_pending(
+        &mut self,
+        proposer: &NodeKey,
+    ) -> Result<Option<Block>, BlockValidationError> {
+        let (transactions, state_root, failed) =
+            self.applicable_transactions(self.pending_transactions.clone(), &proposer.id())?;
+        let failed: Vec<Hash> = failed.iter().map(|(tx, _)| tx.hash()).collect();
+        self.pending_transactions
+            .retain(|tx| !failed.contains(&tx.hash()));
+
+        let tip = self.latest_block();
+        let block = Block::new(
+            tip.header.height + 1,
+            tip.hash,
+            state_root,
+            transactions,
+            proposer,
+        );
+        Ok(self.check_proposer(&block).is_ok().then_some(block))
+    }
+
+    /// Drops pending transactions whose nonce a block has used up.
+    fn prune_pending(&mut self) {
+        let accounts = &self.accounts;
+        self.pending_transactions
+            .retain(|tx| tx.nonce >= accounts.nonce(&tx.sender));
     }
 
-    pub fn complete_task(&mut self, id: &str) -> Option<Task> {
-        self.task_queue.complete_task(id)
+    /// The authenticated tree over the current accounts, stakes and tasks.
+    pub fn state_tree(&self) -> SparseMerkleTree {
+        state_tree(&self.accounts, &self.staking_state, &self.task_queue)
+    }
+
+    pub fn state_root(&self) -> Hash {
+        self.state_tree().root()
+    }
+
+    /// Returns the current state root, the value stored under `key` (if any)
+    /// and the proof of its inclusion or exclusion.
+    pub fn state_proof(&self, key: StateKey) -> (Hash, Option<Vec<u8>>, SparseMerkleProof) {
+        let tree = self.state_tree();
+        let value = state_value(key, &self.accounts, &self.staking_state, &self.task_queue);
+        (tree.root(), value, tree.prove(&key.encode()))
+    }
+
+    /// Hash of block 0, which identifies the network and is covered by every
+    /// transaction signature.
+    pub fn genesis_hash(&self) -> Hash {
+        self.chain[0].hash
+    }
+
+    pub fn latest_block(&self) -> &Block {
+        self.chain.last().expect("Blockchain is empty")
+    }
+
+    pub fn get_block(&self, height: u64) -> Option<&Block> {
+        self.chain.get(height as usize)
+    }
+
+    /// Returns the block at `height`, the transaction at `index` within it and
+    /// the Merkle proof tying that transaction to the block's `merkle_root`.
+    pub fn transaction_proof(
+        &self,
+        height: u64,
+        index: usize,
+    ) -> Option<(&Block, &Transaction, MerkleProof)> {
+        let block = self.get_block(height)?;
+        let tx = block.transactions.get(index)?;
+        let leaves: Vec<Hash> = block.transactions.iter().map(Transaction::hash).collect();
+        let proof = merkle_proof(&leaves, index)?;
+        Some((block, tx, proof))
+    }
+
+    pub fn get_unbonding(&self, wallet: &str) -> &[UnbondingEntry] {
+        self.staking_state.get_unbonding(wallet)
+    }
+
+    pub fn get_rewards(&self, wallet: &str) -> RewardSummary {
+        self.staking_state
+            .get_rewards(wallet, &self.genesis.consensus)
     }
 
     pub fn get_pending_tasks(&self) -> Vec<&Task> {
@@ -100,4 +758,162 @@ impl Blockchain {
     pub fn get_task(&self, id: &str) -> Option<&Task> {
         self.task_queue.get_task(id)
     }
+
+    pub fn get_task_type(&self, name: &str) -> Option<&TaskType> {
+        self.task_queue.get_task_type(name)
+    }
+
+    pub fn get_worker(&self, address: &str) -> Option<&WorkerInfo> {
+        self.task_queue.get_worker(address)
+    }
+}
+
+/// State transition for a block at `height` on top of `parent_hash`, signed
+/// with `proposer`'s consensus key. First PoUW tasks are moved along: results
+/// handed in during earlier blocks are settled, with the workers behind
+/// accepted ones paid the task's reward and the proposer's validator its
+/// verifier fee out of escrow, abandoned claims are released and overdue
+/// tasks expire, refunding their escrow. Workers lose `worker_slash_bps` of
+/// their collateral to the slashing destination for every rejected result
+/// and expired slot. Then the block's transactions move
+/// funds between balances, stakes and task escrows and bump sender nonces,
+/// with unstaked and undelegated funds released `unbonding_blocks` after
+/// `height`, double-sign evidence slashes and jails the offender, task
+/// claims, results, commitments, reveals and cancellations are recorded,
+/// workers join or leave the registry with their collateral, and validators
+/// register and change their commission.
+/// Validators `consensus` says were skipped miss a slot and those that have
+/// missed too many in a row are slashed and jailed, finished jail terms end,
+/// `consensus` pays its block rewards, if `height` closes an epoch the active
+/// set is recalculated, and unbonding funds that mature at `height` are
+/// returned to their owners. Overdrafts, nonce mismatches, stake shortfalls,
+/// unusable evidence and task transactions the task does not accept reject
+/// the whole block. On error the state may be partially updated, so callers
+/// work on a copy or restore a snapshot.
+#[allow(clippy::too_many_arguments)]
+fn apply_block_state(
+    accounts: &mut AccountState,
+    staking_state: &mut StakingState,
+    task_queue: &mut TaskQueue,
+    height: u64,
+    parent_hash: &Hash,
+    proposer: &str,
+    transactions: &[Transaction],
+    params: &ConsensusParams,
+    consensus: &dyn Consensus,
+) -> Result<(), BlockValidationError> {
+    // The slot's ranking is drawn from the state the block builds on.
+    let skipped = consensus.skipped_validators(staking_state, parent_hash, height, proposer);
+
+    // Results submitted in earlier blocks are settled before this block's
+    // transactions can claim or submit anew.
+    // Verifier fees go to the validator that proposed the block.
+    let verifier = staking_state
+        .operator_of(proposer)
+        .unwrap_or(proposer)
+        .to_string();
+    let (payouts, slashed) =
+        task_queue.process_block(height, &verifier, params.slashing.worker_slash_bps);
+    for (wallet, amount) in payouts {
+        accounts
+            .credit(&wallet, amount)
+            .map_err(|_| BlockValidationError::BalanceOverflow { height })?;
+    }
+    slashing::route_slashed(accounts, &params.slashing, slashed);
+
+    accounts
+        .apply_transactions(transactions)
+        .map_err(|(index, error)| BlockValidationError::InvalidTransaction { index, error })?;
+
+    let release_height = height.saturating_add(params.unbonding_blocks);
+    for (index, tx) in transactions.iter().enumerate() {
+        let result = match &tx.kind {
+            TransactionKind::DoubleSign(evidence) => {
+                slashing::apply_double_sign(accounts, staking_state, params, evidence, height)
+                    .map(|_| ())
+                    .map_err(TransactionError::InvalidEvidence)
+            }
+            TransactionKind::TaskClaim(claim) => task_queue
+                .claim(&tx.sender, claim, height)
+                .map_err(TransactionError::InvalidTask),
+            TransactionKind::TaskResult(submission) => task_queue
+                .submit_result(&tx.sender, submission, height)
+                .map_err(TransactionError::InvalidTask),
+            TransactionKind::TaskCommit(commit) => task_queue
+                .commit_result(&tx.sender, commit, height)
+                .map_err(TransactionError::InvalidTask),
+            TransactionKind::TaskReveal(reveal) => task_queue
+                .reveal_result(&tx.sender, reveal, height)
+                .map_err(TransactionError::InvalidTask),
+            TransactionKind::TaskCreate(spec) => task_queue
+                .create_task(&tx.sender, spec, tx.amount, &tx.signature, height)
+                .map_err(TransactionError::InvalidTask),
+            TransactionKind::TaskCancel(cancel) => task_queue
+                .cancel_task(&tx.sender, cancel)
+                .map_err(TransactionError::InvalidTask)
+                .and_then(|refund| accounts.credit(&tx.sender, refund)),
+            TransactionKind::TaskTypeRegister(spec) => task_queue
+                .register_task_type(&tx.sender, spec)
+                .map_err(TransactionError::InvalidTask),
+            TransactionKind::WorkerRegister(registration) => task_queue
+                .register_worker(&tx.sender, registration, tx.amount, height)
+                .map_err(TransactionError::InvalidTask),
+            TransactionKind::WorkerUnregister => task_queue
+                .unregister_worker(&tx.sender)
+                .map_err(TransactionError::InvalidTask)
+                .and_then(|refund| accounts.credit(&tx.sender, refund)),
+            TransactionKind::SetCommission { commission_bps } => staking_state.set_commission(
+                &tx.sender,
+                *commission_bps,
+                height / params.epoch_length,
+                params,
+            ),
+            TransactionKind::ValidatorRegister(registration) => {
+                let registered = staking_state.register_validator(
+                    &tx.sender,
+                    &registration.consensus_key,
+                    &registration.name,
+                );
+                if registered {
+                    Ok(())
+                } else {
+                    Err(TransactionError::InvalidValidatorRegistration)
+                }
+            }
+            _ => staking_state.apply_transaction(tx, release_height),
+        };
+        result.map_err(|error| BlockValidationError::InvalidTransaction { index, error })?;
+    }
+    // Offences this old can no longer be reported, so they need not be
+    // remembered.
+    staking_state.punished.retain(|(_, offence_height)| {
+        offence_height.saturating_add(params.unbonding_blocks) >= height
+    });
+
+    let slashing_params = &params.slashing;
+    for validator in
+        staking_state.record_block(proposer, &skipped, slashing_params.max_missed_slots)
+    {
+        slashing::punish(
+            accounts,
+            staking_state,
+            slashing_params,
+            &validator,
+            slashing_params.downtime_slash_bps,
+            height.saturating_add(slashing_params.jail_blocks),
+        );
+    }
+    staking_state.release_jailed(height);
+
+    consensus.reward_block(accounts, staking_state, task_queue, params, height)?;
+    if height % params.epoch_length == 0 {
+        staking_state.update_active_set(params);
+    }
+
+    for (wallet, amount) in staking_state.release_matured(height) {
+        accounts
+            .credit(&wallet, amount)
+            .map_err(|_| BlockValidationError::BalanceOverflow { height })?;
+    }
+    Ok(())
 }
@@ -0,0 +1,265 @@
+use crate::blockchain::block::{encode_bytes, Block, BlockHeader, BLOCK_VERSION};
+use crate::blockchain::state::{state_tree, AccountState, StakingState, MAX_VALIDATOR_NAME_LEN};
+use crate::blockchain::validation::MAX_FUTURE_DRIFT_MS;
+use crate::consensus::bft::BftParams;
+use crate::consensus::fork_choice::ForkChoiceRule;
+use crate::consensus::pouw::TaskQueue;
+use crate::consensus::slashing::SlashingParams;
+use crate::cryptography::sha3::{sha3_256, Hash, ZERO_HASH};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fs;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenesisValidator {
+    pub address: String,
+    pub stake: u64,
+    /// Key the validator signs blocks with. Defaults to `address`.
+    #[serde(default)]
+    pub consensus_key: Option<String>,
+    #[serde(default)]
+    pub name: String,
+}
+
+impl GenesisValidator {
+    pub fn consensus_key(&self) -> &str {
+        self.consensus_key.as_deref().unwrap_or(&self.address)
+    }
+}
+
+/// Chain-wide consensus parameters fixed at genesis.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConsensusParams {
+    /// Target time between blocks, in milliseconds.
+    pub block_time_ms: u64,
+    /// Number of blocks per epoch.
+    pub epoch_length: u64,
+    /// Reward for staking through a whole epoch, in basis points of the stake.
+    pub epoch_reward_bps: u64,
+    /// Blocks that unstaked funds wait before they are returned to the balance.
+    pub unbonding_blocks: u64,
+    /// Own stake a registered validator needs to be eligible for the active
+    /// set.
+    pub min_validator_stake: u64,
+    /// Largest number of validators in the active set.
+    pub max_validators: u64,
+    /// How long each validator ranked for a slot has to propose before the
+    /// next one may, in milliseconds.
+    pub proposer_timeout_ms: u64,
+    /// Most a validator may raise its commission by in one epoch, in basis
+    /// points. Lowering it is not limited.
+    pub max_commission_change_bps: u64,
+    /// How competing branches are resolved.
+    pub fork_choice: ForkChoiceRule,
+    /// Penalties for double-signing and downtime.
+    pub slashing: SlashingParams,
+    /// Round timeouts for finality voting.
+    pub bft: BftParams,
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        Self {
+            block_time_ms: 5_000,
+            epoch_length: 100,
+            epoch_reward_bps: 10,
+            unbonding_blocks: 200,
+            min_validator_stake: 1_000,
+            max_validators: 100,
+            proposer_timeout_ms: 20_000,
+            max_commission_change_bps: 500,
+            fork_choice: ForkChoiceRule::default(),
+            slashing: SlashingParams::default(),
+            bft: BftParams::default(),
+        }
+    }
+}
+
+impl ConsensusParams {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.block_time_ms.to_be_bytes());
+        out.extend_from_slice(&self.epoch_length.to_be_bytes());
+        out.extend_from_slice(&self.epoch_reward_bps.to_be_bytes());
+        out.extend_from_slice(&self.unbonding_blocks.to_be_bytes());
+        out.extend_from_slice(&self.min_validator_stake.to_be_bytes());
+        out.extend_from_slice(&self.max_validators.to_be_bytes());
+        out.extend_from_slice(&self.proposer_timeout_ms.to_be_bytes());
+        out.extend_from_slice(&self.max_commission_change_bps.to_be_bytes());
+        out.push(self.fork_choice.tag());
+        self.slashing.encode(out);
+        self.bft.encode(out);
+    }
+}
+
+/// Contents of `genesis.json`: everything needed to build block 0 and the
+/// initial state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenesisConfig {
+    pub chain_id: String,
+    /// Genesis timestamp in milliseconds since the Unix epoch.
+    pub genesis_time: u128,
+    /// Initial spendable balances by address.
+    #[serde(default)]
+    pub allocations: BTreeMap<String, u64>,
+    /// Initial stakers, who form the starting validator set.
+    #[serde(default)]
+    pub validators: Vec<GenesisValidator>,
+    #[serde(default)]
+    pub consensus: ConsensusParams,
+}
+
+impl GenesisConfig {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let config: GenesisConfig = serde_json::from_str(&fs::read_to_string(path)?)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.chain_id.is_empty() {
+            return Err("chain_id must not be empty".into());
+        }
+        if self.consensus.epoch_length == 0 {
+            return Err("consensus.epoch_length must be greater than zero".into());
+        }
+        if self.consensus.max_validators == 0 {
+            return Err("consensus.max_validators must be greater than zero".into());
+        }
+        // A runner-up's timestamp must be able to clear the timeout before it
+        // is rejected as too far in the future, or a stalled leader stalls
+        // the chain.
+        if self.consensus.proposer_timeout_ms as u128 <= MAX_FUTURE_DRIFT_MS {
+            return Err(format!(
+                "consensus.proposer_timeout_ms must exceed the {} ms allowed clock drift",
+                MAX_FUTURE_DRIFT_MS
+            )
+            .into());
+        }
+        let slashing = &self.consensus.slashing;
+        if slashing.double_sign_slash_bps > 10_000
+            || slashing.downtime_slash_bps > 10_000
+            || slashing.worker_slash_bps > 10_000
+        {
+            return Err("consensus.slashing rates must not exceed 10000 basis points".into());
+        }
+
+        let mut seen = HashSet::new();
+        let mut keys = HashSet::new();
+        for validator in &self.validators {
+            if validator.stake < self.consensus.min_validator_stake {
+                return Err(format!(
+                    "validator {} stakes less than consensus.min_validator_stake",
+                    validator.address
+                )
+                .into());
+            }
+            if validator.consensus_key().is_empty() {
+                return Err(
+                    format!("validator {} has an empty consensus key", validator.address).into(),
+                );
+            }
+            if validator.name.len() > MAX_VALIDATOR_NAME_LEN {
+                return Err(format!(
+                    "validator {} has a name longer than {} bytes",
+                    validator.address, MAX_VALIDATOR_NAME_LEN
+                )
+                .into());
+            }
+            if !seen.insert(&validator.address) {
+                return Err(format!("validator {} is listed twice", validator.address).into());
+            }
+            if !keys.insert(validator.consensus_key()) {
+                return Err(format!(
+                    "validator {} reuses another validator's consensus key",
+                    validator.address
+                )
+                .into());
+            }
+        }
+
+        let allocated = self
+            .allocations
+            .values()
+            .chain(self.validators.iter().map(|v| &v.stake))
+            .try_fold(0u64, |total, amount| total.checked_add(*amount));
+        if allocated.is_none() {
+            return Err("genesis allocations overflow the total supply".into());
+        }
+        Ok(())
+    }
+
+    /// Canonical binary encoding of the configuration. Allocations are
+    /// encoded in address order and validators in file order.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_bytes(&mut out, self.chain_id.as_bytes());
+        out.extend_from_slice(&self.genesis_time.to_be_bytes());
+
+        out.extend_from_slice(&(self.allocations.len() as u32).to_be_bytes());
+        for (address, amount) in &self.allocations {
+            encode_bytes(&mut out, address.as_bytes());
+            out.extend_from_slice(&amount.to_be_bytes());
+        }
+
+        out.extend_from_slice(&(self.validators.len() as u32).to_be_bytes());
+        for validator in &self.validators {
+            encode_bytes(&mut out, validator.address.as_bytes());
+            out.extend_from_slice(&validator.stake.to_be_bytes());
+            encode_bytes(&mut out, validator.consensus_key().as_bytes());
+            encode_bytes(&mut out, validator.name.as_bytes());
+        }
+
+        self.consensus.encode(&mut out);
+        out
+    }
+
+    pub fn hash(&self) -> Hash {
+        sha3_256(&self.encode())
+    }
+
+    /// Balances from the allocations, and registrations and stakes from the
+    /// validator list, with the first active set chosen from them. Balances
+    /// and stakes both count towards the initial total supply.
+    pub fn initial_state(&self) -> (AccountState, StakingState) {
+        let mut accounts = AccountState::new();
+        for (address, amount) in &self.allocations {
+            accounts
+                .mint(address, *amount)
+                .expect("genesis allocations are checked for overflow");
+        }
+
+        let mut staking_state = StakingState::new();
+        for validator in &self.validators {
+            let registered = staking_state.register_validator(
+                &validator.address,
+                validator.consensus_key(),
+                &validator.name,
+            );
+            assert!(registered, "genesis validators are checked by validate");
+            staking_state.stake(validator.address.clone(), validator.stake);
+            accounts.total_supply += validator.stake;
+        }
+        staking_state.update_active_set(&self.consensus);
+        (accounts, staking_state)
+    }
+
+    /// Block 0. It has no parent, so its `previous_hash` commits to the
+    /// genesis configuration; two networks with different configurations
+    /// therefore have different genesis hashes.
+    pub fn genesis_block(&self) -> Block {
+        let (accounts, staking_state) = self.initial_state();
+        let header = BlockHeader {
+            version: BLOCK_VERSION,
+            height: 0,
+            previous_hash: self.hash(),
+            merkle_root: ZERO_HASH,
+            tx_count: 0,
+            state_root: state_tree(&accounts, &staking_state, &TaskQueue::new()).root(),
+            timestamp: self.genesis_time,
+            proposer: String::new(),
+        };
+        Block::from_header(header, vec![])
+    }
+}
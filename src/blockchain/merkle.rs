@@ -0,0 +1,135 @@
+use crate::cryptography::sha3::{hex_hash, sha3_256_concat, Hash, ZERO_HASH};
+use serde::{Deserialize, Serialize};
+
+// Domain separation prefixes so a leaf can never be passed off as an inner node.
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+pub fn leaf_hash(data: &[u8]) -> Hash {
+    sha3_256_concat(&[&[LEAF_PREFIX], data])
+}
+
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    sha3_256_concat(&[&[NODE_PREFIX], left, right])
+}
+
+/// Computes the binary Merkle root over already-hashed leaves.
+///
+/// A node without a sibling is promoted to the next level unchanged instead of
+/// being paired with itself. An empty list yields `ZERO_HASH`.
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return ZERO_HASH;
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// One step of an inclusion proof: the sibling hash and the side it sits on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofStep {
+    #[serde(with = "hex_hash")]
+    pub hash: Hash,
+    pub side: Side,
+}
+
+/// Inclusion proof for a single leaf, ordered from the leaf up to the root.
+/// Levels where the node is promoted without a sibling contribute no step.
+/// The leaf count is not part of the proof: the root does not commit to it,
+/// so the verifier takes it from the block header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub steps: Vec<ProofStep>,
+}
+
+/// Builds an inclusion proof for `leaves[index]`, or `None` if out of range.
+pub fn merkle_proof(leaves: &[Hash], index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut steps = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut position = index;
+    while level.len() > 1 {
+        let sibling = position ^ 1;
+        if sibling < level.len() {
+            let side = if sibling < position {
+                Side::Left
+            } else {
+                Side::Right
+            };
+            steps.push(ProofStep {
+                hash: level[sibling],
+                side,
+            });
+        }
+        level = next_level(&level);
+        position /= 2;
+    }
+
+    Some(MerkleProof { index, steps })
+}
+
+/// Checks that `leaf` is included under `root` according to `proof`, in a
+/// tree of `leaf_count` leaves. This needs nothing but the leaf, the proof
+/// and the root and transaction count from a block header.
+///
+/// Which side each sibling sits on follows from `proof.index` and
+/// `leaf_count`, so a proof only verifies for the position it was built for.
+/// Steps whose stated side disagrees, and missing or extra steps, fail.
+pub fn verify_merkle_proof(
+    leaf: &Hash,
+    proof: &MerkleProof,
+    leaf_count: usize,
+    root: &Hash,
+) -> bool {
+    if proof.index >= leaf_count {
+        return false;
+    }
+
+    let mut steps = proof.steps.iter();
+    let mut computed = *leaf;
+    let mut level_len = leaf_count;
+    let mut position = proof.index;
+    while level_len > 1 {
+        let sibling = position ^ 1;
+        if sibling < level_len {
+            let step = match steps.next() {
+                Some(step) => step,
+                None => return false,
+            };
+            computed = match (sibling < position, step.side) {
+                (true, Side::Left) => node_hash(&step.hash, &computed),
+                (false, Side::Right) => node_hash(&computed, &step.hash),
+                _ => return false,
+            };
+        }
+        level_len = level_len.div_ceil(2);
+        position /= 2;
+    }
+    steps.next().is_none() && &computed == root
+}
@@ -1,3 +1,7 @@
 pub mod block;
 pub mod chain;
-pub mod state;
\ No newline at end of file
+pub mod genesis;
+pub mod merkle;
+pub mod sparse_merkle;
+pub mod state;
+pub mod validation;
@@ -0,0 +1,159 @@
+use crate::blockchain::merkle::{leaf_hash, node_hash};
+use crate::cryptography::sha3::{hex_hash, hex_hashes, sha3_256, Hash, ZERO_HASH};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Number of bits in a path, and so the deepest a proof can reach.
+const DEPTH: usize = 256;
+
+/// Sparse Merkle tree over 256-bit paths. Keys are placed at `sha3_256(key)`
+/// and the bits of that hash, most significant first, choose left (0) or
+/// right (1) at each level.
+///
+/// Empty subtrees hash to `ZERO_HASH`, and a subtree holding a single entry
+/// hashes to that entry's leaf, so proofs only reach as deep as needed to
+/// separate a key from its neighbours.
+#[derive(Debug, Clone, Default)]
+pub struct SparseMerkleTree {
+    leaves: BTreeMap<Hash, Hash>, // Path -> value hash
+}
+
+/// The entry found at the bottom of a proof path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofLeaf {
+    #[serde(with = "hex_hash")]
+    pub path: Hash,
+    #[serde(with = "hex_hash")]
+    pub value_hash: Hash,
+}
+
+/// Proof for one key. `siblings` are ordered from the root down; `leaf` is
+/// the entry where the path ends, or `None` if it ends in an empty subtree.
+/// The key is included when `leaf` sits at the key's own path and excluded
+/// otherwise.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SparseMerkleProof {
+    pub leaf: Option<ProofLeaf>,
+    #[serde(with = "hex_hashes")]
+    pub siblings: Vec<Hash>,
+}
+
+impl SparseMerkleTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) {
+        self.leaves.insert(sha3_256(key), sha3_256(value));
+    }
+
+    pub fn root(&self) -> Hash {
+        let entries: Vec<(Hash, Hash)> = self.leaves.iter().map(|(k, v)| (*k, *v)).collect();
+        subtree_root(&entries, 0)
+    }
+
+    pub fn prove(&self, key: &[u8]) -> SparseMerkleProof {
+        let path = sha3_256(key);
+        let entries: Vec<(Hash, Hash)> = self.leaves.iter().map(|(k, v)| (*k, *v)).collect();
+
+        let mut siblings = Vec::new();
+        let mut subtree = &entries[..];
+        let mut depth = 0;
+        while subtree.len() > 1 {
+            let (left, right) = split(subtree, depth);
+            if bit(&path, depth) {
+                siblings.push(subtree_root(left, depth + 1));
+                subtree = right;
+            } else {
+                siblings.push(subtree_root(right, depth + 1));
+                subtree = left;
+            }
+            depth += 1;
+        }
+
+        SparseMerkleProof {
+            leaf: subtree.first().map(|(path, value_hash)| ProofLeaf {
+                path: *path,
+                value_hash: *value_hash,
+            }),
+            siblings,
+        }
+    }
+}
+
+/// Checks that `key` maps to `value` under `root`.
+pub fn verify_inclusion(root: &Hash, key: &[u8], value: &[u8], proof: &SparseMerkleProof) -> bool {
+    let path = sha3_256(key);
+    match &proof.leaf {
+        Some(leaf) if leaf.path == path && leaf.value_hash == sha3_256(value) => {
+            fold_proof(&path, entry_hash(&leaf.path, &leaf.value_hash), proof) == Some(*root)
+        }
+        _ => false,
+    }
+}
+
+/// Checks that `key` has no entry under `root`: the proof path either ends in
+/// an empty subtree or at another key sharing every bit walked so far.
+pub fn verify_exclusion(root: &Hash, key: &[u8], proof: &SparseMerkleProof) -> bool {
+    let path = sha3_256(key);
+    let bottom = match &proof.leaf {
+        None => ZERO_HASH,
+        Some(leaf) => {
+            let depth = proof.siblings.len().min(DEPTH);
+            if leaf.path == path || (0..depth).any(|i| bit(&leaf.path, i) != bit(&path, i)) {
+                return false;
+            }
+            entry_hash(&leaf.path, &leaf.value_hash)
+        }
+    };
+    fold_proof(&path, bottom, proof) == Some(*root)
+}
+
+/// Hashes from the bottom of the proof path up to the root, or `None` if the
+/// proof is longer than a path.
+fn fold_proof(path: &Hash, bottom: Hash, proof: &SparseMerkleProof) -> Option<Hash> {
+    if proof.siblings.len() > DEPTH {
+        return None;
+    }
+    let root = proof
+        .siblings
+        .iter()
+        .enumerate()
+        .rev()
+        .fold(bottom, |acc, (depth, sibling)| {
+            if bit(path, depth) {
+                node_hash(sibling, &acc)
+            } else {
+                node_hash(&acc, sibling)
+            }
+        });
+    Some(root)
+}
+
+fn entry_hash(path: &Hash, value_hash: &Hash) -> Hash {
+    leaf_hash(&[*path, *value_hash].concat())
+}
+
+/// Hash of the subtree at `depth` holding `entries`, which are sorted by path
+/// and share their first `depth` bits.
+fn subtree_root(entries: &[(Hash, Hash)], depth: usize) -> Hash {
+    match entries {
+        [] => ZERO_HASH,
+        [(path, value_hash)] => entry_hash(path, value_hash),
+        _ => {
+            let (left, right) = split(entries, depth);
+            node_hash(
+                &subtree_root(left, depth + 1),
+                &subtree_root(right, depth + 1),
+            )
+        }
+    }
+}
+
+fn split(entries: &[(Hash, Hash)], depth: usize) -> (&[(Hash, Hash)], &[(Hash, Hash)]) {
+    entries.split_at(entries.partition_point(|(path, _)| !bit(path, depth)))
+}
+
+fn bit(path: &Hash, depth: usize) -> bool {
+    path[depth / 8] >> (7 - depth % 8) & 1 == 1
+}
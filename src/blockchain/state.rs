@@ -1,10 +1,232 @@
-use std::collections::HashMap;
+use crate::blockchain::block::{encode_bytes, Transaction, TransactionError, TransactionKind};
+use crate::blockchain::genesis::ConsensusParams;
+use crate::blockchain::sparse_merkle::SparseMerkleTree;
+use crate::consensus::pouw::{TaskQueue, TaskType, WorkerInfo};
+use crate::cryptography::sphincs::Sphincs;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    pub balance: u64,
+    pub nonce: u64, // Number of transactions sent; the next one must carry this nonce
+}
+
+impl Account {
+    /// Value stored in the state tree: `balance (8) | nonce (8)`, big-endian.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(16);
+        out.extend_from_slice(&self.balance.to_be_bytes());
+        out.extend_from_slice(&self.nonce.to_be_bytes());
+        out
+    }
+}
+
+/// Spendable balances and nonces by address, updated as blocks are applied.
+#[derive(Debug, Clone, Default)]
+pub struct AccountState {
+    pub accounts: HashMap<String, Account>,
+    pub total_supply: u64, // Every coin issued so far, spendable or staked
+}
+
+impl AccountState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, address: &str) -> Account {
+        self.accounts.get(address).cloned().unwrap_or_default()
+    }
+
+    pub fn balance(&self, address: &str) -> u64 {
+        self.get(address).balance
+    }
+
+    pub fn nonce(&self, address: &str) -> u64 {
+        self.get(address).nonce
+    }
+
+    pub fn credit(&mut self, address: &str, amount: u64) -> Result<(), TransactionError> {
+        let account = self.accounts.entry(address.to_string()).or_default();
+        account.balance = account
+            .balance
+            .checked_add(amount)
+            .ok_or(TransactionError::BalanceOverflow)?;
+        Ok(())
+    }
+
+    /// Issues `amount` new coins to `address`, growing the total supply.
+    pub fn mint(&mut self, address: &str, amount: u64) -> Result<(), TransactionError> {
+        let total_supply = self
+            .total_supply
+            .checked_add(amount)
+            .ok_or(TransactionError::BalanceOverflow)?;
+        self.credit(address, amount)?;
+        self.total_supply = total_supply;
+        Ok(())
+    }
+
+    /// Works out the accounts touched by `transactions`, applied in order,
+    /// without modifying the state. Only balances and nonces are covered;
+    /// stakes are updated by `StakingState::apply_transaction`. On failure
+    /// returns the index of the offending transaction.
+    pub fn preview(
+        &self,
+        transactions: &[Transaction],
+    ) -> Result<HashMap<String, Account>, (usize, TransactionError)> {
+        let mut touched: HashMap<String, Account> = HashMap::new();
+        for (index, tx) in transactions.iter().enumerate() {
+            let mut sender = touched
+                .get(&tx.sender)
+                .cloned()
+                .unwrap_or_else(|| self.get(&tx.sender));
+            if tx.nonce != sender.nonce {
+                return Err((
+                    index,
+                    TransactionError::InvalidNonce {
+                        expected: sender.nonce,
+                        found: tx.nonce,
+                    },
+                ));
+            }
+            sender.nonce += 1;
+            if !tx.kind.debits_balance() {
+                touched.insert(tx.sender.clone(), sender);
+                continue;
+            }
+            if sender.balance < tx.amount {
+                return Err((
+                    index,
+                    TransactionError::InsufficientBalance {
+                        balance: sender.balance,
+                        amount: tx.amount,
+                    },
+                ));
+            }
+            sender.balance -= tx.amount;
+            touched.insert(tx.sender.clone(), sender);
+            if tx.kind != TransactionKind::Transfer {
+                continue;
+            }
+
+            let mut receiver = touched
+                .get(&tx.receiver)
+                .cloned()
+                .unwrap_or_else(|| self.get(&tx.receiver));
+            receiver.balance = receiver
+                .balance
+                .checked_add(tx.amount)
+                .ok_or((index, TransactionError::BalanceOverflow))?;
+            touched.insert(tx.receiver.clone(), receiver);
+        }
+        Ok(touched)
+    }
+
+    /// Applies `transactions` in order. Either all of them apply or the state
+    /// is left untouched.
+    pub fn apply_transactions(
+        &mut self,
+        transactions: &[Transaction],
+    ) -> Result<(), (usize, TransactionError)> {
+        let touched = self.preview(transactions)?;
+        self.accounts.extend(touched);
+        Ok(())
+    }
+}
+
+/// Reward bookkeeping for one staker.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RewardAccount {
+    pub stake_blocks: u128, // Stake held at each block of the current epoch, summed
+    pub claimed: u64,       // Rewards already credited to the balance
+}
+
+/// Rewards of one staker as reported by `/staking/rewards`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RewardSummary {
+    pub accrued: u64, // claimed + pending
+    pub claimed: u64,
+    pub pending: u64, // Earned this epoch, paid when it closes
+}
+
+/// Unstaked funds waiting out the unbonding period. They earn no rewards and
+/// carry no validator weight, but stay slashable until `release_height`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnbondingEntry {
+    pub amount: u64,
+    pub release_height: u64, // First block at which the funds are returned
+    /// Validator the funds were delegated to, or `None` for the wallet's own
+    /// stake. Slashing that validator also cuts this entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validator: Option<String>,
+}
+
+/// Longest display name a validator may register, in bytes.
+pub const MAX_VALIDATOR_NAME_LEN: usize = 64;
+
+/// A registered validator. It is keyed by its operator wallet, which holds
+/// the stake, while `consensus_key` signs its blocks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorInfo {
+    pub consensus_key: String, // Hex-encoded SPHINCS+ public key
+    pub name: String,
+}
+
+/// Payload of a validator registration: the key the sender's node will sign
+/// blocks with and its display name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorRegistration {
+    pub consensus_key: String,
+    pub name: String,
+}
+
+impl ValidatorRegistration {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        encode_bytes(out, self.consensus_key.as_bytes());
+        encode_bytes(out, self.name.as_bytes());
+    }
+
+    /// Checks that the consensus key is a hex-encoded SPHINCS+ public key and
+    /// the name is at most `MAX_VALIDATOR_NAME_LEN` bytes.
+    pub fn verify(&self) -> Result<(), TransactionError> {
+        let is_key = hex::decode(&self.consensus_key)
+            .ok()
+            .and_then(|bytes| Sphincs::public_key_from_bytes(&bytes))
+            .is_some();
+        if !is_key || self.name.len() > MAX_VALIDATOR_NAME_LEN {
+            return Err(TransactionError::InvalidValidatorRegistration);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StakingState {
     pub staked_balances: HashMap<String, u64>, // Wallet address -> Staked amount
-    pub validators: Vec<String>,              // List of validators
-    pub total_staked: u64,                    // Total staked amount in the network
+    pub validators: Vec<String>,               // Active set, highest voting power first
+    pub total_staked: u64,                     // Total staked amount in the network
+    #[serde(default)]
+    pub rewards: HashMap<String, RewardAccount>,
+    #[serde(default)]
+    pub total_rewards_paid: u64,
+    #[serde(default)]
+    pub unbonding: HashMap<String, Vec<UnbondingEntry>>, // Oldest first
+    #[serde(default)]
+    pub jailed: HashMap<String, u64>, // Wallet address -> Height the jail term ends
+    #[serde(default)]
+    pub missed_slots: HashMap<String, u64>, // Validator -> Slots missed in a row
+    #[serde(default)]
+    pub punished: BTreeSet<(String, u64)>, // Double-sign offences already slashed
+    #[serde(default)]
+    pub total_slashed: u64,
+    #[serde(default)]
+    pub delegations: HashMap<String, HashMap<String, u64>>, // Validator -> Delegator -> Amount
+    #[serde(default)]
+    pub commission_bps: HashMap<String, u64>, // Validator -> Cut of its delegators' rewards
+    #[serde(default)]
+    pub commission_raised: HashMap<String, u64>, // Validator -> Epoch of its last raise
+    #[serde(default)]
+    pub registry: HashMap<String, ValidatorInfo>, // Operator wallet -> Registration
 }
 
 impl StakingState {
@@ -13,37 +235,821 @@ impl StakingState {
             staked_balances: HashMap::new(),
             validators: Vec::new(),
             total_staked: 0,
+            rewards: HashMap::new(),
+            total_rewards_paid: 0,
+            unbonding: HashMap::new(),
+            jailed: HashMap::new(),
+            missed_slots: HashMap::new(),
+            punished: BTreeSet::new(),
+            total_slashed: 0,
+            delegations: HashMap::new(),
+            commission_bps: HashMap::new(),
+            commission_raised: HashMap::new(),
+            registry: HashMap::new(),
         }
     }
 
     pub fn stake(&mut self, wallet: String, amount: u64) {
-        let current_balance = self.staked_balances.entry(wallet.clone()).or_insert(0);
+        let current_balance = self.staked_balances.entry(wallet).or_insert(0);
         *current_balance += amount;
         self.total_staked += amount;
+    }
 
-        if !self.validators.contains(&wallet) {
-            self.validators.push(wallet);
+    /// Applies the stake changes of a staking transaction whose balance and
+    /// nonce changes have already been applied. Unstaked and undelegated
+    /// funds are released at `release_height`. Other kinds are ignored.
+    pub fn apply_transaction(
+        &mut self,
+        tx: &Transaction,
+        release_height: u64,
+    ) -> Result<(), TransactionError> {
+        match tx.kind {
+            TransactionKind::Stake => self.stake(tx.sender.clone(), tx.amount),
+            TransactionKind::Unstake => {
+                let staked = self.staked_balances.get(&tx.sender).cloned().unwrap_or(0);
+                if !self.unstake(tx.sender.clone(), tx.amount, release_height) {
+                    return Err(TransactionError::InsufficientStake {
+                        staked,
+                        amount: tx.amount,
+                    });
+                }
+            }
+            TransactionKind::Delegate => {
+                if !self.delegate(&tx.sender, &tx.receiver, tx.amount) {
+                    return Err(TransactionError::UnknownValidator);
+                }
+            }
+            TransactionKind::Undelegate => {
+                let staked = self
+                    .delegations
+                    .get(&tx.receiver)
+                    .and_then(|delegators| delegators.get(&tx.sender))
+                    .cloned()
+                    .unwrap_or(0);
+                if !self.undelegate(&tx.sender, &tx.receiver, tx.amount, release_height) {
+                    return Err(TransactionError::InsufficientStake {
+                        staked,
+                        amount: tx.amount,
+                    });
+                }
+            }
+            TransactionKind::Transfer
+            | TransactionKind::DoubleSign(_)
+            | TransactionKind::TaskResult(_)
+            | TransactionKind::TaskClaim(_)
+            | TransactionKind::TaskCommit(_)
+            | TransactionKind::TaskReveal(_)
+            | TransactionKind::TaskCreate(_)
+            | TransactionKind::TaskCancel(_)
+            | TransactionKind::TaskTypeRegister(_)
+            | TransactionKind::WorkerRegister(_)
+            | TransactionKind::WorkerUnregister
+            | TransactionKind::SetCommission { .. }
+            | TransactionKind::ValidatorRegister(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Registers `operator` as a validator that signs with `consensus_key`,
+    /// or updates its registration. Fails if the key is empty or belongs to
+    /// another validator, or the name is too long. A registered validator
+    /// joins the active set at the next epoch boundary if its stake ranks
+    /// high enough.
+    pub fn register_validator(&mut self, operator: &str, consensus_key: &str, name: &str) -> bool {
+        if consensus_key.is_empty() || name.len() > MAX_VALIDATOR_NAME_LEN {
+            return false;
+        }
+        if matches!(self.operator_of(consensus_key), Some(other) if other != operator) {
+            return false;
         }
+        self.registry.insert(
+            operator.to_string(),
+            ValidatorInfo {
+                consensus_key: consensus_key.to_string(),
+                name: name.to_string(),
+            },
+        );
+        true
+    }
+
+    /// The registered validator whose blocks are signed with `consensus_key`.
+    pub fn operator_of(&self, consensus_key: &str) -> Option<&str> {
+        self.registry
+            .iter()
+            .find(|(_, info)| info.consensus_key == consensus_key)
+            .map(|(operator, _)| operator.as_str())
     }
 
-    pub fn unstake(&mut self, wallet: String, amount: u64) -> bool {
+    /// Whether the block signer `consensus_key` belongs to a validator in the
+    /// active set.
+    pub fn is_active_key(&self, consensus_key: &str) -> bool {
+        self.operator_of(consensus_key)
+            .is_some_and(|operator| self.validators.iter().any(|v| v == operator))
+    }
+
+    /// Registered validators that may join the active set: not jailed and
+    /// holding at least `min_validator_stake` of their own, ranked by voting
+    /// power with ties broken by address.
+    pub fn candidates(&self, params: &ConsensusParams) -> Vec<String> {
+        let mut candidates: Vec<(u64, &String)> = self
+            .registry
+            .keys()
+            .filter(|operator| !self.jailed.contains_key(*operator))
+            .filter(|operator| {
+                self.staked_balances.get(*operator).cloned().unwrap_or(0)
+                    >= params.min_validator_stake
+            })
+            .map(|operator| (self.voting_power(operator), operator))
+            .collect();
+        candidates.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        candidates
+            .into_iter()
+            .map(|(_, operator)| operator.clone())
+            .collect()
+    }
+
+    /// Replaces the active set with the `max_validators` best-ranked
+    /// candidates. Called at genesis and at each epoch boundary.
+    pub fn update_active_set(&mut self, params: &ConsensusParams) {
+        let mut active = self.candidates(params);
+        active.truncate(params.max_validators as usize);
+        self.missed_slots
+            .retain(|validator, _| active.contains(validator));
+        self.validators = active;
+    }
+
+    /// Moves `amount` of `wallet`'s stake into the unbonding queue, to be
+    /// returned at `release_height`.
+    pub fn unstake(&mut self, wallet: String, amount: u64, release_height: u64) -> bool {
         if let Some(balance) = self.staked_balances.get_mut(&wallet) {
             if *balance >= amount {
                 *balance -= amount;
                 self.total_staked -= amount;
 
                 if *balance == 0 {
-                    self.validators.retain(|v| v != &wallet);
+                    self.remove_validator(&wallet);
                 }
+                self.unbonding
+                    .entry(wallet)
+                    .or_default()
+                    .push(UnbondingEntry {
+                        amount,
+                        release_height,
+                        validator: None,
+                    });
                 return true;
             }
         }
         false
     }
 
-    pub fn get_rewards(&self, wallet: &String) -> u64 {
-        let balance = self.staked_balances.get(wallet).unwrap_or(&0);
-        let reward_rate = 0.05; // Example: 5% annualized reward
-        (*balance as f64 * reward_rate) as u64
+    /// Delegates `amount` from `delegator` to `validator`, which must be
+    /// registered. The delegation adds to the validator's voting power
+    /// and earns rewards for the delegator, less the validator's commission.
+    pub fn delegate(&mut self, delegator: &str, validator: &str, amount: u64) -> bool {
+        if amount == 0 || !self.registry.contains_key(validator) {
+            return false;
+        }
+        *self
+            .delegations
+            .entry(validator.to_string())
+            .or_default()
+            .entry(delegator.to_string())
+            .or_insert(0) += amount;
+        self.total_staked += amount;
+        true
+    }
+
+    /// Moves `amount` of `delegator`'s delegation to `validator` into the
+    /// delegator's unbonding queue, to be returned at `release_height`.
+    pub fn undelegate(
+        &mut self,
+        delegator: &str,
+        validator: &str,
+        amount: u64,
+        release_height: u64,
+    ) -> bool {
+        let Some(delegators) = self.delegations.get_mut(validator) else {
+            return false;
+        };
+        match delegators.get_mut(delegator) {
+            Some(delegated) if amount > 0 && *delegated >= amount => {
+                *delegated -= amount;
+                if *delegated == 0 {
+                    delegators.remove(delegator);
+                }
+            }
+            _ => return false,
+        }
+        if delegators.is_empty() {
+            self.delegations.remove(validator);
+        }
+        self.total_staked -= amount;
+
+        self.unbonding
+            .entry(delegator.to_string())
+            .or_default()
+            .push(UnbondingEntry {
+                amount,
+                release_height,
+                validator: Some(validator.to_string()),
+            });
+        true
+    }
+
+    /// Sets the share of its delegators' rewards that `validator` keeps, in
+    /// basis points, during `epoch`. Only wallets with stake of their own may
+    /// set one. A raise is limited to `params.max_commission_change_bps` and
+    /// to one per epoch, so that delegators are not surprised by a jump just
+    /// before a payout; lowering the rate is always allowed.
+    pub fn set_commission(
+        &mut self,
+        validator: &str,
+        bps: u64,
+        epoch: u64,
+        params: &ConsensusParams,
+    ) -> Result<(), TransactionError> {
+        if self.staked_balances.get(validator).cloned().unwrap_or(0) == 0 {
+            return Err(TransactionError::NoStake);
+        }
+        let current = self.get_commission(validator);
+        if bps > current {
+            let raised_this_epoch = self.commission_raised.get(validator) == Some(&epoch);
+            if bps > 10_000 || bps - current > params.max_commission_change_bps || raised_this_epoch
+            {
+                return Err(TransactionError::InvalidCommission {
+                    current,
+                    requested: bps,
+                });
+            }
+            self.commission_raised.insert(validator.to_string(), epoch);
+        }
+        if bps == 0 {
+            self.commission_bps.remove(validator);
+        } else {
+            self.commission_bps.insert(validator.to_string(), bps);
+        }
+        Ok(())
+    }
+
+    pub fn get_commission(&self, validator: &str) -> u64 {
+        self.commission_bps.get(validator).cloned().unwrap_or(0)
+    }
+
+    /// Total delegated to `validator`.
+    pub fn delegated_to(&self, validator: &str) -> u64 {
+        self.delegations
+            .get(validator)
+            .map_or(0, |delegators| delegators.values().sum())
+    }
+
+    /// Delegations made by `wallet`, as `(validator, amount)` in validator
+    /// order.
+    pub fn delegations_of(&self, wallet: &str) -> Vec<(String, u64)> {
+        let mut delegations: Vec<(String, u64)> = self
+            .delegations
+            .iter()
+            .filter_map(|(validator, delegators)| {
+                delegators
+                    .get(wallet)
+                    .map(|amount| (validator.clone(), *amount))
+            })
+            .collect();
+        delegations.sort();
+        delegations
+    }
+
+    /// Delegations to `validator`, as `(delegator, amount)` in delegator
+    /// order.
+    pub fn delegators_of(&self, validator: &str) -> Vec<(String, u64)> {
+        let mut delegators: Vec<(String, u64)> = self
+            .delegations
+            .get(validator)
+            .map(|delegators| {
+                delegators
+                    .iter()
+                    .map(|(delegator, amount)| (delegator.clone(), *amount))
+                    .collect()
+            })
+            .unwrap_or_default();
+        delegators.sort();
+        delegators
+    }
+
+    /// A validator's own stake plus everything delegated to it.
+    pub fn voting_power(&self, validator: &str) -> u64 {
+        self.staked_balances
+            .get(validator)
+            .cloned()
+            .unwrap_or(0)
+            .saturating_add(self.delegated_to(validator))
+    }
+
+    /// Removes the unbonding entries that mature at or before `height` and
+    /// returns the amounts to return to each wallet, in address order.
+    pub fn release_matured(&mut self, height: u64) -> Vec<(String, u64)> {
+        let mut released = Vec::new();
+        for (wallet, entries) in self.unbonding.iter_mut() {
+            let amount = entries
+                .iter()
+                .filter(|entry| entry.release_height <= height)
+                .fold(0u64, |total, entry| total.saturating_add(entry.amount));
+            if amount > 0 {
+                entries.retain(|entry| entry.release_height > height);
+                released.push((wallet.clone(), amount));
+            }
+        }
+        self.unbonding.retain(|_, entries| !entries.is_empty());
+        released.sort();
+        released
+    }
+
+    pub fn get_unbonding(&self, wallet: &str) -> &[UnbondingEntry] {
+        self.unbonding.get(wallet).map_or(&[], Vec::as_slice)
+    }
+
+    /// Everything that could be slashed for `wallet`'s misbehaviour: its own
+    /// stake and unbonding funds, plus the stake delegated to it, including
+    /// delegations that are still unbonding.
+    pub fn bonded(&self, wallet: &str) -> u64 {
+        let own = self
+            .get_unbonding(wallet)
+            .iter()
+            .filter(|entry| entry.validator.is_none())
+            .fold(
+                self.staked_balances.get(wallet).cloned().unwrap_or(0),
+                |total, entry| total.saturating_add(entry.amount),
+            );
+        self.unbonding
+            .values()
+            .flatten()
+            .filter(|entry| entry.validator.as_deref() == Some(wallet))
+            .fold(
+                own.saturating_add(self.delegated_to(wallet)),
+                |total, entry| total.saturating_add(entry.amount),
+            )
+    }
+
+    /// Takes `bps` basis points of everything counted by `bonded`: `wallet`'s
+    /// stake, each delegation to it and each unbonding entry of either,
+    /// rounding each down. Returns the total taken.
+    pub fn slash(&mut self, wallet: &str, bps: u64) -> u64 {
+        let cut = |amount: u64| (amount as u128 * bps.min(10_000) as u128 / 10_000) as u64;
+        let mut slashed = 0u64;
+
+        if let Some(stake) = self.staked_balances.get_mut(wallet) {
+            let amount = cut(*stake);
+            *stake -= amount;
+            self.total_staked -= amount;
+            slashed += amount;
+            if *stake == 0 {
+                self.remove_validator(wallet);
+            }
+        }
+        if let Some(delegators) = self.delegations.get_mut(wallet) {
+            for delegated in delegators.values_mut() {
+                let amount = cut(*delegated);
+                *delegated -= amount;
+                self.total_staked -= amount;
+                slashed += amount;
+            }
+            delegators.retain(|_, delegated| *delegated > 0);
+            if delegators.is_empty() {
+                self.delegations.remove(wallet);
+            }
+        }
+        for (owner, entries) in self.unbonding.iter_mut() {
+            for entry in entries.iter_mut() {
+                let liable = match &entry.validator {
+                    Some(validator) => validator == wallet,
+                    None => owner == wallet,
+                };
+                if liable {
+                    let amount = cut(entry.amount);
+                    entry.amount -= amount;
+                    slashed += amount;
+                }
+            }
+            entries.retain(|entry| entry.amount > 0);
+        }
+        self.unbonding.retain(|_, entries| !entries.is_empty());
+
+        self.total_slashed = self.total_slashed.saturating_add(slashed);
+        slashed
+    }
+
+    /// Removes `wallet` from the validator set until `until`. A wallet that is
+    /// already jailed keeps the later of the two release heights.
+    pub fn jail(&mut self, wallet: &str, until: u64) {
+        self.remove_validator(wallet);
+        let release = self.jailed.entry(wallet.to_string()).or_insert(until);
+        *release = (*release).max(until);
+    }
+
+    /// Ends the jail terms that are over at `height`. Released validators
+    /// become candidates for the active set again at the next epoch
+    /// boundary.
+    pub fn release_jailed(&mut self, height: u64) {
+        self.jailed.retain(|_, until| *until > height);
+    }
+
+    /// Records that the validator signing with `proposer_key` produced a
+    /// block in a slot where the validators in `skipped` were ranked ahead of
+    /// it and missed their turn. Returns those that have now missed more than
+    /// `max_missed_slots` slots in a row, in address order.
+    pub fn record_block(
+        &mut self,
+        proposer_key: &str,
+        skipped: &[String],
+        max_missed_slots: u64,
+    ) -> Vec<String> {
+        if let Some(proposer) = self.operator_of(proposer_key) {
+            let proposer = proposer.to_string();
+            self.missed_slots.remove(&proposer);
+        }
+
+        let mut down = Vec::new();
+        for validator in skipped {
+            let missed = self.missed_slots.entry(validator.clone()).or_insert(0);
+            *missed += 1;
+            if *missed > max_missed_slots {
+                down.push(validator.clone());
+            }
+        }
+        down.sort();
+        down
+    }
+
+    fn remove_validator(&mut self, wallet: &str) {
+        self.validators.retain(|v| v != wallet);
+        self.missed_slots.remove(wallet);
+    }
+
+    /// Records one block of staking: each validator in the active set
+    /// accrues weight equal to its own stake. Each delegation to an active
+    /// validator accrues weight equal to its amount, of which the validator's
+    /// commission goes to the validator and the rest to the delegator.
+    pub fn accrue_block(&mut self) {
+        for validator in &self.validators {
+            let stake = self.staked_balances.get(validator).cloned().unwrap_or(0);
+            if stake > 0 {
+                self.rewards
+                    .entry(validator.clone())
+                    .or_default()
+                    .stake_blocks += stake as u128;
+            }
+        }
+        for (validator, delegators) in &self.delegations {
+            if !self.validators.contains(validator) {
+                continue;
+            }
+            let commission = self.get_commission(validator) as u128;
+            let mut commission_blocks = 0u128;
+            for (delegator, amount) in delegators {
+                let fee = *amount as u128 * commission / 10_000;
+                commission_blocks += fee;
+                self.rewards
+                    .entry(delegator.clone())
+                    .or_default()
+                    .stake_blocks += *amount as u128 - fee;
+            }
+            if commission_blocks > 0 {
+                self.rewards
+                    .entry(validator.clone())
+                    .or_default()
+                    .stake_blocks += commission_blocks;
+            }
+        }
+    }
+
+    /// Closes the epoch: turns the weight each staker accrued into a reward,
+    /// marks it claimed and returns the payouts to credit, in address order.
+    pub fn close_epoch(&mut self, params: &ConsensusParams) -> Vec<(String, u64)> {
+        let mut payouts = Vec::new();
+        for (wallet, account) in self.rewards.iter_mut() {
+            let amount = epoch_reward(account.stake_blocks, params);
+            account.stake_blocks = 0;
+            if amount > 0 {
+                account.claimed = account.claimed.saturating_add(amount);
+                payouts.push((wallet.clone(), amount));
+            }
+        }
+        payouts.sort();
+        self.total_rewards_paid = payouts
+            .iter()
+            .fold(self.total_rewards_paid, |total, (_, amount)| {
+                total.saturating_add(*amount)
+            });
+        payouts
+    }
+
+    /// What one block earns the whole stake: the coins `ProofOfStake` mints
+    /// per block on average, and `ProofOfUsefulWork` pays for verified work.
+    pub fn block_issuance(&self, params: &ConsensusParams) -> u64 {
+        epoch_reward(self.total_staked as u128, params)
+    }
+
+    pub fn get_rewards(&self, wallet: &str, params: &ConsensusParams) -> RewardSummary {
+        let account = self.rewards.get(wallet).cloned().unwrap_or_default();
+        let pending = epoch_reward(account.stake_blocks, params);
+        RewardSummary {
+            accrued: account.claimed.saturating_add(pending),
+            claimed: account.claimed,
+            pending,
+        }
+    }
+}
+
+/// Reward for `stake_blocks` of accrued weight. Holding a stake for a whole
+/// epoch earns `epoch_reward_bps` basis points of it; shorter periods earn
+/// proportionally less. Fractions of a coin are dropped.
+fn epoch_reward(stake_blocks: u128, params: &ConsensusParams) -> u64 {
+    let reward =
+        stake_blocks * params.epoch_reward_bps as u128 / (10_000 * params.epoch_length as u128);
+    reward.min(u64::MAX as u128) as u64
+}
+
+/// Copy of the block-derived state, taken before a block is applied so that
+/// a reorganization can roll the block back.
+#[derive(Debug, Clone)]
+pub struct StateSnapshot {
+    pub accounts: AccountState,
+    pub staking_state: StakingState,
+    pub task_queue: TaskQueue,
+}
+
+/// Key of an entry in the state tree. Each kind gets its own prefix, so an
+/// address and a task id that happen to be the same string never collide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateKey<'a> {
+    Account(&'a str),
+    Stake(&'a str),
+    Unbonding(&'a str),
+    Jail(&'a str),
+    /// A delegation, as `(validator, delegator)`.
+    Delegation(&'a str, &'a str),
+    Commission(&'a str),
+    /// The epoch a validator last raised its commission in.
+    CommissionRaised(&'a str),
+    Validator(&'a str),
+    /// A staker's reward bookkeeping, including rewards accrued this epoch
+    /// but not yet paid.
+    Reward(&'a str),
+    MissedSlots(&'a str),
+    /// The double-sign offences a validator has been slashed for.
+    Punished(&'a str),
+    TotalSupply,
+    TotalSlashed,
+    TotalRewardsPaid,
+    Task(&'a str),
+    TaskType(&'a str),
+    Worker(&'a str),
+}
+
+impl StateKey<'_> {
+    pub fn encode(&self) -> Vec<u8> {
+        let parts: &[&[u8]] = match self {
+            StateKey::Account(address) => &[b"account/", address.as_bytes()],
+            StateKey::Stake(address) => &[b"stake/", address.as_bytes()],
+            StateKey::Unbonding(address) => &[b"unbonding/", address.as_bytes()],
+            StateKey::Jail(address) => &[b"jail/", address.as_bytes()],
+            StateKey::Delegation(validator, delegator) => &[
+                b"delegation/",
+                validator.as_bytes(),
+                b"/",
+                delegator.as_bytes(),
+            ],
+            StateKey::Commission(address) => &[b"commission/", address.as_bytes()],
+            StateKey::CommissionRaised(address) => &[b"commission_raised/", address.as_bytes()],
+            StateKey::Validator(address) => &[b"validator/", address.as_bytes()],
+            StateKey::Reward(address) => &[b"reward/", address.as_bytes()],
+            StateKey::MissedSlots(address) => &[b"missed_slots/", address.as_bytes()],
+            StateKey::Punished(address) => &[b"punished/", address.as_bytes()],
+            StateKey::TotalSupply => &[b"total_supply"],
+            StateKey::TotalSlashed => &[b"total_slashed"],
+            StateKey::TotalRewardsPaid => &[b"total_rewards_paid"],
+            StateKey::Task(id) => &[b"task/", id.as_bytes()],
+            StateKey::TaskType(name) => &[b"task_type/", name.as_bytes()],
+            StateKey::Worker(address) => &[b"worker/", address.as_bytes()],
+        };
+        parts.concat()
+    }
+}
+
+/// Value of `key` in the state tree, or `None` if the tree has no entry
+/// for it.
+pub fn state_value(
+    key: StateKey,
+    accounts: &AccountState,
+    staking_state: &StakingState,
+    task_queue: &TaskQueue,
+) -> Option<Vec<u8>> {
+    match key {
+        StateKey::Account(address) => accounts
+            .accounts
+            .get(address)
+            .filter(|account| **account != Account::default())
+            .map(Account::encode),
+        StateKey::Stake(address) => staking_state
+            .staked_balances
+            .get(address)
+            .filter(|amount| **amount > 0)
+            .map(|amount| amount.to_be_bytes().to_vec()),
+        StateKey::Unbonding(address) => staking_state
+            .unbonding
+            .get(address)
+            .filter(|entries| !entries.is_empty())
+            .map(|entries| {
+                let mut out = Vec::new();
+                for entry in entries {
+                    out.extend_from_slice(&entry.amount.to_be_bytes());
+                    out.extend_from_slice(&entry.release_height.to_be_bytes());
+                    encode_bytes(
+                        &mut out,
+                        entry.validator.as_deref().unwrap_or_default().as_bytes(),
+                    );
+                }
+                out
+            }),
+        StateKey::Jail(address) => staking_state
+            .jailed
+            .get(address)
+            .map(|until| until.to_be_bytes().to_vec()),
+        StateKey::Delegation(validator, delegator) => staking_state
+            .delegations
+            .get(validator)
+            .and_then(|delegators| delegators.get(delegator))
+            .filter(|amount| **amount > 0)
+            .map(|amount| amount.to_be_bytes().to_vec()),
+        StateKey::Commission(address) => staking_state
+            .commission_bps
+            .get(address)
+            .filter(|bps| **bps > 0)
+            .map(|bps| bps.to_be_bytes().to_vec()),
+        StateKey::CommissionRaised(address) => staking_state
+            .commission_raised
+            .get(address)
+            .map(|epoch| epoch.to_be_bytes().to_vec()),
+        StateKey::Validator(address) => staking_state.registry.get(address).map(|info| {
+            let mut out = Vec::new();
+            encode_bytes(&mut out, info.consensus_key.as_bytes());
+            encode_bytes(&mut out, info.name.as_bytes());
+            out.push(staking_state.validators.iter().any(|v| v == address) as u8);
+            out
+        }),
+        StateKey::Reward(address) => staking_state
+            .rewards
+            .get(address)
+            .filter(|account| **account != RewardAccount::default())
+            .map(|account| {
+                let mut out = account.stake_blocks.to_be_bytes().to_vec();
+                out.extend_from_slice(&account.claimed.to_be_bytes());
+                out
+            }),
+        StateKey::MissedSlots(address) => staking_state
+            .missed_slots
+            .get(address)
+            .filter(|missed| **missed > 0)
+            .map(|missed| missed.to_be_bytes().to_vec()),
+        StateKey::Punished(address) => {
+            let out: Vec<u8> = staking_state
+                .punished
+                .iter()
+                .filter(|(validator, _)| validator == address)
+                .flat_map(|(_, height)| height.to_be_bytes())
+                .collect();
+            Some(out).filter(|out| !out.is_empty())
+        }
+        StateKey::TotalSupply => Some(accounts.total_supply)
+            .filter(|total| *total > 0)
+            .map(|total| total.to_be_bytes().to_vec()),
+        StateKey::TotalSlashed => Some(staking_state.total_slashed)
+            .filter(|total| *total > 0)
+            .map(|total| total.to_be_bytes().to_vec()),
+        StateKey::TotalRewardsPaid => Some(staking_state.total_rewards_paid)
+            .filter(|total| *total > 0)
+            .map(|total| total.to_be_bytes().to_vec()),
+        StateKey::Task(id) => task_queue.tasks.get(id).map(|task| task.encode()),
+        StateKey::TaskType(name) => task_queue.task_types.get(name).map(TaskType::encode),
+        StateKey::Worker(address) => task_queue.workers.get(address).map(WorkerInfo::encode),
+    }
+}
+
+/// Builds the sparse Merkle tree whose root each block header commits to as
+/// `state_root`. It holds every non-empty account, every non-zero stake (as a
+/// big-endian `u64`), every non-empty unbonding queue (each entry as
+/// `amount (8) | release_height (8) | validator`, oldest first, with the
+/// validator length-prefixed and empty for the wallet's own stake), the
+/// height each jailed validator is released at, every delegation and
+/// non-zero commission rate (each as a big-endian `u64`), the epoch of each
+/// validator's last commission raise, every validator registration
+/// (length-prefixed consensus key and name, then `1` if the validator is in
+/// the active set and `0` otherwise), every staker's reward bookkeeping
+/// (`stake_blocks (16) | claimed (8)`), every non-zero count of missed slots,
+/// the heights of each validator's slashed double-sign offences, the total
+/// supply, total slashed and total rewards paid, every task, every task type
+/// and every worker registry entry. Empty entries are left out so that an
+/// address that was never used and one that was drained look the same.
+pub fn state_tree(
+    accounts: &AccountState,
+    staking_state: &StakingState,
+    task_queue: &TaskQueue,
+) -> SparseMerkleTree {
+    let keys = accounts
+        .accounts
+        .keys()
+        .map(|address| StateKey::Account(address))
+        .chain(
+            staking_state
+                .staked_balances
+                .keys()
+                .map(|address| StateKey::Stake(address)),
+        )
+        .chain(
+            staking_state
+                .unbonding
+                .keys()
+                .map(|address| StateKey::Unbonding(address)),
+        )
+        .chain(
+            staking_state
+                .jailed
+                .keys()
+                .map(|address| StateKey::Jail(address)),
+        )
+        .chain(
+            staking_state
+                .delegations
+                .iter()
+                .flat_map(|(validator, delegators)| {
+                    delegators
+                        .keys()
+                        .map(move |delegator| StateKey::Delegation(validator, delegator))
+                }),
+        )
+        .chain(
+            staking_state
+                .commission_bps
+                .keys()
+                .map(|address| StateKey::Commission(address)),
+        )
+        .chain(
+            staking_state
+                .commission_raised
+                .keys()
+                .map(|address| StateKey::CommissionRaised(address)),
+        )
+        .chain(
+            staking_state
+                .registry
+                .keys()
+                .map(|address| StateKey::Validator(address)),
+        )
+        .chain(
+            staking_state
+                .rewards
+                .keys()
+                .map(|address| StateKey::Reward(address)),
+        )
+        .chain(
+            staking_state
+                .missed_slots
+                .keys()
+                .map(|address| StateKey::MissedSlots(address)),
+        )
+        .chain(
+            staking_state
+                .punished
+                .iter()
+                .map(|(address, _)| address.as_str())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .map(StateKey::Punished),
+        )
+        .chain([
+            StateKey::TotalSupply,
+            StateKey::TotalSlashed,
+            StateKey::TotalRewardsPaid,
+        ])
+        .chain(task_queue.tasks.keys().map(|id| StateKey::Task(id)))
+        .chain(
+            task_queue
+                .task_types
+                .keys()
+                .map(|name| StateKey::TaskType(name)),
+        )
+        .chain(
+            task_queue
+                .workers
+                .keys()
+                .map(|address| StateKey::Worker(address)),
+        );
+
+    let mut tree = SparseMerkleTree::new();
+    for key in keys {
+        if let Some(value) = state_value(key, accounts, staking_state, task_queue) {
+            tree.insert(&key.encode(), &value);
+        }
     }
+    tree
 }
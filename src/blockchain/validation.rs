@@ -0,0 +1,235 @@
+use crate::blockchain::block::{transactions_root, Block, TransactionError, BLOCK_VERSION};
+use crate::cryptography::sha3::Hash;
+use crate::storage::database::StorageError;
+use std::fmt;
+
+/// How far ahead of the local clock a block timestamp may be, in milliseconds.
+pub const MAX_FUTURE_DRIFT_MS: u128 = 15_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockValidationError {
+    UnsupportedVersion(u32),
+    /// The stored hash does not match the hash of the header.
+    HashMismatch {
+        height: u64,
+    },
+    /// The stored genesis block differs from the one this node expects.
+    GenesisMismatch,
+    PreviousHashMismatch {
+        height: u64,
+    },
+    NonSequentialHeight {
+        expected: u64,
+        found: u64,
+    },
+    TimestampBeforeParent {
+        height: u64,
+    },
+    TimestampInFuture {
+        height: u64,
+    },
+    /// The header's `merkle_root` or `tx_count` does not describe the
+    /// block's transactions.
+    MerkleRootMismatch {
+        height: u64,
+    },
+    /// Paying epoch rewards or matured withdrawals would overflow a balance
+    /// or the total supply.
+    BalanceOverflow {
+        height: u64,
+    },
+    /// The state after applying the block does not match its `state_root`.
+    StateRootMismatch {
+        height: u64,
+    },
+    InvalidBlockSignature {
+        height: u64,
+    },
+    /// The proposer is not in the active validator set.
+    UnknownProposer {
+        height: u64,
+        proposer: String,
+    },
+    /// The block is off the canonical chain at or below the finalized
+    /// height, so it can never become canonical.
+    ConflictsWithFinalized {
+        height: u64,
+    },
+    /// The proposer is active but not eligible for this slot: it was not
+    /// elected, and the validators ranked ahead of it have not timed out.
+    WrongProposer {
+        height: u64,
+        expected: String,
+        proposer: String,
+    },
+    InvalidTransaction {
+        index: usize,
+        error: TransactionError,
+    },
+    /// A stored record could not be decoded.
+    Corrupted {
+        key: String,
+        reason: String,
+    },
+    /// The persisted staking state and tasks were written for a different tip.
+    StateMismatch,
+    Storage(String),
+}
+
+impl fmt::Display for BlockValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockValidationError::UnsupportedVersion(version) => {
+                write!(f, "unsupported block version {}", version)
+            }
+            BlockValidationError::HashMismatch { height } => {
+                write!(f, "block {} hash does not match its header", height)
+            }
+            BlockValidationError::GenesisMismatch => write!(f, "genesis block mismatch"),
+            BlockValidationError::PreviousHashMismatch { height } => {
+                write!(f, "block {} does not link to its parent", height)
+            }
+            BlockValidationError::NonSequentialHeight { expected, found } => {
+                write!(f, "expected block height {}, found {}", expected, found)
+            }
+            BlockValidationError::TimestampBeforeParent { height } => {
+                write!(f, "block {} is older than its parent", height)
+            }
+            BlockValidationError::TimestampInFuture { height } => {
+                write!(f, "block {} timestamp is too far in the future", height)
+            }
+            BlockValidationError::MerkleRootMismatch { height } => {
+                write!(
+                    f,
+                    "block {} merkle root or transaction count does not match its transactions",
+                    height
+                )
+            }
+            BlockValidationError::BalanceOverflow { height } => {
+                write!(f, "block {} payouts overflow a balance", height)
+            }
+            BlockValidationError::StateRootMismatch { height } => {
+                write!(
+                    f,
+                    "block {} state root does not match the resulting state",
+                    height
+                )
+            }
+            BlockValidationError::InvalidBlockSignature { height } => {
+                write!(f, "block {} has an invalid proposer signature", height)
+            }
+            BlockValidationError::UnknownProposer { height, proposer } => {
+                write!(
+                    f,
+                    "block {} proposer {} is not an active validator",
+                    height, proposer
+                )
+            }
+            BlockValidationError::ConflictsWithFinalized { height } => {
+                write!(f, "block {} conflicts with a finalized block", height)
+            }
+            BlockValidationError::WrongProposer {
+                height,
+                expected,
+                proposer,
+            } => {
+                write!(
+                    f,
+                    "block {} proposer {} is not eligible for the slot, elected {}",
+                    height, proposer, expected
+                )
+            }
+            BlockValidationError::InvalidTransaction { index, error } => {
+                write!(f, "transaction {} is invalid: {}", index, error)
+            }
+            BlockValidationError::Corrupted { key, reason } => {
+                write!(f, "stored record {} is corrupted: {}", key, reason)
+            }
+            BlockValidationError::StateMismatch => {
+                write!(f, "persisted state does not match the stored chain tip")
+            }
+            BlockValidationError::Storage(reason) => write!(f, "storage error: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for BlockValidationError {}
+
+impl From<StorageError> for BlockValidationError {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::Corrupted { key, reason } => {
+                BlockValidationError::Corrupted { key, reason }
+            }
+            e => BlockValidationError::Storage(e.to_string()),
+        }
+    }
+}
+
+/// Checks that `block` is internally consistent: supported version, hash
+/// matching the header, a valid proposer signature, Merkle root matching the
+/// transactions, and every transaction correctly signed for the network whose
+/// genesis block hashes to `genesis_hash`.
+pub fn check_block_contents(
+    block: &Block,
+    genesis_hash: &Hash,
+) -> Result<(), BlockValidationError> {
+    let height = block.header.height;
+
+    if block.header.version != BLOCK_VERSION {
+        return Err(BlockValidationError::UnsupportedVersion(
+            block.header.version,
+        ));
+    }
+    if block.hash != block.header.hash() {
+        return Err(BlockValidationError::HashMismatch { height });
+    }
+    if !block.verify_signature() {
+        return Err(BlockValidationError::InvalidBlockSignature { height });
+    }
+    if block.header.merkle_root != transactions_root(&block.transactions)
+        || block.header.tx_count as usize != block.transactions.len()
+    {
+        return Err(BlockValidationError::MerkleRootMismatch { height });
+    }
+    for (index, tx) in block.transactions.iter().enumerate() {
+        tx.verify(genesis_hash)
+            .map_err(|error| BlockValidationError::InvalidTransaction { index, error })?;
+    }
+    Ok(())
+}
+
+/// Checks that `block` extends `parent`: consecutive height, matching
+/// `previous_hash`, and a timestamp no older than the parent and not too far
+/// past `now_ms`.
+pub fn check_block_link(
+    parent: &Block,
+    block: &Block,
+    now_ms: u128,
+) -> Result<(), BlockValidationError> {
+    let height = block.header.height;
+
+    if height != parent.header.height + 1 {
+        return Err(BlockValidationError::NonSequentialHeight {
+            expected: parent.header.height + 1,
+            found: height,
+        });
+    }
+    if block.header.previous_hash != parent.hash {
+        return Err(BlockValidationError::PreviousHashMismatch { height });
+    }
+    if block.header.timestamp < parent.header.timestamp {
+        return Err(BlockValidationError::TimestampBeforeParent { height });
+    }
+    if block.header.timestamp > now_ms + MAX_FUTURE_DRIFT_MS {
+        return Err(BlockValidationError::TimestampInFuture { height });
+    }
+    Ok(())
+}
+
+pub fn now_ms() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis()
+}
@@ -0,0 +1,698 @@
+use crate::blockchain::state::StakingState;
+use crate::consensus::pos;
+use crate::cryptography::sha3::{hex_hash, hex_hash_option, Hash};
+use crate::cryptography::sphincs::Sphincs;
+use crate::wallet::key_manager::NodeKey;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+/// Domain tag prefixed to signed votes so a vote signature can never be
+/// replayed as a block or transaction signature.
+const VOTE_TAG: &[u8] = b"quantumcoin/vote";
+
+/// Round timeouts, fixed at genesis. Each grows by `timeout_delta_ms` per
+/// round, so a slow network eventually gets rounds long enough to agree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BftParams {
+    /// How long to wait for the round's proposal before prevoting nil.
+    pub timeout_propose_ms: u64,
+    /// How long to wait for a prevote quorum on one value once prevotes from
+    /// two thirds of the stake have arrived.
+    pub timeout_prevote_ms: u64,
+    /// How long to wait for a precommit quorum on one value once precommits
+    /// from two thirds of the stake have arrived.
+    pub timeout_precommit_ms: u64,
+    pub timeout_delta_ms: u64,
+}
+
+impl Default for BftParams {
+    fn default() -> Self {
+        Self {
+            timeout_propose_ms: 3_000,
+            timeout_prevote_ms: 1_000,
+            timeout_precommit_ms: 1_000,
+            timeout_delta_ms: 500,
+        }
+    }
+}
+
+impl BftParams {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.timeout_propose_ms.to_be_bytes());
+        out.extend_from_slice(&self.timeout_prevote_ms.to_be_bytes());
+        out.extend_from_slice(&self.timeout_precommit_ms.to_be_bytes());
+        out.extend_from_slice(&self.timeout_delta_ms.to_be_bytes());
+    }
+
+    /// Timeout for `step` in `round`, in milliseconds.
+    pub fn timeout(&self, step: Step, round: u32) -> u64 {
+        let base = match step {
+            Step::Propose => self.timeout_propose_ms,
+            Step::Prevote => self.timeout_prevote_ms,
+            Step::Precommit | Step::Commit => self.timeout_precommit_ms,
+        };
+        base.saturating_add(self.timeout_delta_ms.saturating_mul(round as u64))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BftError {
+    /// No canonical block exists at the certified height.
+    UnknownBlock {
+        height: u64,
+    },
+    /// The certified block is not the canonical block at its height.
+    NotCanonical {
+        height: u64,
+    },
+    AlreadyFinalized {
+        height: u64,
+    },
+    WrongHeight {
+        expected: u64,
+        found: u64,
+    },
+    /// The signer is not in the active validator set.
+    UnknownValidator(String),
+    InvalidSignature(String),
+    /// The validator signed twice, in a certificate or for different values
+    /// in one round.
+    DuplicateVote(String),
+    /// The signers hold too little of the active stake.
+    InsufficientQuorum {
+        power: u64,
+        total: u64,
+    },
+    Storage(String),
+}
+
+impl fmt::Display for BftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BftError::UnknownBlock { height } => write!(f, "no block at height {}", height),
+            BftError::NotCanonical { height } => {
+                write!(f, "certified block is not canonical at height {}", height)
+            }
+            BftError::AlreadyFinalized { height } => {
+                write!(f, "height {} is already finalized", height)
+            }
+            BftError::WrongHeight { expected, found } => {
+                write!(f, "expected a vote at height {}, found {}", expected, found)
+            }
+            BftError::UnknownValidator(key) => write!(f, "{} is not an active validator", key),
+            BftError::InvalidSignature(key) => write!(f, "vote signature from {} is invalid", key),
+            BftError::DuplicateVote(key) => write!(f, "{} voted more than once", key),
+            BftError::InsufficientQuorum { power, total } => {
+                write!(
+                    f,
+                    "votes hold {} of {} stake, need over two thirds",
+                    power, total
+                )
+            }
+            BftError::Storage(reason) => write!(f, "storage error: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for BftError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VoteType {
+    Prevote,
+    Precommit,
+}
+
+impl VoteType {
+    fn tag(self) -> u8 {
+        match self {
+            VoteType::Prevote => 0,
+            VoteType::Precommit => 1,
+        }
+    }
+}
+
+/// A validator's signed vote in one round.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vote {
+    pub vote_type: VoteType,
+    pub height: u64,
+    pub round: u32,
+    /// The block voted for, or `None` for a nil vote.
+    #[serde(with = "hex_hash_option")]
+    pub block_hash: Option<Hash>,
+    /// Consensus key of the voter.
+    pub validator: String,
+    pub signature: Vec<u8>,
+}
+
+impl Vote {
+    pub fn new(
+        vote_type: VoteType,
+        height: u64,
+        round: u32,
+        block_hash: Option<Hash>,
+        signer: &NodeKey,
+    ) -> Self {
+        let message = vote_signing_bytes(vote_type, height, round, block_hash.as_ref());
+        Vote {
+            vote_type,
+            height,
+            round,
+            block_hash,
+            validator: signer.id(),
+            signature: signer.sign(&message).expect("Failed to sign vote"),
+        }
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        let message = vote_signing_bytes(
+            self.vote_type,
+            self.height,
+            self.round,
+            self.block_hash.as_ref(),
+        );
+        verify_key_signature(&self.validator, &message, &self.signature)
+    }
+}
+
+/// Bytes a vote signature covers: `tag | type (1) | height (8) | round (4) |
+/// 0` for nil, or `1 | block_hash (32)`.
+fn vote_signing_bytes(
+    vote_type: VoteType,
+    height: u64,
+    round: u32,
+    block_hash: Option<&Hash>,
+) -> Vec<u8> {
+    let mut out = Vec::with_capacity(VOTE_TAG.len() + 46);
+    out.extend_from_slice(VOTE_TAG);
+    out.push(vote_type.tag());
+    out.extend_from_slice(&height.to_be_bytes());
+    out.extend_from_slice(&round.to_be_bytes());
+    match block_hash {
+        Some(hash) => {
+            out.push(1);
+            out.extend_from_slice(hash);
+        }
+        None => out.push(0),
+    }
+    out
+}
+
+fn verify_key_signature(key: &str, message: &[u8], signature: &[u8]) -> bool {
+    match hex::decode(key) {
+        Ok(public_key) => Sphincs::verify_with_key_bytes(message, signature, &public_key),
+        Err(_) => false,
+    }
+}
+
+/// One validator's precommit signature inside a quorum certificate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitSignature {
+    pub validator: String,
+    pub signature: Vec<u8>,
+}
+
+/// Precommits for one block from more than two thirds of the active stake,
+/// proving the block final. SPHINCS+ signatures cannot be combined, so the
+/// certificate aggregates them as a list, one per validator in key order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuorumCertificate {
+    pub height: u64,
+    pub round: u32,
+    #[serde(with = "hex_hash")]
+    pub block_hash: Hash,
+    pub signatures: Vec<CommitSignature>,
+}
+
+impl QuorumCertificate {
+    /// Checks that every signature is a valid precommit from a distinct
+    /// member of `validators` and that together they hold a quorum.
+    pub fn verify(&self, validators: &ValidatorSet) -> Result<(), BftError> {
+        let message = vote_signing_bytes(
+            VoteType::Precommit,
+            self.height,
+            self.round,
+            Some(&self.block_hash),
+        );
+        let mut signers = BTreeSet::new();
+        let mut power = 0u64;
+        for commit in &self.signatures {
+            let weight = validators
+                .power(&commit.validator)
+                .ok_or_else(|| BftError::UnknownValidator(commit.validator.clone()))?;
+            if !signers.insert(commit.validator.as_str()) {
+                return Err(BftError::DuplicateVote(commit.validator.clone()));
+            }
+            if !verify_key_signature(&commit.validator, &message, &commit.signature) {
+                return Err(BftError::InvalidSignature(commit.validator.clone()));
+            }
+            power = power.saturating_add(weight);
+        }
+        if !validators.is_quorum(power) {
+            return Err(BftError::InsufficientQuorum {
+                power,
+                total: validators.total(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Voting power of the active set, keyed by consensus key.
+#[derive(Debug, Clone, Default)]
+pub struct ValidatorSet {
+    powers: HashMap<String, u64>,
+    total: u64,
+}
+
+impl ValidatorSet {
+    /// The active validators in `staking_state` that hold voting power.
+    pub fn from_state(staking_state: &StakingState) -> Self {
+        let mut set = ValidatorSet::default();
+        for operator in &staking_state.validators {
+            let power = staking_state.voting_power(operator);
+            if let (Some(info), true) = (staking_state.registry.get(operator), power > 0) {
+                set.powers.insert(info.consensus_key.clone(), power);
+                set.total = set.total.saturating_add(power);
+            }
+        }
+        set
+    }
+
+    pub fn power(&self, consensus_key: &str) -> Option<u64> {
+        self.powers.get(consensus_key).cloned()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Whether `power` is more than two thirds of the total.
+    pub fn is_quorum(&self, power: u64) -> bool {
+        power as u128 * 3 > self.total as u128 * 2
+    }
+
+    /// Whether `power` is more than a third of the total, so at least one
+    /// honest validator is among its holders.
+    pub fn exceeds_one_third(&self, power: u64) -> bool {
+        power as u128 * 3 > self.total as u128
+    }
+}
+
+/// Step within a round, in the order a round moves through them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    Propose,
+    Prevote,
+    Precommit,
+    /// A block has been committed; the height is done.
+    Commit,
+}
+
+/// What the driver of a `RoundState` has to do next.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// This node proposes in `round`. It re-proposes `valid_block`, with
+    /// the round it became valid in, if it has one, and a new block otherwise.
+    Propose {
+        round: u32,
+        valid_block: Option<(Hash, u32)>,
+    },
+    /// Send this node's vote to every validator.
+    Broadcast(Vote),
+    /// Call `on_timeout(step, height, round)` after `after_ms`.
+    ScheduleTimeout {
+        step: Step,
+        round: u32,
+        after_ms: u64,
+    },
+    /// The block reached a precommit quorum and is final.
+    Commit(QuorumCertificate),
+}
+
+/// A proposal received for a round.
+#[derive(Debug, Clone, Copy)]
+struct Proposal {
+    block_hash: Hash,
+    valid_round: Option<u32>,
+    valid: bool,
+}
+
+/// Tendermint voting for a single height.
+///
+/// The state machine does no I/O. Its driver feeds it proposals, votes and
+/// expired timeouts, and carries out the returned actions: broadcasting
+/// votes, scheduling timeouts, building proposals and finalizing the
+/// committed block. Each round moves from propose to prevote to precommit. A
+/// validator that precommits a block locks on it and only prevotes another
+/// block once it sees a newer prevote quorum for it, so two blocks can never
+/// both gather precommits from more than two thirds of the stake.
+pub struct RoundState {
+    pub height: u64,
+    pub round: u32,
+    pub step: Step,
+    params: BftParams,
+    validators: ValidatorSet,
+    proposers: Vec<String>, // Consensus keys in round order
+    signer: Option<Arc<NodeKey>>,
+    locked: Option<(u32, Hash)>,
+    valid: Option<(u32, Hash)>,
+    proposals: HashMap<u32, Proposal>,
+    votes: HashMap<(u32, VoteType), BTreeMap<String, Vote>>, // Round and type -> Voter -> Vote
+    scheduled: HashSet<(Step, u32)>,                         // Vote timeouts already scheduled
+    polkas: HashSet<u32>, // Rounds whose prevote quorum was acted on
+}
+
+impl RoundState {
+    /// Voting for the block at `height` on top of `parent_hash`, among the
+    /// active set of `staking_state`. The proposer of round `r` is the
+    /// validator ranked `r` for the slot, wrapping around the ranking. Votes
+    /// are signed with `signer` if it belongs to the set; otherwise the node
+    /// only follows the rounds.
+    pub fn new(
+        staking_state: &StakingState,
+        parent_hash: &Hash,
+        height: u64,
+        params: BftParams,
+        signer: Option<Arc<NodeKey>>,
+    ) -> Self {
+        let proposers = pos::proposer_ranking(staking_state, parent_hash, height)
+            .into_iter()
+            .filter_map(|operator| staking_state.registry.get(operator))
+            .map(|info| info.consensus_key.clone())
+            .collect();
+        RoundState {
+            height,
+            round: 0,
+            step: Step::Propose,
+            params,
+            validators: ValidatorSet::from_state(staking_state),
+            proposers,
+            signer,
+            locked: None,
+            valid: None,
+            proposals: HashMap::new(),
+            votes: HashMap::new(),
+            scheduled: HashSet::new(),
+            polkas: HashSet::new(),
+        }
+    }
+
+    /// Starts round 0.
+    pub fn start(&mut self) -> Vec<Action> {
+        let mut actions = self.start_round(0);
+        actions.extend(self.process());
+        actions
+    }
+
+    /// Consensus key of the proposer for `round`.
+    pub fn proposer(&self, round: u32) -> Option<&str> {
+        if self.proposers.is_empty() {
+            return None;
+        }
+        let index = round as usize % self.proposers.len();
+        Some(self.proposers[index].as_str())
+    }
+
+    /// Records the proposal for `round`. The driver checks that it came from
+    /// `proposer(round)` and passes `valid` as whether the block would be
+    /// accepted on top of the parent. `valid_round` is the round the proposer
+    /// saw a prevote quorum for the block in, if it is re-proposing one.
+    pub fn on_proposal(
+        &mut self,
+        round: u32,
+        block_hash: Hash,
+        valid_round: Option<u32>,
+        valid: bool,
+    ) -> Vec<Action> {
+        self.proposals.entry(round).or_insert(Proposal {
+            block_hash,
+            valid_round,
+            valid,
+        });
+        self.process()
+    }
+
+    /// Records a vote from another validator. Votes for other heights,
+    /// from outside the validator set or with bad signatures are rejected, as
+    /// is a second, different vote from a validator in the same round and
+    /// step.
+    pub fn on_vote(&mut self, vote: Vote) -> Result<Vec<Action>, BftError> {
+        if vote.height != self.height {
+            return Err(BftError::WrongHeight {
+                expected: self.height,
+                found: vote.height,
+            });
+        }
+        if self.validators.power(&vote.validator).is_none() {
+            return Err(BftError::UnknownValidator(vote.validator));
+        }
+        if !vote.verify_signature() {
+            return Err(BftError::InvalidSignature(vote.validator));
+        }
+        let votes = self.votes.entry((vote.round, vote.vote_type)).or_default();
+        match votes.get(&vote.validator) {
+            Some(existing) if existing.block_hash != vote.block_hash => {
+                return Err(BftError::DuplicateVote(vote.validator));
+            }
+            Some(_) => return Ok(Vec::new()),
+            None => {
+                votes.insert(vote.validator.clone(), vote);
+            }
+        }
+        Ok(self.process())
+    }
+
+    /// Handles an expired timeout scheduled by an earlier action. Timeouts
+    /// for a round or step that has already passed are ignored.
+    pub fn on_timeout(&mut self, step: Step, height: u64, round: u32) -> Vec<Action> {
+        if height != self.height || round != self.round || self.step == Step::Commit {
+            return Vec::new();
+        }
+        let mut actions = match (step, self.step) {
+            (Step::Propose, Step::Propose) => self.cast(VoteType::Prevote, None),
+            (Step::Prevote, Step::Prevote) => self.cast(VoteType::Precommit, None),
+            (Step::Precommit, _) => self.start_round(round + 1),
+            _ => return Vec::new(),
+        };
+        actions.extend(self.process());
+        actions
+    }
+
+    fn start_round(&mut self, round: u32) -> Vec<Action> {
+        self.round = round;
+        self.step = Step::Propose;
+        let mut actions = Vec::new();
+        let own_key = self.signer.as_ref().map(|key| key.id());
+        if own_key.is_some() && self.proposer(round) == own_key.as_deref() {
+            actions.push(Action::Propose {
+                round,
+                valid_block: self.valid.map(|(round, hash)| (hash, round)),
+            });
+        }
+        actions.push(Action::ScheduleTimeout {
+            step: Step::Propose,
+            round,
+            after_ms: self.params.timeout(Step::Propose, round),
+        });
+        actions
+    }
+
+    /// Applies rules until none fires, since acting on one (casting a vote)
+    /// can enable the next.
+    fn process(&mut self) -> Vec<Action> {
+        let mut actions = Vec::new();
+        while self.step != Step::Commit {
+            let fired = self.apply_rule();
+            if fired.is_empty() {
+                break;
+            }
+            actions.extend(fired);
+        }
+        actions
+    }
+
+    /// Fires the first rule whose condition holds and returns its actions,
+    /// or nothing if no rule applies.
+    fn apply_rule(&mut self) -> Vec<Action> {
+        let round = self.round;
+
+        // A precommit quorum for a block in any round commits it.
+        let committed = self
+            .votes
+            .iter()
+            .filter(|((_, vote_type), _)| *vote_type == VoteType::Precommit)
+            .find_map(|((round, _), votes)| {
+                let hash = self.quorum_value(votes)?;
+                hash.map(|hash| (*round, hash))
+            });
+        if let Some((round, hash)) = committed {
+            self.step = Step::Commit;
+            return vec![Action::Commit(self.certificate(round, hash))];
+        }
+
+        // More than a third of the stake is already in a later round.
+        let later = self
+            .votes
+            .keys()
+            .map(|(round, _)| *round)
+            .filter(|r| *r > round)
+            .collect::<BTreeSet<u32>>()
+            .into_iter()
+            .find(|r| self.validators.exceeds_one_third(self.round_power(*r)));
+        if let Some(later) = later {
+            return self.start_round(later);
+        }
+
+        let proposal = self.proposals.get(&round).cloned();
+        if let (Step::Propose, Some(proposal)) = (self.step, proposal) {
+            let locked_on = |hash: Hash| self.locked.is_none_or(|(_, locked)| locked == hash);
+            let prevote = match proposal.valid_round {
+                None => Some(proposal.valid && locked_on(proposal.block_hash)),
+                // A re-proposal waits for the prevote quorum it refers to.
+                Some(valid_round) if valid_round < round => self
+                    .has_polka(valid_round, Some(proposal.block_hash))
+                    .then(|| {
+                        proposal.valid
+                            && self.locked.is_none_or(|(locked_round, hash)| {
+                                locked_round <= valid_round || hash == proposal.block_hash
+                            })
+                    }),
+                Some(_) => Some(false),
+            };
+            if let Some(accept) = prevote {
+                let value = accept.then_some(proposal.block_hash);
+                return self.cast(VoteType::Prevote, value);
+            }
+        }
+
+        if self.step >= Step::Prevote && !self.polkas.contains(&round) {
+            if let Some(proposal) = proposal.filter(|p| p.valid) {
+                if self.has_polka(round, Some(proposal.block_hash)) {
+                    self.polkas.insert(round);
+                    self.valid = Some((round, proposal.block_hash));
+                    if self.step == Step::Prevote {
+                        self.locked = Some((round, proposal.block_hash));
+                        return self.cast(VoteType::Precommit, Some(proposal.block_hash));
+                    }
+                    return Vec::new();
+                }
+            }
+        }
+
+        if self.step == Step::Prevote && self.has_polka(round, None) {
+            return self.cast(VoteType::Precommit, None);
+        }
+
+        for (step, vote_type) in [
+            (Step::Prevote, VoteType::Prevote),
+            (Step::Precommit, VoteType::Precommit),
+        ] {
+            let waiting = match step {
+                Step::Prevote => self.step == Step::Prevote,
+                _ => true,
+            };
+            if waiting
+                && !self.scheduled.contains(&(step, round))
+                && self
+                    .validators
+                    .is_quorum(self.voted_power(round, vote_type))
+            {
+                self.scheduled.insert((step, round));
+                return vec![Action::ScheduleTimeout {
+                    step,
+                    round,
+                    after_ms: self.params.timeout(step, round),
+                }];
+            }
+        }
+        Vec::new()
+    }
+
+    /// Signs and records this node's vote, then moves to the step after it.
+    fn cast(&mut self, vote_type: VoteType, block_hash: Option<Hash>) -> Vec<Action> {
+        self.step = match vote_type {
+            VoteType::Prevote => Step::Prevote,
+            VoteType::Precommit => Step::Precommit,
+        };
+        let signer = match &self.signer {
+            Some(signer) if self.validators.power(&signer.id()).is_some() => signer,
+            _ => return Vec::new(),
+        };
+        let vote = Vote::new(vote_type, self.height, self.round, block_hash, signer);
+        self.votes
+            .entry((self.round, vote_type))
+            .or_default()
+            .insert(vote.validator.clone(), vote.clone());
+        vec![Action::Broadcast(vote)]
+    }
+
+    /// The value holding a quorum among `votes`: `Some(Some(hash))` for a
+    /// block, `Some(None)` for nil, or `None` if no value has one.
+    fn quorum_value(&self, votes: &BTreeMap<String, Vote>) -> Option<Option<Hash>> {
+        let mut tally: HashMap<Option<Hash>, u64> = HashMap::new();
+        for vote in votes.values() {
+            let power = self.validators.power(&vote.validator).unwrap_or(0);
+            *tally.entry(vote.block_hash).or_insert(0) += power;
+        }
+        tally
+            .into_iter()
+            .find(|(_, power)| self.validators.is_quorum(*power))
+            .map(|(value, _)| value)
+    }
+
+    fn has_polka(&self, round: u32, value: Option<Hash>) -> bool {
+        self.votes
+            .get(&(round, VoteType::Prevote))
+            .and_then(|votes| self.quorum_value(votes))
+            == Some(value)
+    }
+
+    /// Stake that cast a vote of `vote_type` in `round`, for any value.
+    fn voted_power(&self, round: u32, vote_type: VoteType) -> u64 {
+        self.votes.get(&(round, vote_type)).map_or(0, |votes| {
+            votes
+                .keys()
+                .filter_map(|key| self.validators.power(key))
+                .sum()
+        })
+    }
+
+    /// Stake that cast any vote in `round`.
+    fn round_power(&self, round: u32) -> u64 {
+        let voters: BTreeSet<&String> = [VoteType::Prevote, VoteType::Precommit]
+            .iter()
+            .filter_map(|vote_type| self.votes.get(&(round, *vote_type)))
+            .flat_map(|votes| votes.keys())
+            .collect();
+        voters
+            .into_iter()
+            .filter_map(|key| self.validators.power(key))
+            .sum()
+    }
+
+    fn certificate(&self, round: u32, block_hash: Hash) -> QuorumCertificate {
+        let signatures = self
+            .votes
+            .get(&(round, VoteType::Precommit))
+            .into_iter()
+            .flat_map(|votes| votes.values())
+            .filter(|vote| vote.block_hash == Some(block_hash))
+            .map(|vote| CommitSignature {
+                validator: vote.validator.clone(),
+                signature: vote.signature.clone(),
+            })
+            .collect();
+        QuorumCertificate {
+            height: self.height,
+            round,
+            block_hash,
+            signatures,
+        }
+    }
+}
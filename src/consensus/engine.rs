@@ -0,0 +1,329 @@
+use crate::blockchain::block::{Block, BlockHeader};
+use crate::blockchain::genesis::ConsensusParams;
+use crate::blockchain::state::{AccountState, StakingState};
+use crate::blockchain::validation::BlockValidationError;
+use crate::consensus::bft::{BftError, QuorumCertificate, ValidatorSet};
+use crate::consensus::pos;
+use crate::consensus::pouw::TaskQueue;
+use crate::cryptography::sha3::Hash;
+use std::fmt::Debug;
+use std::str::FromStr;
+
+/// Consensus engine a node runs, chosen by node configuration rather than
+/// genesis. Every node of a network must run the same one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EngineKind {
+    #[default]
+    ProofOfStake,
+    ProofOfUsefulWork,
+    Dev,
+}
+
+impl EngineKind {
+    /// Builds the engine. `authority` is the only key the dev engine accepts
+    /// blocks from; the other engines ignore it.
+    pub fn build(self, authority: &str) -> Box<dyn Consensus> {
+        match self {
+            EngineKind::ProofOfStake => Box::new(ProofOfStake),
+            EngineKind::ProofOfUsefulWork => Box::new(ProofOfUsefulWork),
+            EngineKind::Dev => Box::new(DevAuthority {
+                authority: authority.to_string(),
+            }),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EngineKind::ProofOfStake => "pos",
+            EngineKind::ProofOfUsefulWork => "pouw",
+            EngineKind::Dev => "dev",
+        }
+    }
+}
+
+impl FromStr for EngineKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "pos" => Ok(EngineKind::ProofOfStake),
+            "pouw" => Ok(EngineKind::ProofOfUsefulWork),
+            "dev" => Ok(EngineKind::Dev),
+            other => Err(format!(
+                "unknown consensus engine {} (expected pos, pouw or dev)",
+                other
+            )),
+        }
+    }
+}
+
+/// Rules that differ between consensus engines: who may propose a block,
+/// what a block earns and how blocks become final. Everything else about a
+/// block, such as its link to the parent, its transactions and its state
+/// root, is checked the same way by `Blockchain` under every engine.
+pub trait Consensus: Debug + Send + Sync {
+    fn kind(&self) -> EngineKind;
+
+    /// Consensus key expected to propose the block at `height` on top of
+    /// `parent_hash`, or `None` if no one is.
+    fn select_proposer<'a>(
+        &'a self,
+        staking_state: &'a StakingState,
+        parent_hash: &Hash,
+        height: u64,
+    ) -> Option<&'a str>;
+
+    /// Checks that the proposer named in `header` may build on `parent`.
+    fn check_proposer(
+        &self,
+        staking_state: &StakingState,
+        params: &ConsensusParams,
+        parent: &Block,
+        header: &BlockHeader,
+    ) -> Result<(), BlockValidationError>;
+
+    /// Validators whose turn passed before `proposer_key` proposed the block
+    /// at `height`. Each is charged a missed slot.
+    fn skipped_validators(
+        &self,
+        _staking_state: &StakingState,
+        _parent_hash: &Hash,
+        _height: u64,
+        _proposer_key: &str,
+    ) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Runs once a block's tasks, transactions and penalties have been
+    /// applied, to pay whatever the engine pays for a block.
+    fn reward_block(
+        &self,
+        _accounts: &mut AccountState,
+        _staking_state: &mut StakingState,
+        _task_queue: &TaskQueue,
+        _params: &ConsensusParams,
+        _height: u64,
+    ) -> Result<(), BlockValidationError> {
+        Ok(())
+    }
+
+    /// Checks a certificate finalizing a block proposed to the active set of
+    /// `staking_state`.
+    fn verify_commit(
+        &self,
+        staking_state: &StakingState,
+        qc: &QuorumCertificate,
+    ) -> Result<(), BftError> {
+        qc.verify(&ValidatorSet::from_state(staking_state))
+    }
+
+    /// Whether a block is final as soon as it extends the tip, without a
+    /// quorum certificate.
+    fn finalizes_on_connect(&self) -> bool {
+        false
+    }
+}
+
+/// The active validator set takes turns proposing by stake (see
+/// `pos::proposer_ranking`), validators that miss their turn are charged for
+/// it, stakers earn epoch rewards and blocks are finalized by BFT voting.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProofOfStake;
+
+impl Consensus for ProofOfStake {
+    fn kind(&self) -> EngineKind {
+        EngineKind::ProofOfStake
+    }
+
+    fn select_proposer<'a>(
+        &'a self,
+        staking_state: &'a StakingState,
+        parent_hash: &Hash,
+        height: u64,
+    ) -> Option<&'a str> {
+        pos::select_proposer(staking_state, parent_hash, height)
+    }
+
+    fn check_proposer(
+        &self,
+        staking_state: &StakingState,
+        params: &ConsensusParams,
+        parent: &Block,
+        header: &BlockHeader,
+    ) -> Result<(), BlockValidationError> {
+        check_elected(staking_state, params, parent, header)
+    }
+
+    fn skipped_validators(
+        &self,
+        staking_state: &StakingState,
+        parent_hash: &Hash,
+        height: u64,
+        proposer_key: &str,
+    ) -> Vec<String> {
+        pos::skipped_validators(staking_state, parent_hash, height, proposer_key)
+    }
+
+    /// Every active validator and delegation accrues one block of rewards,
+    /// and if `height` closes an epoch the accrued rewards are minted to the
+    /// stakers' balances.
+    fn reward_block(
+        &self,
+        accounts: &mut AccountState,
+        staking_state: &mut StakingState,
+        _task_queue: &TaskQueue,
+        params: &ConsensusParams,
+        height: u64,
+    ) -> Result<(), BlockValidationError> {
+        staking_state.accrue_block();
+        if height.is_multiple_of(params.epoch_length) {
+            for (wallet, amount) in staking_state.close_epoch(params) {
+                accounts
+                    .mint(&wallet, amount)
+                    .map_err(|_| BlockValidationError::BalanceOverflow { height })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Blocks are proposed and finalized by the staked validator set exactly as
+/// under `ProofOfStake`, but stake earns nothing: the coins a block mints go
+/// to the workers whose results it verified instead.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProofOfUsefulWork;
+
+impl Consensus for ProofOfUsefulWork {
+    fn kind(&self) -> EngineKind {
+        EngineKind::ProofOfUsefulWork
+    }
+
+    fn select_proposer<'a>(
+        &'a self,
+        staking_state: &'a StakingState,
+        parent_hash: &Hash,
+        height: u64,
+    ) -> Option<&'a str> {
+        pos::select_proposer(staking_state, parent_hash, height)
+    }
+
+    fn check_proposer(
+        &self,
+        staking_state: &StakingState,
+        params: &ConsensusParams,
+        parent: &Block,
+        header: &BlockHeader,
+    ) -> Result<(), BlockValidationError> {
+        check_elected(staking_state, params, parent, header)
+    }
+
+    fn skipped_validators(
+        &self,
+        staking_state: &StakingState,
+        parent_hash: &Hash,
+        height: u64,
+        proposer_key: &str,
+    ) -> Vec<String> {
+        pos::skipped_validators(staking_state, parent_hash, height, proposer_key)
+    }
+
+    /// Mints the block's issuance (see `StakingState::block_issuance`) to
+    /// the registered workers whose results were verified at `height`, in
+    /// proportion to the task rewards they earned. A block that verifies
+    /// nothing mints nothing, and fractions of a coin are dropped.
+    fn reward_block(
+        &self,
+        accounts: &mut AccountState,
+        staking_state: &mut StakingState,
+        task_queue: &TaskQueue,
+        params: &ConsensusParams,
+        height: u64,
+    ) -> Result<(), BlockValidationError> {
+        let work = task_queue.verified_work(height);
+        let total_work: u128 = work.iter().map(|(_, share)| *share as u128).sum();
+        if total_work == 0 {
+            return Ok(());
+        }
+        let issuance = staking_state.block_issuance(params) as u128;
+        for (worker, share) in work {
+            let amount = (issuance * share as u128 / total_work) as u64;
+            if amount > 0 {
+                accounts
+                    .mint(&worker, amount)
+                    .map_err(|_| BlockValidationError::BalanceOverflow { height })?;
+                staking_state.total_rewards_paid =
+                    staking_state.total_rewards_paid.saturating_add(amount);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A single authority proposes every block, and each block is final as soon
+/// as it is applied. Meant for local development and deterministic tests,
+/// where no validator set is needed.
+#[derive(Debug, Clone)]
+pub struct DevAuthority {
+    pub authority: String,
+}
+
+impl Consensus for DevAuthority {
+    fn kind(&self) -> EngineKind {
+        EngineKind::Dev
+    }
+
+    fn select_proposer<'a>(
+        &'a self,
+        _staking_state: &'a StakingState,
+        _parent_hash: &Hash,
+        _height: u64,
+    ) -> Option<&'a str> {
+        Some(&self.authority)
+    }
+
+    fn check_proposer(
+        &self,
+        _staking_state: &StakingState,
+        _params: &ConsensusParams,
+        _parent: &Block,
+        header: &BlockHeader,
+    ) -> Result<(), BlockValidationError> {
+        if header.proposer != self.authority {
+            return Err(BlockValidationError::WrongProposer {
+                height: header.height,
+                expected: self.authority.clone(),
+                proposer: header.proposer.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    fn finalizes_on_connect(&self) -> bool {
+        true
+    }
+}
+
+/// The proposer must be in the active set and eligible for the slot under
+/// the stake-weighted ranking.
+fn check_elected(
+    staking_state: &StakingState,
+    params: &ConsensusParams,
+    parent: &Block,
+    header: &BlockHeader,
+) -> Result<(), BlockValidationError> {
+    if !staking_state.is_active_key(&header.proposer) {
+        return Err(BlockValidationError::UnknownProposer {
+            height: header.height,
+            proposer: header.proposer.clone(),
+        });
+    }
+    if !pos::is_eligible(staking_state, params, parent, header) {
+        let expected = pos::select_proposer(staking_state, &parent.hash, header.height);
+        return Err(BlockValidationError::WrongProposer {
+            height: header.height,
+            expected: expected.unwrap_or_default().to_string(),
+            proposer: header.proposer.clone(),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use crate::blockchain::chain::Blockchain;
+use crate::consensus::bft::{Action, BftError, QuorumCertificate, RoundState, Step, Vote};
+use crate::wallet::key_manager::NodeKey;
+use log::{error, warn};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How often the node fires expired round timeouts and looks for a new
+/// block to vote on.
+pub const FINALITY_TICK_MS: u64 = 100;
+
+/// A round timeout that has been scheduled but has not fired yet.
+#[derive(Debug, Clone, Copy)]
+struct Timeout {
+    at: Instant,
+    step: Step,
+    height: u64,
+    round: u32,
+}
+
+/// Drives a `RoundState` for the lowest block that is not final yet.
+///
+/// Blocks are built by the proposer loop, so a round's proposal is the
+/// canonical block at the height once the validator the round belongs to
+/// has proposed it. Votes from other validators come in through
+/// `on_vote`, this node's own votes are kept for them to fetch, round
+/// timeouts fire on `tick`, and a precommit quorum finalizes the block. The
+/// certificate is kept as well, for validators that missed the votes.
+pub struct Finality {
+    node_key: Arc<NodeKey>,
+    round: Option<RoundState>,
+    timeouts: Vec<Timeout>,
+    votes: Vec<Vote>, // Cast by this node at the current height
+    last_commit: Option<QuorumCertificate>,
+}
+
+impl Finality {
+    pub fn new(node_key: Arc<NodeKey>) -> Self {
+        Finality {
+            node_key,
+            round: None,
+            timeouts: Vec::new(),
+            votes: Vec::new(),
+            last_commit: None,
+        }
+    }
+
+    /// The round being voted on, if the next block to finalize is on the
+    /// chain.
+    pub fn round(&self) -> Option<&RoundState> {
+        self.round.as_ref()
+    }
+
+    /// This node's votes at the current height, oldest first.
+    pub fn votes(&self) -> &[Vote] {
+        &self.votes
+    }
+
+    /// Certificate of the last block this node's votes finalized.
+    pub fn last_commit(&self) -> Option<&QuorumCertificate> {
+        self.last_commit.as_ref()
+    }
+
+    /// Records a vote from another validator and carries out whatever it
+    /// triggers.
+    pub fn on_vote(&mut self, blockchain: &mut Blockchain, vote: Vote) -> Result<(), BftError> {
+        self.sync(blockchain);
+        let round = match self.round.as_mut() {
+            Some(round) => round,
+            None => {
+                return Err(BftError::WrongHeight {
+                    expected: blockchain.finalized_height + 1,
+                    found: vote.height,
+                })
+            }
+        };
+        let actions = round.on_vote(vote)?;
+        self.perform(blockchain, actions);
+        self.sync(blockchain);
+        Ok(())
+    }
+
+    /// Starts voting once the next block to finalize is on the chain, hands
+    /// it to the round it belongs to and fires the timeouts due by `now`.
+    pub fn tick(&mut self, blockchain: &mut Blockchain, now: Instant) {
+        self.sync(blockchain);
+        let (due, pending): (Vec<Timeout>, Vec<Timeout>) = std::mem::take(&mut self.timeouts)
+            .into_iter()
+            .partition(|timeout| timeout.at <= now);
+        self.timeouts = pending;
+        for timeout in due {
+            let Some(round) = self.round.as_mut() else {
+                break;
+            };
+            let actions = round.on_timeout(timeout.step, timeout.height, timeout.round);
+            self.perform(blockchain, actions);
+            self.sync(blockchain);
+        }
+    }
+
+    /// Moves to the height above the finalized one, and feeds the canonical
+    /// block there to the current round if its proposer is the round's.
+    fn sync(&mut self, blockchain: &mut Blockchain) {
+        if blockchain.consensus.finalizes_on_connect() {
+            return;
+        }
+        let height = blockchain.finalized_height + 1;
+        if self.round.as_ref().map(|round| round.height) != Some(height) {
+            self.round = None;
+            self.timeouts.clear();
+            self.votes.clear();
+            let parent = match blockchain.get_block(height - 1) {
+                Some(parent) if blockchain.get_block(height).is_some() => parent.hash,
+                _ => return,
+            };
+            let mut round = RoundState::new(
+                blockchain.staking_state_before(height),
+                &parent,
+                height,
+                blockchain.genesis.consensus.bft.clone(),
+                Some(self.node_key.clone()),
+            );
+            let actions = round.start();
+            self.round = Some(round);
+            self.perform(blockchain, actions);
+        }
+
+        let (Some(round), Some(block)) = (self.round.as_mut(), blockchain.get_block(height)) else {
+            return;
+        };
+        if round.step == Step::Propose
+            && round.proposer(round.round) == Some(block.header.proposer.as_str())
+        {
+            let actions = round.on_proposal(round.round, block.hash, None, true);
+            self.perform(blockchain, actions);
+        }
+    }
+
+    fn perform(&mut self, blockchain: &mut Blockchain, actions: Vec<Action>) {
+        for action in actions {
+            match action {
+                // The proposer loop builds blocks; `sync` feeds them in.
+                Action::Propose { .. } => {}
+                Action::Broadcast(vote) => self.votes.push(vote),
+                Action::ScheduleTimeout {
+                    step,
+                    round,
+                    after_ms,
+                } => self.timeouts.push(Timeout {
+                    at: Instant::now() + Duration::from_millis(after_ms),
+                    step,
+                    height: blockchain.finalized_height + 1,
+                    round,
+                }),
+                Action::Commit(qc) => match blockchain.finalize(qc.clone()) {
+                    Ok(()) => self.last_commit = Some(qc),
+                    Err(e) => warn!("Failed to finalize block {}: {}", qc.height, e),
+                },
+            }
+        }
+    }
+}
+
+/// Ticks `finality` every `interval` until the node stops. Each tick runs on
+/// a blocking thread, and a panicked one is logged and retried on the next.
+pub async fn run(
+    finality: Arc<Mutex<Finality>>,
+    blockchain: Arc<Mutex<Blockchain>>,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let ticked = tokio::task::spawn_blocking({
+            let finality = finality.clone();
+            let blockchain = blockchain.clone();
+            move || {
+                let mut finality = finality.lock().unwrap_or_else(PoisonError::into_inner);
+                let mut blockchain = blockchain.lock().unwrap_or_else(PoisonError::into_inner);
+                finality.tick(&mut blockchain, Instant::now());
+            }
+        });
+        if let Err(e) = ticked.await {
+            error!("Finality tick panicked: {}", e);
+        }
+    }
+}
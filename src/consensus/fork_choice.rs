@@ -0,0 +1,68 @@
+use crate::blockchain::block::Block;
+use crate::blockchain::state::StakingState;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+/// Fork-choice rule selected in the genesis consensus parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForkChoiceRule {
+    #[default]
+    HeaviestStake,
+    LongestChain,
+}
+
+impl ForkChoiceRule {
+    pub fn build(self) -> Box<dyn ForkChoice> {
+        match self {
+            ForkChoiceRule::HeaviestStake => Box::new(HeaviestStake),
+            ForkChoiceRule::LongestChain => Box::new(LongestChain),
+        }
+    }
+
+    /// Single-byte tag used in the canonical genesis encoding.
+    pub fn tag(self) -> u8 {
+        match self {
+            ForkChoiceRule::HeaviestStake => 0,
+            ForkChoiceRule::LongestChain => 1,
+        }
+    }
+}
+
+/// Decides which of two competing branches is canonical. A branch's score is
+/// the sum of `block_weight` over its blocks above the common ancestor; the
+/// branch with the strictly higher score wins, so ties keep the current chain.
+pub trait ForkChoice: Debug + Send + Sync {
+    fn block_weight(&self, block: &Block, staking_state: &StakingState) -> u128;
+
+    fn branch_weight(&self, blocks: &[Block], staking_state: &StakingState) -> u128 {
+        blocks
+            .iter()
+            .map(|block| self.block_weight(block, staking_state))
+            .sum()
+    }
+}
+
+/// Every block counts the same, so the longest branch wins.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LongestChain;
+
+impl ForkChoice for LongestChain {
+    fn block_weight(&self, _block: &Block, _staking_state: &StakingState) -> u128 {
+        1
+    }
+}
+
+/// Each block weighs as much as its proposer's current voting power (own
+/// stake plus delegations), so the branch backed by the most stake wins even
+/// if it is shorter. Blocks signed by unregistered keys weigh nothing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeaviestStake;
+
+impl ForkChoice for HeaviestStake {
+    fn block_weight(&self, block: &Block, staking_state: &StakingState) -> u128 {
+        staking_state
+            .operator_of(&block.header.proposer)
+            .map_or(0, |operator| staking_state.voting_power(operator)) as u128
+    }
+}
@@ -1,2 +1,9 @@
+pub mod bft;
+pub mod engine;
+pub mod finality;
+pub mod fork_choice;
 pub mod pouw;
-pub mod pos;
\ No newline at end of file
+pub mod pos;
+pub mod proposer;
+pub mod slashing;
+pub mod wasm;
\ No newline at end of file
@@ -0,0 +1,114 @@
+use crate::blockchain::block::{Block, BlockHeader};
+use crate::blockchain::genesis::ConsensusParams;
+use crate::blockchain::state::StakingState;
+use crate::cryptography::sha3::{sha3_256_concat, Hash};
+
+/// Domain tag mixed into the slot seed so it cannot collide with other
+/// hashes taken over the same bytes.
+const SLOT_SEED_TAG: &[u8] = b"quantumcoin/slot";
+
+/// Randomness for the slot at `height`, derived from the hash of the block
+/// it builds on. Every node holding that block derives the same seed.
+pub fn slot_seed(parent_hash: &Hash, height: u64) -> Hash {
+    sha3_256_concat(&[SLOT_SEED_TAG, parent_hash, &height.to_be_bytes()])
+}
+
+/// Operators of the active set in the order they may propose the block at
+/// `height` on top of `parent_hash`.
+///
+/// Each rank is drawn from the validators not yet ranked, with probability
+/// proportional to their voting power: the seed for the rank is reduced to a
+/// point below their total power, and the remaining validators are walked in
+/// active-set order until the running total passes that point. Validators
+/// without voting power are never ranked.
+pub fn proposer_ranking<'a>(
+    staking_state: &'a StakingState,
+    parent_hash: &Hash,
+    height: u64,
+) -> Vec<&'a str> {
+    let seed = slot_seed(parent_hash, height);
+    let mut remaining: Vec<(&str, u128)> = staking_state
+        .validators
+        .iter()
+        .map(|operator| {
+            let power = staking_state.voting_power(operator) as u128;
+            (operator.as_str(), power)
+        })
+        .filter(|(_, power)| *power > 0)
+        .collect();
+
+    let mut ranking = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        let total: u128 = remaining.iter().map(|(_, power)| power).sum();
+        let draw = sha3_256_concat(&[&seed, &(ranking.len() as u64).to_be_bytes()]);
+        let mut point = u128::from_be_bytes(draw[..16].try_into().expect("16-byte prefix")) % total;
+        let index = remaining
+            .iter()
+            .position(|(_, power)| {
+                if point < *power {
+                    return true;
+                }
+                point -= power;
+                false
+            })
+            .expect("point is below the total power");
+        ranking.push(remaining.remove(index).0);
+    }
+    ranking
+}
+
+/// Consensus key of the validator elected to propose the block at `height`
+/// on top of `parent_hash`, or `None` if the active set has no voting power.
+pub fn select_proposer<'a>(
+    staking_state: &'a StakingState,
+    parent_hash: &Hash,
+    height: u64,
+) -> Option<&'a str> {
+    let operator = *proposer_ranking(staking_state, parent_hash, height).first()?;
+    staking_state
+        .registry
+        .get(operator)
+        .map(|info| info.consensus_key.as_str())
+}
+
+/// Whether the proposer named in `header` may propose on top of `parent`.
+/// The elected validator may propose straight away; the validator at rank
+/// `r` may take over once the block's timestamp is at least
+/// `r * proposer_timeout_ms` past the parent's.
+pub fn is_eligible(
+    staking_state: &StakingState,
+    params: &ConsensusParams,
+    parent: &Block,
+    header: &BlockHeader,
+) -> bool {
+    let operator = match staking_state.operator_of(&header.proposer) {
+        Some(operator) => operator,
+        None => return false,
+    };
+    let rank = proposer_ranking(staking_state, &parent.hash, header.height)
+        .iter()
+        .position(|ranked| *ranked == operator);
+    rank.is_some_and(|rank| {
+        let wait = rank as u128 * params.proposer_timeout_ms as u128;
+        header.timestamp >= parent.header.timestamp.saturating_add(wait)
+    })
+}
+
+/// Validators ranked ahead of the one signing with `proposer_key`, which
+/// missed their turn when it proposed the block at `height`. Empty if the
+/// proposer is not ranked.
+pub fn skipped_validators(
+    staking_state: &StakingState,
+    parent_hash: &Hash,
+    height: u64,
+    proposer_key: &str,
+) -> Vec<String> {
+    let ranking = proposer_ranking(staking_state, parent_hash, height);
+    let rank = staking_state
+        .operator_of(proposer_key)
+        .and_then(|operator| ranking.iter().position(|ranked| *ranked == operator));
+    match rank {
+        Some(rank) => ranking[..rank].iter().map(|v| v.to_string()).collect(),
+        None => Vec::new(),
+    }
+}
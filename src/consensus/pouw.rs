@@ -1,66 +1,1553 @@
+use crate::blockchain::block::encode_bytes;
+use crate::consensus::wasm::{self, WasmError, MAX_TASK_FUEL};
+use crate::cryptography::sha3::{hex_hash, hex_hash_option, sha3_256, Hash};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Rejected submissions kept per task. Older ones are dropped first.
+pub const MAX_REJECTIONS: usize = 64;
+
+/// Blocks a worker has to submit a result after claiming a task, unless the
+/// task sets its own. Replicated tasks give the same again for reveals.
+pub const DEFAULT_CLAIM_BLOCKS: u64 = 100;
+
+/// Highest reputation a worker can have, in basis points.
+pub const MAX_REPUTATION: u64 = 10_000;
+
+/// Capabilities a worker may advertise.
+pub const MAX_CAPABILITIES: usize = 32;
+
+/// Task outcomes kept in a worker's history. Older ones are dropped first.
+pub const MAX_WORKER_HISTORY: usize = 64;
+
+/// Where a task is in its lifecycle.
+///
+/// An `Open` task is claimed by workers until it has one per replica, which
+/// makes it `Claimed`; a claim that is not followed up by its deadline
+/// lapses and reopens the slot. A task with a single replica takes its
+/// worker's result directly. A replicated one first takes a commitment from
+/// every worker, which makes it `Committed`, and then their reveals. Once all
+/// results are in the task is `Submitted`, and the results are settled at
+/// the start of the next block: the task becomes `Verified` and the workers
+/// behind the accepted result are paid, or `Disputed`, which may be claimed
+/// again. A task that is not finished by its expiry height becomes
+/// `Expired`; the creator may cancel a task no worker is busy with, making it
+/// `Cancelled`. Either way its escrow is refunded to the creator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    #[default]
+    Open,
+    Claimed,
+    Submitted,
+    Verified,
+    Disputed,
+    Expired,
+    Cancelled,
+    Committed,
+}
+
+impl TaskStatus {
+    fn tag(self) -> u8 {
+        match self {
+            TaskStatus::Open => 0,
+            TaskStatus::Claimed => 1,
+            TaskStatus::Submitted => 2,
+            TaskStatus::Verified => 3,
+            TaskStatus::Disputed => 4,
+            TaskStatus::Expired => 5,
+            TaskStatus::Cancelled => 6,
+            TaskStatus::Committed => 7,
+        }
+    }
+
+    /// Whether the task is done with, one way or another.
+    pub fn is_final(self) -> bool {
+        matches!(
+            self,
+            TaskStatus::Verified | TaskStatus::Expired | TaskStatus::Cancelled
+        )
+    }
+
+    /// Whether a worker may claim the task.
+    pub fn is_claimable(self) -> bool {
+        matches!(self, TaskStatus::Open | TaskStatus::Disputed)
+    }
+}
+
+impl fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TaskStatus::Open => "open",
+            TaskStatus::Claimed => "claimed",
+            TaskStatus::Committed => "committed",
+            TaskStatus::Submitted => "submitted",
+            TaskStatus::Verified => "verified",
+            TaskStatus::Disputed => "disputed",
+            TaskStatus::Expired => "expired",
+            TaskStatus::Cancelled => "cancelled",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for TaskStatus {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "open" => Ok(TaskStatus::Open),
+            "claimed" => Ok(TaskStatus::Claimed),
+            "committed" => Ok(TaskStatus::Committed),
+            "submitted" => Ok(TaskStatus::Submitted),
+            "verified" => Ok(TaskStatus::Verified),
+            "disputed" => Ok(TaskStatus::Disputed),
+            "expired" => Ok(TaskStatus::Expired),
+            "cancelled" => Ok(TaskStatus::Cancelled),
+            other => Err(format!("unknown task status {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Task {
     pub id: String,
     pub description: String,
     pub data: Vec<u8>,
+    /// Paid to the workers behind the accepted result.
     pub reward: u64,
-    pub is_complete: bool,
+    /// Wallet that created the task and funded its escrow.
+    #[serde(default)]
+    pub creator: String,
+    /// Paid to the proposer of the block that accepts a result.
+    #[serde(default)]
+    pub verifier_fee: u64,
+    /// Coins locked for the reward and verifier fee, taken from the
+    /// creator's balance. Zero once they are paid out or refunded.
+    #[serde(default)]
+    pub escrow: u64,
+    #[serde(default)]
+    pub status: TaskStatus,
     pub sphincs_public_key: Vec<u8>,
     pub signature: Vec<u8>,
+    /// SHA3-256 of the result the creator expects, if it can be checked
+    /// against a known answer.
+    #[serde(default, with = "hex_hash_option")]
+    pub expected_result: Option<Hash>,
+    /// Registered task type whose program computes the result from `data`.
+    /// Results are checked by re-running it.
+    #[serde(default)]
+    pub task_type: Option<String>,
+    /// Reputation, in basis points, a worker needs to claim the task. Zero
+    /// lets any worker claim it, registered or not.
+    #[serde(default)]
+    pub min_reputation: u64,
+    /// Independent workers that must compute the task. A result is accepted
+    /// when more than half of them agree on it.
+    #[serde(default = "default_replicas")]
+    pub replicas: u32,
+    /// Blocks a claim lasts, and blocks given for reveals once every worker
+    /// has committed.
+    #[serde(default = "default_claim_blocks")]
+    pub claim_blocks: u64,
+    /// Workers holding a claim on the task, in the order they claimed it.
+    #[serde(default)]
+    pub assignments: Vec<Assignment>,
+    /// Last height at which committed workers may reveal their results.
+    #[serde(default)]
+    pub reveal_deadline: Option<u64>,
+    /// SHA3-256 of the accepted result.
+    #[serde(default, with = "hex_hash_option")]
+    pub result_hash: Option<Hash>,
+    /// Last height at which the task may be worked on, if it expires.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    #[serde(default)]
+    pub rejections: Vec<Rejection>,
 }
 
-#[derive(Debug, Clone)]
-pub struct TaskQueue {
-    pub tasks: HashMap<String, Task>,
+fn default_replicas() -> u32 {
+    1
 }
 
-impl TaskQueue {
-    pub fn new() -> Self {
-        Self {
-            tasks: HashMap::new(),
+fn default_claim_blocks() -> u64 {
+    DEFAULT_CLAIM_BLOCKS
+}
+
+/// One worker's claim on a task and what it has handed in for it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Assignment {
+    pub worker: String,
+    /// Last height at which the worker may submit, or commit to, its result.
+    pub claim_deadline: u64,
+    /// The worker's commitment to its result (see `result_commitment`).
+    #[serde(default, with = "hex_hash_option")]
+    pub commitment: Option<Hash>,
+    /// SHA3-256 of the worker's submitted or revealed result.
+    #[serde(default, with = "hex_hash_option")]
+    pub result_hash: Option<Hash>,
+    /// Height of the block that carried the result.
+    #[serde(default)]
+    pub submitted_height: Option<u64>,
+}
+
+/// Commitment a worker publishes before revealing `result` for a replicated
+/// task: SHA3-256 over the length-prefixed worker address, result and salt.
+/// Binding the worker's address means another worker cannot reuse it.
+pub fn result_commitment(worker: &str, result: &[u8], salt: &[u8]) -> Hash {
+    let mut out = Vec::new();
+    encode_bytes(&mut out, worker.as_bytes());
+    encode_bytes(&mut out, result);
+    encode_bytes(&mut out, salt);
+    sha3_256(&out)
+}
+
+/// A worker's claim on an open task, carried by a `TaskClaim` transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskClaim {
+    pub task_id: String,
+}
+
+impl TaskClaim {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        encode_bytes(out, self.task_id.as_bytes());
+    }
+}
+
+/// A new task, carried by a `TaskCreate` transaction whose `amount` is
+/// locked in escrow: the reward plus `verifier_fee`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskSpec {
+    pub id: String,
+    pub description: String,
+    pub data: Vec<u8>,
+    #[serde(default, with = "hex_hash_option")]
+    pub expected_result: Option<Hash>,
+    #[serde(default)]
+    pub task_type: Option<String>,
+    pub replicas: u32,
+    pub claim_blocks: u64,
+    /// Blocks after creation the task may be worked on, if it expires.
+    #[serde(default)]
+    pub ttl_blocks: Option<u64>,
+    #[serde(default)]
+    pub verifier_fee: u64,
+    #[serde(default)]
+    pub min_reputation: u64,
+}
+
+impl TaskSpec {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        encode_bytes(out, self.id.as_bytes());
+        encode_bytes(out, self.description.as_bytes());
+        encode_bytes(out, &self.data);
+        encode_hash_option(out, self.expected_result.as_ref());
+        encode_name_option(out, self.task_type.as_deref());
+        out.extend_from_slice(&self.replicas.to_be_bytes());
+        out.extend_from_slice(&self.claim_blocks.to_be_bytes());
+        encode_height_option(out, self.ttl_blocks);
+        out.extend_from_slice(&self.verifier_fee.to_be_bytes());
+        out.extend_from_slice(&self.min_reputation.to_be_bytes());
+    }
+
+    /// Checks the spec against the `escrow` the transaction locks.
+    pub fn verify(&self, escrow: u64) -> Result<(), TaskError> {
+        if self.id.is_empty() {
+            return Err(TaskError::EmptyId);
+        }
+        if self.replicas == 0 || self.claim_blocks == 0 || self.min_reputation > MAX_REPUTATION {
+            return Err(TaskError::InvalidSpec);
+        }
+        if self.replicas == 1 && self.task_type.is_none() && self.expected_result.is_none() {
+            return Err(TaskError::Unverifiable);
+        }
+        if escrow <= self.verifier_fee {
+            return Err(TaskError::InsufficientEscrow);
+        }
+        Ok(())
+    }
+}
+
+/// A deterministic WebAssembly program registered under `name`, computing
+/// the result of every task of its type from the task's data. Types are
+/// registered once and never change, so a task's result means the same
+/// thing for as long as the task exists.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskType {
+    pub name: String,
+    /// Wallet that registered the type.
+    pub owner: String,
+    /// The program, following the ABI described in `consensus::wasm`.
+    pub code: Vec<u8>,
+    /// SHA3-256 of `code`.
+    #[serde(with = "hex_hash")]
+    pub code_hash: Hash,
+    /// Fuel a single run of the program may use.
+    pub fuel_limit: u64,
+}
+
+impl TaskType {
+    /// Canonical binary encoding, used as the type's value in the state
+    /// tree. The program itself is committed to through its hash.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_bytes(&mut out, self.name.as_bytes());
+        encode_bytes(&mut out, self.owner.as_bytes());
+        out.extend_from_slice(&self.code_hash);
+        out.extend_from_slice(&self.fuel_limit.to_be_bytes());
+        out
+    }
+
+    /// Runs the program on `data` and returns the SHA3-256 of its output.
+    pub fn result_hash(&self, data: &[u8]) -> Result<Hash, WasmError> {
+        wasm::execute(&self.code, data, self.fuel_limit)
+            .map(|execution| sha3_256(&execution.output))
+    }
+}
+
+/// A new task type, carried by a `TaskTypeRegister` transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskTypeSpec {
+    pub name: String,
+    pub code: Vec<u8>,
+    pub fuel_limit: u64,
+}
+
+impl TaskTypeSpec {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        encode_bytes(out, self.name.as_bytes());
+        encode_bytes(out, &self.code);
+        out.extend_from_slice(&self.fuel_limit.to_be_bytes());
+    }
+
+    /// Checks the name and fuel limit, and that the code is a valid program.
+    pub fn verify(&self) -> Result<(), TaskError> {
+        if self.name.is_empty() {
+            return Err(TaskError::EmptyId);
+        }
+        if self.fuel_limit == 0 || self.fuel_limit > MAX_TASK_FUEL {
+            return Err(TaskError::InvalidFuelLimit);
         }
+        wasm::validate(&self.code).map_err(TaskError::InvalidProgram)
     }
+}
+
+/// A worker's entry in the registry, carried by a `WorkerRegister`
+/// transaction whose `amount` is added to the worker's collateral.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerRegistration {
+    /// Task types the worker offers to run.
+    pub capabilities: Vec<String>,
+}
+
+impl WorkerRegistration {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        encode_names(out, &self.capabilities);
+    }
+
+    /// Checks that the capabilities are distinct, non-empty and at most
+    /// `MAX_CAPABILITIES`.
+    pub fn verify(&self) -> Result<(), TaskError> {
+        let mut names: Vec<&String> = self.capabilities.iter().collect();
+        names.sort();
+        names.dedup();
+        if names.len() != self.capabilities.len()
+            || names.len() > MAX_CAPABILITIES
+            || names.iter().any(|name| name.is_empty())
+        {
+            return Err(TaskError::InvalidCapabilities);
+        }
+        Ok(())
+    }
+}
+
+/// How a worker's job on a task ended, as far as its reputation goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskOutcome {
+    /// The worker's result was accepted.
+    Verified,
+    /// The worker's result was handed in but rejected.
+    Rejected,
+    /// The worker let its claim lapse, did not reveal its result in time or
+    /// held a claim when the task expired.
+    Expired,
+}
+
+impl TaskOutcome {
+    fn tag(self) -> u8 {
+        match self {
+            TaskOutcome::Verified => 0,
+            TaskOutcome::Rejected => 1,
+            TaskOutcome::Expired => 2,
+        }
+    }
+}
+
+/// One entry of a worker's history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerEvent {
+    pub task_id: String,
+    /// Block whose processing settled the outcome.
+    pub height: u64,
+    pub outcome: TaskOutcome,
+    /// Why the worker's result was rejected, or that it was not revealed.
+    #[serde(default)]
+    pub reason: Option<RejectReason>,
+}
 
-    pub fn add_task(
+/// A registered worker and its track record. Records are kept when a
+/// worker leaves the registry, so that it cannot shed a poor reputation by
+/// registering again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerInfo {
+    pub address: String,
+    /// The worker's SPHINCS+ identity key, which its address encodes and
+    /// which signs its claims and results.
+    pub sphincs_public_key: Vec<u8>,
+    pub capabilities: Vec<String>,
+    /// Coins the worker has bonded, refunded when it leaves the registry.
+    /// A share is slashed for every rejected or expired outcome.
+    pub collateral: u64,
+    /// Whether the worker is in the registry. Only active workers may claim
+    /// tasks that ask for a minimum reputation.
+    pub active: bool,
+    /// Height of the block that first registered the worker.
+    pub registered_at: u64,
+    pub verified: u64,
+    pub rejected: u64,
+    pub expired: u64,
+    /// The latest outcomes, oldest first.
+    pub history: Vec<WorkerEvent>,
+}
+
+impl WorkerInfo {
+    fn new(address: &str, height: u64) -> Self {
+        WorkerInfo {
+            address: address.to_string(),
+            sphincs_public_key: hex::decode(address).unwrap_or_default(),
+            capabilities: Vec::new(),
+            collateral: 0,
+            active: false,
+            registered_at: height,
+            verified: 0,
+            rejected: 0,
+            expired: 0,
+            history: Vec::new(),
+        }
+    }
+
+    /// The share of the worker's jobs that were verified, in basis points,
+    /// counted as if it had one verified and one failed job more. A new
+    /// worker starts at half of `MAX_REPUTATION` and every outcome moves it
+    /// less than the one before.
+    pub fn reputation(&self) -> u64 {
+        let jobs = u128::from(self.verified) + u128::from(self.rejected) + u128::from(self.expired);
+        let verified = u128::from(self.verified) + 1;
+        (verified * u128::from(MAX_REPUTATION) / (jobs + 2)) as u64
+    }
+
+    /// Canonical binary encoding, used as the worker's value in the state
+    /// tree.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_bytes(&mut out, self.address.as_bytes());
+        encode_bytes(&mut out, &self.sphincs_public_key);
+        encode_names(&mut out, &self.capabilities);
+        out.extend_from_slice(&self.collateral.to_be_bytes());
+        out.push(self.active as u8);
+        out.extend_from_slice(&self.registered_at.to_be_bytes());
+        out.extend_from_slice(&self.verified.to_be_bytes());
+        out.extend_from_slice(&self.rejected.to_be_bytes());
+        out.extend_from_slice(&self.expired.to_be_bytes());
+        out.extend_from_slice(&(self.history.len() as u32).to_be_bytes());
+        for event in &self.history {
+            encode_bytes(&mut out, event.task_id.as_bytes());
+            out.extend_from_slice(&event.height.to_be_bytes());
+            out.push(event.outcome.tag());
+            match event.reason {
+                Some(reason) => out.extend_from_slice(&[1, reason.tag()]),
+                None => out.push(0),
+            }
+        }
+        out
+    }
+
+    /// Tallies `outcome` and, if it is a failure, slashes `slash_bps` basis
+    /// points of the worker's collateral. Returns the amount slashed.
+    fn record(
         &mut self,
+        task_id: &str,
+        height: u64,
+        outcome: TaskOutcome,
+        reason: Option<RejectReason>,
+        slash_bps: u64,
+    ) -> u64 {
+        let slashed = match outcome {
+            TaskOutcome::Verified => 0,
+            TaskOutcome::Rejected | TaskOutcome::Expired => {
+                (u128::from(self.collateral) * u128::from(slash_bps) / 10_000) as u64
+            }
+        };
+        self.collateral -= slashed;
+        let count = match outcome {
+            TaskOutcome::Verified => &mut self.verified,
+            TaskOutcome::Rejected => &mut self.rejected,
+            TaskOutcome::Expired => &mut self.expired,
+        };
+        *count = count.saturating_add(1);
+        if self.history.len() >= MAX_WORKER_HISTORY {
+            self.history.remove(0);
+        }
+        self.history.push(WorkerEvent {
+            task_id: task_id.to_string(),
+            height,
+            outcome,
+            reason,
+        });
+        slashed
+    }
+}
+
+/// The creator's withdrawal of a task, carried by a `TaskCancel`
+/// transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskCancel {
+    pub task_id: String,
+}
+
+impl TaskCancel {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        encode_bytes(out, self.task_id.as_bytes());
+    }
+}
+
+/// A worker's result for a task, carried by a `TaskResult` transaction and
+/// signed with the worker's key as part of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskResult {
+    pub task_id: String,
+    pub result: Vec<u8>,
+}
+
+impl TaskResult {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        encode_bytes(out, self.task_id.as_bytes());
+        encode_bytes(out, &self.result);
+    }
+}
+
+/// A worker's commitment to its result for a replicated task, carried by a
+/// `TaskCommit` transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskCommit {
+    pub task_id: String,
+    #[serde(with = "hex_hash")]
+    pub commitment: Hash,
+}
+
+impl TaskCommit {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        encode_bytes(out, self.task_id.as_bytes());
+        out.extend_from_slice(&self.commitment);
+    }
+}
+
+/// The result and salt behind a worker's commitment, carried by a
+/// `TaskReveal` transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskReveal {
+    pub task_id: String,
+    pub result: Vec<u8>,
+    pub salt: Vec<u8>,
+}
+
+impl TaskReveal {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        encode_bytes(out, self.task_id.as_bytes());
+        encode_bytes(out, &self.result);
+        encode_bytes(out, &self.salt);
+    }
+}
+
+/// Why a task transaction cannot be applied. The block carrying it is
+/// rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskError {
+    UnknownTask,
+    /// A task with the same id already exists.
+    DuplicateTask,
+    /// The task names a type that is not registered.
+    UnknownTaskType,
+    /// A task type with the same name is already registered.
+    DuplicateTaskType,
+    /// The task type's fuel limit is zero or above `MAX_TASK_FUEL`.
+    InvalidFuelLimit,
+    InvalidProgram(WasmError),
+    EmptyId,
+    /// The task asks for no replicas, a zero-block claim period or a
+    /// minimum reputation above `MAX_REPUTATION`.
+    InvalidSpec,
+    /// The task has a single replica and neither a type nor an expected
+    /// result, so nothing could check its result.
+    Unverifiable,
+    /// The escrow does not cover the verifier fee and a positive reward.
+    InsufficientEscrow,
+    /// Only the task's creator may cancel it.
+    NotCreator,
+    /// The task is claimed by a worker or finished.
+    NotCancellable(TaskStatus),
+    /// The task is not open to claims.
+    NotClaimable(TaskStatus),
+    /// The sender already holds a claim on the task.
+    AlreadyClaimed,
+    /// The sender does not hold a claim on the task, or has already handed
+    /// in what the transaction carries.
+    NotClaimant,
+    EmptyResult,
+    /// The task is replicated, so results must be committed and revealed.
+    CommitRequired,
+    /// The task has a single replica and takes results directly.
+    NotReplicated,
+    /// Reveals open once every worker has committed.
+    RevealNotOpen,
+    /// The revealed result and salt do not match the worker's commitment.
+    CommitmentMismatch,
+    /// The sender is not an active registered worker.
+    UnknownWorker,
+    /// Replicated tasks are only open to workers with collateral.
+    NoCollateral,
+    /// The worker holds a claim on a task that is not finished.
+    WorkerBusy,
+    /// The advertised capabilities are empty names, repeated or too many.
+    InvalidCapabilities,
+    /// The worker's reputation is below the task's minimum.
+    InsufficientReputation {
+        required: u64,
+        reputation: u64,
+    },
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskError::UnknownTask => write!(f, "task does not exist"),
+            TaskError::DuplicateTask => write!(f, "a task with this id already exists"),
+            TaskError::UnknownTaskType => write!(f, "task type is not registered"),
+            TaskError::DuplicateTaskType => {
+                write!(f, "a task type with this name is already registered")
+            }
+            TaskError::InvalidFuelLimit => {
+                write!(f, "fuel limit must be between 1 and {}", MAX_TASK_FUEL)
+            }
+            TaskError::InvalidProgram(err) => write!(f, "{}", err),
+            TaskError::EmptyId => write!(f, "id or name is empty"),
+            TaskError::InvalidSpec => {
+                write!(
+                    f,
+                    "task needs at least one replica, a claim period and a minimum reputation of \
+                     at most {}",
+                    MAX_REPUTATION
+                )
+            }
+            TaskError::Unverifiable => write!(
+                f,
+                "task needs a type, an expected result or more than one replica"
+            ),
+            TaskError::InsufficientEscrow => {
+                write!(
+                    f,
+                    "amount must cover the verifier fee and a positive reward"
+                )
+            }
+            TaskError::NotCreator => write!(f, "only the task's creator may cancel it"),
+            TaskError::NotCancellable(status) => {
+                write!(f, "task is {} and cannot be cancelled", status)
+            }
+            TaskError::NotClaimable(status) => {
+                write!(f, "task is {} and cannot be claimed", status)
+            }
+            TaskError::AlreadyClaimed => write!(f, "sender already holds a claim on the task"),
+            TaskError::NotClaimant => write!(f, "sender does not hold an open claim on the task"),
+            TaskError::EmptyResult => write!(f, "result is empty"),
+            TaskError::CommitRequired => {
+                write!(f, "task is replicated; commit and reveal the result")
+            }
+            TaskError::NotReplicated => write!(f, "task takes its result directly"),
+            TaskError::RevealNotOpen => write!(f, "not every worker has committed yet"),
+            TaskError::CommitmentMismatch => {
+                write!(f, "revealed result does not match the commitment")
+            }
+            TaskError::UnknownWorker => write!(f, "sender is not a registered worker"),
+            TaskError::NoCollateral => write!(f, "worker has no collateral at stake"),
+            TaskError::WorkerBusy => write!(f, "worker holds a claim on an unfinished task"),
+            TaskError::InvalidCapabilities => write!(
+                f,
+                "capabilities must be distinct, non-empty and at most {}",
+                MAX_CAPABILITIES
+            ),
+            TaskError::InsufficientReputation {
+                required,
+                reputation,
+            } => write!(
+                f,
+                "worker reputation {} is below the required {}",
+                reputation, required
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TaskError {}
+
+/// Why a submitted result was not accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    /// The result does not hash to the task's expected result.
+    ResultMismatch,
+    /// A majority of the task's workers agreed on a different result.
+    Dissent,
+    /// No result was backed by a majority of the task's workers.
+    NoMajority,
+    /// The worker committed but did not reveal its result in time.
+    NotRevealed,
+    /// The result differs from the output of the task type's program.
+    ExecutionMismatch,
+    /// The task type's program failed on the task's data, so no result can
+    /// be checked.
+    ExecutionFailed,
+}
+
+impl RejectReason {
+    fn tag(self) -> u8 {
+        match self {
+            RejectReason::ResultMismatch => 0,
+            RejectReason::Dissent => 1,
+            RejectReason::NoMajority => 2,
+            RejectReason::NotRevealed => 3,
+            RejectReason::ExecutionMismatch => 4,
+            RejectReason::ExecutionFailed => 5,
+        }
+    }
+
+    /// Whether the reason counts against the worker as disagreeing with its
+    /// peers.
+    pub fn is_dissent(self) -> bool {
+        matches!(self, RejectReason::Dissent | RejectReason::NotRevealed)
+    }
+
+    /// How the rejection counts towards the worker's reputation. A program
+    /// failing on the task's data is not the worker's doing, so it does not
+    /// count.
+    pub fn outcome(self) -> Option<TaskOutcome> {
+        match self {
+            RejectReason::ExecutionFailed => None,
+            RejectReason::NotRevealed => Some(TaskOutcome::Expired),
+            _ => Some(TaskOutcome::Rejected),
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::ResultMismatch => write!(f, "result does not match the expected result"),
+            RejectReason::Dissent => write!(f, "result disagrees with the majority"),
+            RejectReason::NoMajority => write!(f, "no result was backed by a majority"),
+            RejectReason::NotRevealed => write!(f, "result was committed but not revealed"),
+            RejectReason::ExecutionMismatch => {
+                write!(f, "result does not match the output of the task's program")
+            }
+            RejectReason::ExecutionFailed => write!(f, "task's program failed on its data"),
+        }
+    }
+}
+
+/// A submission that failed verification, kept on the task it targeted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rejection {
+    pub worker: String,
+    #[serde(with = "hex_hash")]
+    pub result_hash: Hash, // All zeros if the result was never revealed
+    pub height: u64, // Block that carried the submission
+    pub reason: RejectReason,
+}
+
+impl Task {
+    /// An open, single-replica task with no expected result that never
+    /// expires and no creator, whose `reward` is taken to be escrowed
+    /// already. Tasks on chain are created by `TaskQueue::create_task`.
+    pub fn new(
         id: String,
         description: String,
         data: Vec<u8>,
         reward: u64,
-        sphincs_public_key: Vec<u8>, // Added parameter
-        signature: Vec<u8>,          // Added parameter
-    ) {
-        let task = Task {
-            id: id.clone(),
+        sphincs_public_key: Vec<u8>,
+        signature: Vec<u8>,
+    ) -> Self {
+        Task {
+            id,
             description,
             data,
             reward,
-            is_complete: false,
+            creator: String::new(),
+            verifier_fee: 0,
+            escrow: reward,
+            status: TaskStatus::Open,
             sphincs_public_key,
             signature,
-        };
+            expected_result: None,
+            task_type: None,
+            min_reputation: 0,
+            replicas: 1,
+            claim_blocks: DEFAULT_CLAIM_BLOCKS,
+            assignments: Vec::new(),
+            reveal_deadline: None,
+            result_hash: None,
+            expires_at: None,
+            rejections: Vec::new(),
+        }
+    }
+
+    /// Canonical binary encoding, used as the task's value in the state tree.
+    /// Variable-length fields are length-prefixed.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_bytes(&mut out, self.id.as_bytes());
+        encode_bytes(&mut out, self.description.as_bytes());
+        encode_bytes(&mut out, &self.data);
+        out.extend_from_slice(&self.reward.to_be_bytes());
+        encode_bytes(&mut out, self.creator.as_bytes());
+        out.extend_from_slice(&self.verifier_fee.to_be_bytes());
+        out.extend_from_slice(&self.escrow.to_be_bytes());
+        out.push(self.status.tag());
+        encode_bytes(&mut out, &self.sphincs_public_key);
+        encode_bytes(&mut out, &self.signature);
+        encode_hash_option(&mut out, self.expected_result.as_ref());
+        encode_name_option(&mut out, self.task_type.as_deref());
+        out.extend_from_slice(&self.min_reputation.to_be_bytes());
+        out.extend_from_slice(&self.replicas.to_be_bytes());
+        out.extend_from_slice(&self.claim_blocks.to_be_bytes());
+        out.extend_from_slice(&(self.assignments.len() as u32).to_be_bytes());
+        for assignment in &self.assignments {
+            encode_bytes(&mut out, assignment.worker.as_bytes());
+            out.extend_from_slice(&assignment.claim_deadline.to_be_bytes());
+            encode_hash_option(&mut out, assignment.commitment.as_ref());
+            encode_hash_option(&mut out, assignment.result_hash.as_ref());
+            encode_height_option(&mut out, assignment.submitted_height);
+        }
+        encode_height_option(&mut out, self.reveal_deadline);
+        encode_hash_option(&mut out, self.result_hash.as_ref());
+        encode_height_option(&mut out, self.expires_at);
+        out.extend_from_slice(&(self.rejections.len() as u32).to_be_bytes());
+        for rejection in &self.rejections {
+            encode_bytes(&mut out, rejection.worker.as_bytes());
+            out.extend_from_slice(&rejection.result_hash);
+            out.extend_from_slice(&rejection.height.to_be_bytes());
+            out.push(rejection.reason.tag());
+        }
+        out
+    }
+
+    /// Whether results are committed and revealed rather than submitted.
+    pub fn is_replicated(&self) -> bool {
+        self.replicas > 1
+    }
+
+    /// Checks an agreed result against the task: when the creator committed
+    /// to an expected result, the result must hash to it, and when the task
+    /// has a type, the result must be what `task_type`'s program outputs
+    /// for the task's data.
+    pub fn verify_result(
+        &self,
+        result_hash: &Hash,
+        task_type: Option<&TaskType>,
+    ) -> Result<(), RejectReason> {
+        if self
+            .expected_result
+            .is_some_and(|expected| expected != *result_hash)
+        {
+            return Err(RejectReason::ResultMismatch);
+        }
+        if let Some(task_type) = task_type {
+            let output = task_type
+                .result_hash(&self.data)
+                .map_err(|_| RejectReason::ExecutionFailed)?;
+            if output != *result_hash {
+                return Err(RejectReason::ExecutionMismatch);
+            }
+        }
+        Ok(())
+    }
+
+    /// Workers whose result was accepted.
+    pub fn accepted_workers(&self) -> Vec<&str> {
+        match (&self.status, &self.result_hash) {
+            (TaskStatus::Verified, Some(accepted)) => self
+                .assignments
+                .iter()
+                .filter(|assignment| assignment.result_hash.as_ref() == Some(accepted))
+                .map(|assignment| assignment.worker.as_str())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn assignment_mut(&mut self, worker: &str) -> Option<&mut Assignment> {
+        self.assignments
+            .iter_mut()
+            .find(|assignment| assignment.worker == worker)
+    }
+
+    /// Records the rejection and returns it as the worker's verdict.
+    fn reject(
+        &mut self,
+        worker: &str,
+        result_hash: Hash,
+        height: u64,
+        reason: RejectReason,
+    ) -> Verdict {
+        if self.rejections.len() >= MAX_REJECTIONS {
+            self.rejections.remove(0);
+        }
+        self.rejections.push(Rejection {
+            worker: worker.to_string(),
+            result_hash,
+            height,
+            reason,
+        });
+        (worker.to_string(), Err(reason))
+    }
+
+    /// Compares the results handed in and accepts the one more than half of
+    /// the task's replicas agree on, if it also passes `verify_result`
+    /// against the task's type.
+    /// Workers behind it split the reward, the first to claim taking any
+    /// remainder, and `proposer` earns the verifier fee; the others are
+    /// flagged as dissenting. Without an accepted result every submission is
+    /// rejected and the task is disputed, its escrow still locked.
+    fn settle(&mut self, height: u64, proposer: &str, task_type: Option<&TaskType>) -> Settlement {
+        let mut votes: BTreeMap<Hash, u32> = BTreeMap::new();
+        for assignment in &self.assignments {
+            if let Some(result_hash) = assignment.result_hash {
+                *votes.entry(result_hash).or_default() += 1;
+            }
+        }
+        let majority = votes
+            .into_iter()
+            .find(|(_, count)| u64::from(*count) * 2 > u64::from(self.replicas))
+            .map(|(result_hash, count)| {
+                let verdict = self.verify_result(&result_hash, task_type);
+                (result_hash, verdict, count)
+            });
+
+        let assignments = self.assignments.clone();
+        let mut settlement = Settlement::default();
+        let verdicts = &mut settlement.verdicts;
+        match majority {
+            Some((accepted, Ok(()), count)) => {
+                let share = self.reward / u64::from(count);
+                let mut remainder = self.reward % u64::from(count);
+                for assignment in &assignments {
+                    match assignment.result_hash {
+                        Some(result_hash) if result_hash == accepted => {
+                            let worker = assignment.worker.clone();
+                            settlement.payouts.push((worker.clone(), share + remainder));
+                            verdicts.push((worker, Ok(())));
+                            remainder = 0;
+                        }
+                        Some(result_hash) => verdicts.push(self.reject(
+                            &assignment.worker,
+                            result_hash,
+                            assignment.submitted_height.unwrap_or(height),
+                            RejectReason::Dissent,
+                        )),
+                        None => verdicts.push(self.reject(
+                            &assignment.worker,
+                            Hash::default(),
+                            height,
+                            RejectReason::NotRevealed,
+                        )),
+                    }
+                }
+                if self.verifier_fee > 0 {
+                    settlement
+                        .payouts
+                        .push((proposer.to_string(), self.verifier_fee));
+                }
+                self.status = TaskStatus::Verified;
+                self.result_hash = Some(accepted);
+                self.reveal_deadline = None;
+                self.escrow = 0;
+                return settlement;
+            }
+            Some((_, Err(reason), _)) => {
+                for assignment in &assignments {
+                    if let Some(result_hash) = assignment.result_hash {
+                        let submitted = assignment.submitted_height.unwrap_or(height);
+                        verdicts.push(self.reject(
+                            &assignment.worker,
+                            result_hash,
+                            submitted,
+                            reason,
+                        ));
+                    }
+                }
+            }
+            None => {
+                for assignment in &assignments {
+                    let verdict = match assignment.result_hash {
+                        Some(result_hash) => self.reject(
+                            &assignment.worker,
+                            result_hash,
+                            assignment.submitted_height.unwrap_or(height),
+                            RejectReason::NoMajority,
+                        ),
+                        None => self.reject(
+                            &assignment.worker,
+                            Hash::default(),
+                            height,
+                            RejectReason::NotRevealed,
+                        ),
+                    };
+                    verdicts.push(verdict);
+                }
+            }
+        }
+        self.status = TaskStatus::Disputed;
+        self.assignments.clear();
+        self.reveal_deadline = None;
+        settlement
+    }
+}
+
+/// A worker and whether its result was accepted or why it was rejected.
+type Verdict = (String, Result<(), RejectReason>);
+
+/// What settling a task's results pays out, and the verdict on each worker
+/// that held a slot.
+#[derive(Default)]
+struct Settlement {
+    payouts: Vec<(String, u64)>,
+    verdicts: Vec<Verdict>,
+}
+
+/// `0` for `None`, or `1` followed by the hash.
+fn encode_hash_option(out: &mut Vec<u8>, hash: Option<&Hash>) {
+    match hash {
+        Some(hash) => {
+            out.push(1);
+            out.extend_from_slice(hash);
+        }
+        None => out.push(0),
+    }
+}
+
+/// `0` for `None`, or `1` followed by the length-prefixed name.
+fn encode_name_option(out: &mut Vec<u8>, name: Option<&str>) {
+    match name {
+        Some(name) => {
+            out.push(1);
+            encode_bytes(out, name.as_bytes());
+        }
+        None => out.push(0),
+    }
+}
+
+/// A `u32` count followed by each length-prefixed name.
+fn encode_names(out: &mut Vec<u8>, names: &[String]) {
+    out.extend_from_slice(&(names.len() as u32).to_be_bytes());
+    for name in names {
+        encode_bytes(out, name.as_bytes());
+    }
+}
+
+/// Checks that `worker` may claim `task`. Replicated tasks settle by vote,
+/// so their claimants must be active registered workers with collateral at
+/// stake, or one sender could fill every slot with throwaway addresses.
+fn check_claimant(
+    workers: &HashMap<String, WorkerInfo>,
+    worker: &str,
+    task: &Task,
+) -> Result<(), TaskError> {
+    if task.min_reputation == 0 && !task.is_replicated() {
+        return Ok(());
+    }
+    let info = workers
+        .get(worker)
+        .filter(|info| info.active)
+        .ok_or(TaskError::UnknownWorker)?;
+    if task.is_replicated() && info.collateral == 0 {
+        return Err(TaskError::NoCollateral);
+    }
+    let reputation = info.reputation();
+    if reputation < task.min_reputation {
+        return Err(TaskError::InsufficientReputation {
+            required: task.min_reputation,
+            reputation,
+        });
+    }
+    Ok(())
+}
+
+/// `0` for `None`, or `1` followed by the 8-byte height.
+fn encode_height_option(out: &mut Vec<u8>, height: Option<u64>) {
+    match height {
+        Some(height) => {
+            out.push(1);
+            out.extend_from_slice(&height.to_be_bytes());
+        }
+        None => out.push(0),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskQueue {
+    pub tasks: HashMap<String, Task>,
+    #[serde(default)]
+    pub task_types: HashMap<String, TaskType>,
+    /// The worker registry, by address.
+    #[serde(default)]
+    pub workers: HashMap<String, WorkerInfo>,
+}
+
+impl TaskQueue {
+    pub fn new() -> Self {
+        Self {
+            tasks: HashMap::new(),
+            task_types: HashMap::new(),
+            workers: HashMap::new(),
+        }
+    }
+
+    pub fn add_task(&mut self, task: Task) {
         self.tasks.insert(task.id.clone(), task);
     }
 
-    pub fn complete_task(&mut self, id: &str) -> Option<Task> {
-        if let Some(task) = self.tasks.get_mut(id) {
-            task.is_complete = true;
-            return Some(task.clone());
+    /// Creates the task described by `spec` for `creator` in the block at
+    /// `height`, with `escrow` already taken from the creator's balance. The
+    /// task is signed by the creator through `signature`, the signature of
+    /// the transaction that carries it.
+    pub fn create_task(
+        &mut self,
+        creator: &str,
+        spec: &TaskSpec,
+        escrow: u64,
+        signature: &[u8],
+        height: u64,
+    ) -> Result<(), TaskError> {
+        spec.verify(escrow)?;
+        if self.tasks.contains_key(&spec.id) {
+            return Err(TaskError::DuplicateTask);
+        }
+        if let Some(name) = &spec.task_type {
+            if !self.task_types.contains_key(name) {
+                return Err(TaskError::UnknownTaskType);
+            }
+        }
+        let mut task = Task::new(
+            spec.id.clone(),
+            spec.description.clone(),
+            spec.data.clone(),
+            escrow - spec.verifier_fee,
+            hex::decode(creator).unwrap_or_default(),
+            signature.to_vec(),
+        );
+        task.creator = creator.to_string();
+        task.verifier_fee = spec.verifier_fee;
+        task.escrow = escrow;
+        task.expected_result = spec.expected_result;
+        task.task_type = spec.task_type.clone();
+        task.min_reputation = spec.min_reputation;
+        task.replicas = spec.replicas;
+        task.claim_blocks = spec.claim_blocks;
+        task.expires_at = spec.ttl_blocks.map(|ttl| height.saturating_add(ttl));
+        self.add_task(task);
+        Ok(())
+    }
+
+    /// Registers the task type described by `spec` for `owner`.
+    pub fn register_task_type(
+        &mut self,
+        owner: &str,
+        spec: &TaskTypeSpec,
+    ) -> Result<(), TaskError> {
+        spec.verify()?;
+        if self.task_types.contains_key(&spec.name) {
+            return Err(TaskError::DuplicateTaskType);
+        }
+        let task_type = TaskType {
+            name: spec.name.clone(),
+            owner: owner.to_string(),
+            code: spec.code.clone(),
+            code_hash: sha3_256(&spec.code),
+            fuel_limit: spec.fuel_limit,
+        };
+        self.task_types.insert(task_type.name.clone(), task_type);
+        Ok(())
+    }
+
+    pub fn get_task_type(&self, name: &str) -> Option<&TaskType> {
+        self.task_types.get(name)
+    }
+
+    /// Enters `worker` in the registry with the capabilities in
+    /// `registration`, in the block at `height`, adding `collateral`, already
+    /// taken from its balance, to its bond. A worker registering again keeps
+    /// its record: its capabilities are replaced and it becomes active.
+    pub fn register_worker(
+        &mut self,
+        worker: &str,
+        registration: &WorkerRegistration,
+        collateral: u64,
+        height: u64,
+    ) -> Result<(), TaskError> {
+        registration.verify()?;
+        let info = self
+            .workers
+            .entry(worker.to_string())
+            .or_insert_with(|| WorkerInfo::new(worker, height));
+        info.capabilities = registration.capabilities.clone();
+        info.collateral = info.collateral.saturating_add(collateral);
+        info.active = true;
+        Ok(())
+    }
+
+    /// Takes `worker` out of the registry while no unfinished task holds a
+    /// claim of it. Returns its collateral to refund; its record is kept.
+    pub fn unregister_worker(&mut self, worker: &str) -> Result<u64, TaskError> {
+        let busy = self.tasks.values().any(|task| {
+            !task.status.is_final()
+                && task
+                    .assignments
+                    .iter()
+                    .any(|assignment| assignment.worker == worker)
+        });
+        let info = self
+            .workers
+            .get_mut(worker)
+            .filter(|info| info.active)
+            .ok_or(TaskError::UnknownWorker)?;
+        if busy {
+            return Err(TaskError::WorkerBusy);
+        }
+        info.active = false;
+        Ok(std::mem::take(&mut info.collateral))
+    }
+
+    pub fn get_worker(&self, address: &str) -> Option<&WorkerInfo> {
+        self.workers.get(address)
+    }
+
+    /// Checks that `worker` may claim `task`: any sender may claim a
+    /// single-replica task with no minimum reputation, otherwise only an
+    /// active registered worker whose reputation reaches the minimum and,
+    /// for a replicated task, whose collateral is above zero.
+    pub fn check_claimant(&self, worker: &str, task: &Task) -> Result<(), TaskError> {
+        check_claimant(&self.workers, worker, task)
+    }
+
+    /// Gives `worker` one of the task's replica slots, in the block at
+    /// `height`. The claim lasts until `height + claim_blocks`. The task
+    /// stays open until every slot is taken.
+    pub fn claim(&mut self, worker: &str, claim: &TaskClaim, height: u64) -> Result<(), TaskError> {
+        let task = self
+            .tasks
+            .get_mut(&claim.task_id)
+            .ok_or(TaskError::UnknownTask)?;
+        if !task.status.is_claimable() {
+            return Err(TaskError::NotClaimable(task.status));
+        }
+        if task.assignment_mut(worker).is_some() {
+            return Err(TaskError::AlreadyClaimed);
+        }
+        check_claimant(&self.workers, worker, task)?;
+        task.assignments.push(Assignment {
+            worker: worker.to_string(),
+            claim_deadline: height.saturating_add(task.claim_blocks),
+            commitment: None,
+            result_hash: None,
+            submitted_height: None,
+        });
+        task.status = if task.assignments.len() as u64 >= u64::from(task.replicas) {
+            TaskStatus::Claimed
+        } else {
+            TaskStatus::Open
+        };
+        Ok(())
+    }
+
+    /// Records `worker`'s result for the single-replica task named in
+    /// `submission`, carried by the block at `height`. Only the worker
+    /// holding the claim may submit. The result is verified at the start of
+    /// the next block.
+    pub fn submit_result(
+        &mut self,
+        worker: &str,
+        submission: &TaskResult,
+        height: u64,
+    ) -> Result<(), TaskError> {
+        let task = self
+            .tasks
+            .get_mut(&submission.task_id)
+            .ok_or(TaskError::UnknownTask)?;
+        if task.is_replicated() {
+            return Err(TaskError::CommitRequired);
+        }
+        if task.status != TaskStatus::Claimed {
+            return Err(TaskError::NotClaimant);
+        }
+        let assignment = task.assignment_mut(worker).ok_or(TaskError::NotClaimant)?;
+        assignment.result_hash = Some(sha3_256(&submission.result));
+        assignment.submitted_height = Some(height);
+        task.status = TaskStatus::Submitted;
+        Ok(())
+    }
+
+    /// Records `worker`'s commitment for the replicated task named in
+    /// `commit`. Once every replica slot has committed, the task moves to
+    /// `Committed` and reveals are taken until `height + claim_blocks`.
+    pub fn commit_result(
+        &mut self,
+        worker: &str,
+        commit: &TaskCommit,
+        height: u64,
+    ) -> Result<(), TaskError> {
+        let task = self
+            .tasks
+            .get_mut(&commit.task_id)
+            .ok_or(TaskError::UnknownTask)?;
+        if !task.is_replicated() {
+            return Err(TaskError::NotReplicated);
+        }
+        if !matches!(task.status, TaskStatus::Open | TaskStatus::Claimed) {
+            return Err(TaskError::NotClaimant);
+        }
+        let assignment = task.assignment_mut(worker).ok_or(TaskError::NotClaimant)?;
+        if assignment.commitment.is_some() {
+            return Err(TaskError::NotClaimant);
+        }
+        assignment.commitment = Some(commit.commitment);
+
+        let committed = task
+            .assignments
+            .iter()
+            .filter(|assignment| assignment.commitment.is_some())
+            .count();
+        if committed as u64 >= u64::from(task.replicas) {
+            task.status = TaskStatus::Committed;
+            task.reveal_deadline = Some(height.saturating_add(task.claim_blocks));
+        }
+        Ok(())
+    }
+
+    /// Records the result behind `worker`'s commitment, carried by the block
+    /// at `height`. Once every worker has revealed, the task is `Submitted`
+    /// and its results are settled at the start of the next block.
+    pub fn reveal_result(
+        &mut self,
+        worker: &str,
+        reveal: &TaskReveal,
+        height: u64,
+    ) -> Result<(), TaskError> {
+        let task = self
+            .tasks
+            .get_mut(&reveal.task_id)
+            .ok_or(TaskError::UnknownTask)?;
+        if !task.is_replicated() {
+            return Err(TaskError::NotReplicated);
+        }
+        if task.status != TaskStatus::Committed {
+            return Err(TaskError::RevealNotOpen);
+        }
+        let assignment = task.assignment_mut(worker).ok_or(TaskError::NotClaimant)?;
+        if assignment.result_hash.is_some() {
+            return Err(TaskError::NotClaimant);
+        }
+        if assignment.commitment != Some(result_commitment(worker, &reveal.result, &reveal.salt)) {
+            return Err(TaskError::CommitmentMismatch);
+        }
+        assignment.result_hash = Some(sha3_256(&reveal.result));
+        assignment.submitted_height = Some(height);
+
+        if task
+            .assignments
+            .iter()
+            .all(|assignment| assignment.result_hash.is_some())
+        {
+            task.status = TaskStatus::Submitted;
+            task.reveal_deadline = None;
+        }
+        Ok(())
+    }
+
+    /// Moves tasks along at the start of the block at `height`, proposed by
+    /// `proposer`: results handed in during earlier blocks, or whose reveal
+    /// deadline has passed, are settled, claims not followed up by their
+    /// deadline are released and tasks past their expiry height expire.
+    /// The outcome for each worker involved is recorded in its registry
+    /// entry, if it has one, and each failure costs `slash_bps` basis points
+    /// of its collateral.
+    /// Returns what is paid out of escrow, in task id order: rewards to
+    /// workers whose results were accepted, verifier fees to `proposer` and
+    /// refunds to the creators of expired tasks; and the collateral slashed.
+    pub fn process_block(
+        &mut self,
+        height: u64,
+        proposer: &str,
+        slash_bps: u64,
+    ) -> (Vec<(String, u64)>, u64) {
+        let mut ids: Vec<&String> = self.tasks.keys().collect();
+        ids.sort();
+        let ids: Vec<String> = ids.into_iter().cloned().collect();
+
+        let mut payouts = Vec::new();
+        let mut slashed = 0u64;
+        for id in ids {
+            let task = self.tasks.get_mut(&id).expect("id was just listed");
+            let task_type = task
+                .task_type
+                .as_ref()
+                .and_then(|name| self.task_types.get(name));
+            let mut settlement = None;
+            let mut outcomes = Vec::new();
+            match task.status {
+                TaskStatus::Submitted => {
+                    settlement = Some(task.settle(height, proposer, task_type))
+                }
+                TaskStatus::Committed if task.reveal_deadline.is_some_and(|d| d < height) => {
+                    settlement = Some(task.settle(height, proposer, task_type));
+                }
+                TaskStatus::Open | TaskStatus::Claimed => {
+                    // Workers that abandoned the task give up their slot.
+                    // Those that committed keep it for another claim period
+                    // while the other slots fill, then lapse through no
+                    // fault of their own.
+                    let hold = task.claim_blocks;
+                    let (kept, lapsed): (Vec<_>, Vec<_>) = std::mem::take(&mut task.assignments)
+                        .into_iter()
+                        .partition(|assignment| match assignment.commitment {
+                            Some(_) => assignment.claim_deadline.saturating_add(hold) >= height,
+                            None => assignment.claim_deadline >= height,
+                        });
+                    task.assignments = kept;
+                    if !lapsed.is_empty() {
+                        task.status = TaskStatus::Open;
+                    }
+                    for assignment in lapsed {
+                        if assignment.commitment.is_none() {
+                            outcomes.push((assignment.worker, TaskOutcome::Expired, None));
+                        }
+                    }
+                }
+                _ => {}
+            }
+            if let Some(settlement) = settlement {
+                payouts.extend(settlement.payouts);
+                for (worker, verdict) in settlement.verdicts {
+                    match verdict {
+                        Ok(()) => outcomes.push((worker, TaskOutcome::Verified, None)),
+                        Err(reason) => {
+                            if let Some(outcome) = reason.outcome() {
+                                outcomes.push((worker, outcome, Some(reason)));
+                            }
+                        }
+                    }
+                }
+            }
+            if !task.status.is_final() && task.expires_at.is_some_and(|e| e < height) {
+                task.status = TaskStatus::Expired;
+                task.reveal_deadline = None;
+                if task.escrow > 0 {
+                    payouts.push((task.creator.clone(), task.escrow));
+                    task.escrow = 0;
+                }
+                // Workers still holding a slot never handed a result in.
+                for assignment in &task.assignments {
+                    if assignment.result_hash.is_none() {
+                        let worker = assignment.worker.clone();
+                        outcomes.push((worker, TaskOutcome::Expired, None));
+                    }
+                }
+            }
+
+            for (worker, outcome, reason) in outcomes {
+                if let Some(info) = self.workers.get_mut(&worker) {
+                    slashed = slashed
+                        .saturating_add(info.record(&id, height, outcome, reason, slash_bps));
+                }
+            }
         }
-        None
+        (payouts, slashed)
     }
 
+    /// Cancels a task no worker is busy with on behalf of `creator`.
+    /// Returns the escrow to refund to the creator.
+    pub fn cancel_task(&mut self, creator: &str, cancel: &TaskCancel) -> Result<u64, TaskError> {
+        let task = self
+            .tasks
+            .get_mut(&cancel.task_id)
+            .ok_or(TaskError::UnknownTask)?;
+        if task.creator != creator {
+            return Err(TaskError::NotCreator);
+        }
+        if !task.status.is_claimable() || !task.assignments.is_empty() {
+            return Err(TaskError::NotCancellable(task.status));
+        }
+        task.status = TaskStatus::Cancelled;
+        Ok(std::mem::take(&mut task.escrow))
+    }
+
+    /// Tasks that are not finished yet.
     pub fn get_pending_tasks(&self) -> Vec<&Task> {
         self.tasks
             .values()
-            .filter(|task| !task.is_complete)
+            .filter(|task| !task.status.is_final())
             .collect()
     }
 
     pub fn get_task(&self, id: &str) -> Option<&Task> {
         self.tasks.get(id)
     }
+
+    /// Registered workers whose results were accepted by the processing of
+    /// the block at `height`, in address order, each with its share of those
+    /// tasks' rewards.
+    pub fn verified_work(&self, height: u64) -> Vec<(String, u64)> {
+        let mut work: BTreeMap<String, u64> = BTreeMap::new();
+        for info in self.workers.values() {
+            let verified = info
+                .history
+                .iter()
+                .rev()
+                .take_while(|event| event.height == height)
+                .filter(|event| event.outcome == TaskOutcome::Verified);
+            for event in verified {
+                let Some(task) = self.tasks.get(&event.task_id) else {
+                    continue;
+                };
+                let accepted = task.accepted_workers().len().max(1) as u64;
+                let share = work.entry(info.address.clone()).or_default();
+                *share = share.saturating_add(task.reward / accepted);
+            }
+        }
+        work.into_iter().collect()
+    }
+
+    /// How often each worker has been flagged for dissenting from the
+    /// majority of a replicated task or failing to reveal its result, among
+    /// the rejections still kept.
+    pub fn dissent_counts(&self) -> BTreeMap<String, u64> {
+        let mut counts = BTreeMap::new();
+        for task in self.tasks.values() {
+            for rejection in &task.rejections {
+                if rejection.reason.is_dissent() {
+                    *counts.entry(rejection.worker.clone()).or_default() += 1;
+                }
+            }
+        }
+        counts
+    }
 }
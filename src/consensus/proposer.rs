@@ -0,0 +1,37 @@
+use crate::blockchain::chain::Blockchain;
+use crate::wallet::key_manager::NodeKey;
+use log::{error, warn};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// How often the node checks whether it is due to propose unless
+/// `QTC_BLOCK_INTERVAL_MS` says otherwise.
+pub const DEFAULT_BLOCK_INTERVAL_MS: u64 = 5_000;
+
+/// Proposes blocks with `node_key` until the node stops: every `interval`
+/// it builds a block from the pending transactions and adds it if the key is
+/// due to propose on the tip. Building and signing happen on a blocking
+/// thread, and a failed or panicked attempt is logged and retried on the
+/// next tick.
+pub async fn run(blockchain: Arc<Mutex<Blockchain>>, node_key: Arc<NodeKey>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let proposed = tokio::task::spawn_blocking({
+            let blockchain = blockchain.clone();
+            let node_key = node_key.clone();
+            move || {
+                let mut blockchain = blockchain.lock().unwrap_or_else(PoisonError::into_inner);
+                match blockchain.propose_pending(&node_key)? {
+                    Some(block) => blockchain.add_block(block).map(Some),
+                    None => Ok(None),
+                }
+            }
+        });
+        match proposed.await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => warn!("Failed to propose a block: {}", e),
+            Err(e) => error!("Block proposal panicked: {}", e),
+        }
+    }
+}
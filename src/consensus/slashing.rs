@@ -0,0 +1,243 @@
+use crate::blockchain::block::{encode_bytes, BlockHeader};
+use crate::blockchain::genesis::ConsensusParams;
+use crate::blockchain::state::{AccountState, StakingState};
+use crate::cryptography::sphincs::Sphincs;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Where slashed coins go.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlashDestination {
+    /// Destroyed, shrinking the total supply.
+    #[default]
+    Burn,
+    /// Credited to the given treasury address.
+    Treasury(String),
+}
+
+/// Penalties for validator and worker misbehaviour, fixed at genesis.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlashingParams {
+    /// Share of bonded stake taken for signing two blocks at one height, in
+    /// basis points.
+    pub double_sign_slash_bps: u64,
+    /// Share of bonded stake taken for extended downtime, in basis points.
+    pub downtime_slash_bps: u64,
+    /// Slots in a row a validator may miss as a ranked proposer before it
+    /// counts as down.
+    pub max_missed_slots: u64,
+    /// Blocks a punished validator is kept out of the validator set.
+    pub jail_blocks: u64,
+    /// Share of a worker's collateral taken for each of its results that is
+    /// rejected and each of its slots that expires, in basis points.
+    pub worker_slash_bps: u64,
+    pub destination: SlashDestination,
+}
+
+impl Default for SlashingParams {
+    fn default() -> Self {
+        Self {
+            double_sign_slash_bps: 500,
+            downtime_slash_bps: 10,
+            max_missed_slots: 50,
+            jail_blocks: 1_000,
+            worker_slash_bps: 1_000,
+            destination: SlashDestination::Burn,
+        }
+    }
+}
+
+impl SlashingParams {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.double_sign_slash_bps.to_be_bytes());
+        out.extend_from_slice(&self.downtime_slash_bps.to_be_bytes());
+        out.extend_from_slice(&self.max_missed_slots.to_be_bytes());
+        out.extend_from_slice(&self.jail_blocks.to_be_bytes());
+        out.extend_from_slice(&self.worker_slash_bps.to_be_bytes());
+        match &self.destination {
+            SlashDestination::Burn => out.push(0),
+            SlashDestination::Treasury(address) => {
+                out.push(1);
+                encode_bytes(out, address.as_bytes());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvidenceError {
+    HeightMismatch,
+    ProposerMismatch,
+    /// Both headers are the same block, which is not an offence.
+    IdenticalBlocks,
+    InvalidSignature,
+    /// The offence is older than the unbonding period, so the stake involved
+    /// may already have been withdrawn.
+    Expired {
+        height: u64,
+    },
+    /// This offence has already been punished.
+    AlreadySlashed,
+    /// The offender has nothing bonded to slash.
+    NoStake,
+}
+
+impl fmt::Display for EvidenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvidenceError::HeightMismatch => write!(f, "headers are at different heights"),
+            EvidenceError::ProposerMismatch => write!(f, "headers have different proposers"),
+            EvidenceError::IdenticalBlocks => write!(f, "headers are the same block"),
+            EvidenceError::InvalidSignature => write!(f, "header signature is invalid"),
+            EvidenceError::Expired { height } => {
+                write!(
+                    f,
+                    "offence at height {} is past the unbonding period",
+                    height
+                )
+            }
+            EvidenceError::AlreadySlashed => write!(f, "offence has already been punished"),
+            EvidenceError::NoStake => write!(f, "offender has no bonded stake"),
+        }
+    }
+}
+
+impl std::error::Error for EvidenceError {}
+
+/// A block header with its proposer's signature over the header hash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedHeader {
+    pub header: BlockHeader,
+    pub signature: Vec<u8>,
+}
+
+impl SignedHeader {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_bytes(out, &self.header.encode());
+        encode_bytes(out, &self.signature);
+    }
+
+    fn verify_signature(&self) -> bool {
+        match hex::decode(&self.header.proposer) {
+            Ok(public_key) => {
+                Sphincs::verify_with_key_bytes(&self.header.hash(), &self.signature, &public_key)
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Proof that a validator signed two different blocks at the same height.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DoubleSignEvidence {
+    pub first: SignedHeader,
+    pub second: SignedHeader,
+}
+
+impl DoubleSignEvidence {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        self.first.encode(out);
+        self.second.encode(out);
+    }
+
+    /// Consensus key that signed both headers.
+    pub fn offender(&self) -> &str {
+        &self.first.header.proposer
+    }
+
+    pub fn height(&self) -> u64 {
+        self.first.header.height
+    }
+
+    /// Checks that the evidence proves an offence on its own, without
+    /// looking at chain state.
+    pub fn verify(&self) -> Result<(), EvidenceError> {
+        let (first, second) = (&self.first.header, &self.second.header);
+        if first.height != second.height {
+            return Err(EvidenceError::HeightMismatch);
+        }
+        if first.proposer != second.proposer {
+            return Err(EvidenceError::ProposerMismatch);
+        }
+        if first.hash() == second.hash() {
+            return Err(EvidenceError::IdenticalBlocks);
+        }
+        if !self.first.verify_signature() || !self.second.verify_signature() {
+            return Err(EvidenceError::InvalidSignature);
+        }
+        Ok(())
+    }
+}
+
+/// Punishes the offence proven by `evidence`, included in the block at
+/// `height`. Evidence is accepted while the stake it concerns could still be
+/// unbonding, and each offence is punished once. Returns the amount slashed.
+pub fn apply_double_sign(
+    accounts: &mut AccountState,
+    staking_state: &mut StakingState,
+    params: &ConsensusParams,
+    evidence: &DoubleSignEvidence,
+    height: u64,
+) -> Result<u64, EvidenceError> {
+    let offender = staking_state
+        .operator_of(evidence.offender())
+        .ok_or(EvidenceError::NoStake)?
+        .to_string();
+    let offender = offender.as_str();
+    let offence_height = evidence.height();
+    if offence_height.saturating_add(params.unbonding_blocks) < height {
+        return Err(EvidenceError::Expired {
+            height: offence_height,
+        });
+    }
+    let offence = (offender.to_string(), offence_height);
+    if staking_state.punished.contains(&offence) {
+        return Err(EvidenceError::AlreadySlashed);
+    }
+    if staking_state.bonded(offender) == 0 {
+        return Err(EvidenceError::NoStake);
+    }
+
+    staking_state.punished.insert(offence);
+    Ok(punish(
+        accounts,
+        staking_state,
+        &params.slashing,
+        offender,
+        params.slashing.double_sign_slash_bps,
+        height.saturating_add(params.slashing.jail_blocks),
+    ))
+}
+
+/// Takes `bps` basis points of everything `offender` has bonded, including
+/// unbonding funds, routes it to the slashing destination and jails the
+/// offender until `jailed_until`. Returns the amount slashed.
+pub fn punish(
+    accounts: &mut AccountState,
+    staking_state: &mut StakingState,
+    params: &SlashingParams,
+    offender: &str,
+    bps: u64,
+    jailed_until: u64,
+) -> u64 {
+    let slashed = staking_state.slash(offender, bps);
+    staking_state.jail(offender, jailed_until);
+    route_slashed(accounts, params, slashed);
+    slashed
+}
+
+/// Burns `slashed` coins or credits them to the treasury, as `params` say.
+pub fn route_slashed(accounts: &mut AccountState, params: &SlashingParams, slashed: u64) {
+    match &params.destination {
+        SlashDestination::Burn => {
+            accounts.total_supply = accounts.total_supply.saturating_sub(slashed);
+        }
+        SlashDestination::Treasury(treasury) => {
+            // Slashed coins were already part of the supply, so crediting
+            // them cannot overflow it; a single balance is bounded by it too.
+            let _ = accounts.credit(treasury, slashed);
+        }
+    }
+}
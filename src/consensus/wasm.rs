@@ -0,0 +1,179 @@
+use std::fmt;
+use wasmi::core::{TrapCode, ValType};
+use wasmi::{Config, Engine, FuncType, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// Largest program a task type may register, in bytes.
+pub const MAX_PROGRAM_BYTES: usize = 256 * 1024;
+
+/// Most fuel a task type may allow a single run. Every node re-runs the
+/// program when a task's results are settled, so this bounds the work a
+/// block can ask of it.
+pub const MAX_TASK_FUEL: u64 = 1_000_000_000;
+
+/// Linear memory a program may grow to, in bytes.
+pub const MAX_MEMORY_BYTES: usize = 16 * 1024 * 1024;
+
+/// Largest output a program may return, in bytes.
+pub const MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+/// Why a task program cannot be registered or did not produce an output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WasmError {
+    /// The program is larger than `MAX_PROGRAM_BYTES`.
+    TooLarge,
+    /// The bytes are not a valid WebAssembly module, or it uses a feature
+    /// that is disabled because it is not deterministic.
+    InvalidModule(String),
+    /// The module imports something. Programs get no host functions.
+    HasImports,
+    /// The module does not export the named item with the expected type.
+    MissingExport(&'static str),
+    /// Execution trapped.
+    Trap(String),
+    /// Execution used up its fuel.
+    OutOfFuel,
+    /// `alloc` or `run` returned a range outside the program's memory.
+    OutOfBounds,
+    /// The output is larger than `MAX_OUTPUT_BYTES`.
+    OutputTooLarge,
+}
+
+impl fmt::Display for WasmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WasmError::TooLarge => write!(f, "program exceeds {} bytes", MAX_PROGRAM_BYTES),
+            WasmError::InvalidModule(message) => write!(f, "invalid module: {}", message),
+            WasmError::HasImports => write!(f, "program must not import anything"),
+            WasmError::MissingExport(name) => {
+                write!(
+                    f,
+                    "program does not export `{}` with the expected type",
+                    name
+                )
+            }
+            WasmError::Trap(message) => write!(f, "program trapped: {}", message),
+            WasmError::OutOfFuel => write!(f, "program ran out of fuel"),
+            WasmError::OutOfBounds => write!(f, "program returned a range outside its memory"),
+            WasmError::OutputTooLarge => {
+                write!(f, "output exceeds {} bytes", MAX_OUTPUT_BYTES)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WasmError {}
+
+/// A finished run of a task program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execution {
+    pub output: Vec<u8>,
+    pub fuel_used: u64,
+}
+
+/// Interpreter settings shared by every node, so that a program gives the
+/// same output and uses the same fuel everywhere. Floating point is
+/// disabled because NaN bit patterns are not deterministic across hosts.
+fn engine() -> Engine {
+    let mut config = Config::default();
+    config.consume_fuel(true).floats(false);
+    Engine::new(&config)
+}
+
+/// Compiles `code` and checks it against the task program ABI. A program
+/// imports nothing and exports:
+///
+/// - `memory`, its linear memory;
+/// - `alloc(len: i32) -> i32`, which reserves `len` bytes for the input and
+///   returns where they start;
+/// - `run(ptr: i32, len: i32) -> i64`, which computes the output for the
+///   input at `ptr` and returns where the output starts in the high 32 bits
+///   and its length in the low 32 bits.
+fn compile(engine: &Engine, code: &[u8]) -> Result<Module, WasmError> {
+    if code.len() > MAX_PROGRAM_BYTES {
+        return Err(WasmError::TooLarge);
+    }
+    let module =
+        Module::new(engine, code).map_err(|err| WasmError::InvalidModule(err.to_string()))?;
+    if module.imports().len() > 0 {
+        return Err(WasmError::HasImports);
+    }
+    if module
+        .get_export("memory")
+        .and_then(|ty| ty.memory().cloned())
+        .is_none()
+    {
+        return Err(WasmError::MissingExport("memory"));
+    }
+    let exports = [
+        ("alloc", FuncType::new([ValType::I32], [ValType::I32])),
+        (
+            "run",
+            FuncType::new([ValType::I32, ValType::I32], [ValType::I64]),
+        ),
+    ];
+    for (name, expected) in exports {
+        if module.get_export(name).and_then(|ty| ty.func().cloned()) != Some(expected) {
+            return Err(WasmError::MissingExport(name));
+        }
+    }
+    Ok(module)
+}
+
+/// Checks that `code` is a task program that can be registered.
+pub fn validate(code: &[u8]) -> Result<(), WasmError> {
+    compile(&engine(), code).map(|_| ())
+}
+
+fn trap(err: wasmi::Error) -> WasmError {
+    match err.as_trap_code() {
+        Some(TrapCode::OutOfFuel) => WasmError::OutOfFuel,
+        _ => WasmError::Trap(err.to_string()),
+    }
+}
+
+/// Runs the program in `code` on `input` with at most `fuel` units of fuel,
+/// covering instantiation, `alloc` and `run`.
+pub fn execute(code: &[u8], input: &[u8], fuel: u64) -> Result<Execution, WasmError> {
+    let engine = engine();
+    let module = compile(&engine, code)?;
+    let limits = StoreLimitsBuilder::new()
+        .memory_size(MAX_MEMORY_BYTES)
+        .instances(1)
+        .memories(1)
+        .build();
+    let mut store: Store<StoreLimits> = Store::new(&engine, limits);
+    store.limiter(|limits| limits);
+    store.set_fuel(fuel).expect("fuel metering is enabled");
+
+    let instance = Linker::new(&engine)
+        .instantiate(&mut store, &module)
+        .and_then(|instance| instance.start(&mut store))
+        .map_err(trap)?;
+    let memory = instance
+        .get_memory(&store, "memory")
+        .ok_or(WasmError::MissingExport("memory"))?;
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&store, "alloc")
+        .map_err(|_| WasmError::MissingExport("alloc"))?;
+    let run = instance
+        .get_typed_func::<(i32, i32), i64>(&store, "run")
+        .map_err(|_| WasmError::MissingExport("run"))?;
+
+    let len = i32::try_from(input.len()).map_err(|_| WasmError::OutOfBounds)?;
+    let ptr = alloc.call(&mut store, len).map_err(trap)?;
+    memory
+        .write(&mut store, ptr as u32 as usize, input)
+        .map_err(|_| WasmError::OutOfBounds)?;
+    let packed = run.call(&mut store, (ptr, len)).map_err(trap)? as u64;
+
+    let (start, len) = ((packed >> 32) as usize, (packed as u32) as usize);
+    if len > MAX_OUTPUT_BYTES {
+        return Err(WasmError::OutputTooLarge);
+    }
+    let mut output = vec![0; len];
+    memory
+        .read(&store, start, &mut output)
+        .map_err(|_| WasmError::OutOfBounds)?;
+    let fuel_used = fuel - store.get_fuel().expect("fuel metering is enabled");
+    Ok(Execution { output, fuel_used })
+}
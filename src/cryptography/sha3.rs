@@ -0,0 +1,88 @@
+use sha3::{Digest, Sha3_256};
+
+/// A SHA3-256 digest.
+pub type Hash = [u8; 32];
+
+/// All-zero digest, used wherever a hash is required but nothing is committed yet.
+pub const ZERO_HASH: Hash = [0u8; 32];
+
+pub fn sha3_256(data: &[u8]) -> Hash {
+    Sha3_256::digest(data).into()
+}
+
+/// Hashes the concatenation of several byte slices without building an
+/// intermediate buffer.
+pub fn sha3_256_concat(parts: &[&[u8]]) -> Hash {
+    let mut hasher = Sha3_256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// Serde adapter that stores a `Hash` as a lowercase hex string, so JSON
+/// payloads stay readable while the in-memory value is fixed-size.
+pub mod hex_hash {
+    use super::Hash;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(hash: &Hash, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(hash))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Hash, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let bytes = hex::decode(&s).map_err(serde::de::Error::custom)?;
+        bytes
+            .try_into()
+            .map_err(|_| serde::de::Error::custom("expected a 32-byte hash"))
+    }
+}
+
+/// Like `hex_hash`, for a list of hashes.
+pub mod hex_hashes {
+    use super::Hash;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(hashes: &[Hash], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(hashes.iter().map(hex::encode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Hash>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|s| {
+                let bytes = hex::decode(s).map_err(serde::de::Error::custom)?;
+                bytes
+                    .try_into()
+                    .map_err(|_| serde::de::Error::custom("expected a 32-byte hash"))
+            })
+            .collect()
+    }
+}
+
+/// Like `hex_hash`, for an optional hash stored as `null` when absent.
+pub mod hex_hash_option {
+    use super::Hash;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(hash: &Option<Hash>, serializer: S) -> Result<S::Ok, S::Error> {
+        match hash {
+            Some(hash) => serializer.serialize_some(&hex::encode(hash)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Hash>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| {
+                let bytes = hex::decode(&s).map_err(serde::de::Error::custom)?;
+                bytes
+                    .try_into()
+                    .map_err(|_| serde::de::Error::custom("expected a 32-byte hash"))
+            })
+            .transpose()
+    }
+}
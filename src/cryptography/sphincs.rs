@@ -1,7 +1,7 @@
 use pqcrypto_sphincsplus::sphincssha2128fsimple::{
     detached_sign, keypair, verify_detached_signature, DetachedSignature, PublicKey, SecretKey,
 };
-use pqcrypto_traits::sign::DetachedSignature as _;
+use pqcrypto_traits::sign::{DetachedSignature as _, PublicKey as _, SecretKey as _};
 use std::error::Error;
 
 pub struct Sphincs;
@@ -24,4 +24,25 @@ impl Sphincs {
             false
         }
     }
+
+    /// Verifies a signature against a public key given as raw bytes.
+    pub fn verify_with_key_bytes(
+        message: &[u8],
+        signature_bytes: &[u8],
+        public_key: &[u8],
+    ) -> bool {
+        match Self::public_key_from_bytes(public_key) {
+            Some(pk) => Self::verify(message, signature_bytes, &pk),
+            None => false,
+        }
+    }
+
+    // Helper methods for byte conversion
+    pub fn public_key_from_bytes(bytes: &[u8]) -> Option<PublicKey> {
+        PublicKey::from_bytes(bytes).ok()
+    }
+
+    pub fn secret_key_from_bytes(bytes: &[u8]) -> Option<SecretKey> {
+        SecretKey::from_bytes(bytes).ok()
+    }
 }
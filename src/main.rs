@@ -6,21 +6,132 @@ mod network {
     pub mod p2p; // Import the p2p module from the network directory
 }
 mod cryptography;
+mod storage;
+mod worker;
 
 use std::sync::{Arc, Mutex};
 use tokio::task;
 
 #[tokio::main]
 async fn main() {
-    let blockchain = Arc::new(Mutex::new(blockchain::chain::Blockchain::new(
+    let genesis_path = std::env::var("QTC_GENESIS").unwrap_or_else(|_| "./genesis.json".into());
+    let genesis = match blockchain::genesis::GenesisConfig::load(&genesis_path) {
+        Ok(genesis) => genesis,
+        Err(e) => {
+            eprintln!("Failed to load genesis file {}: {}", genesis_path, e);
+            std::process::exit(1);
+        }
+    };
+    println!(
+        "Chain id: {} (genesis {})",
+        genesis.chain_id,
+        hex::encode(genesis.genesis_block().hash)
+    );
+
+    let node_key = match wallet::key_manager::NodeKey::load_or_generate("./node_key.json") {
+        Ok(key) => Arc::new(key),
+        Err(e) => {
+            eprintln!("Failed to load node key: {}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("Node proposer id: {}", node_key.id());
+
+    // Consensus engine: pos (default), pouw, or dev for a single-node chain
+    // whose blocks are proposed by QTC_DEV_AUTHORITY, or this node.
+    let engine: consensus::engine::EngineKind = match std::env::var("QTC_CONSENSUS") {
+        Ok(name) => match name.parse() {
+            Ok(engine) => engine,
+            Err(e) => {
+                eprintln!("Invalid QTC_CONSENSUS: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Err(_) => Default::default(),
+    };
+    let authority = std::env::var("QTC_DEV_AUTHORITY").unwrap_or_else(|_| node_key.id());
+    println!("Consensus engine: {}", engine.name());
+
+    let blockchain = match blockchain::chain::Blockchain::with_consensus(
         "./blockchain_data",
-    )));
+        genesis,
+        engine.build(&authority),
+    ) {
+        Ok(blockchain) => Arc::new(Mutex::new(blockchain)),
+        Err(e) => {
+            eprintln!("Failed to load blockchain: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Wallet keys, shared by the REST API and the worker
+    let wallets = match wallet::open_wallets("./wallets") {
+        Ok(wallets) => wallets,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Work on PoUW tasks for the wallet named by QTC_WORKER, if any
+    match worker::runner::WorkerConfig::from_env() {
+        Ok(Some(config)) => {
+            println!(
+                "Worker mode for {} (journal {})",
+                config.wallet, config.journal_path
+            );
+            let worker = match worker::runner::Worker::open(config) {
+                Ok(worker) => worker,
+                Err(e) => {
+                    eprintln!("Failed to open worker journal: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            task::spawn(worker::runner::run(
+                worker,
+                blockchain.clone(),
+                wallets.clone(),
+            ));
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Invalid worker configuration: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Propose blocks from the pending transactions whenever this node's key
+    // is due to propose, every QTC_BLOCK_INTERVAL_MS
+    let block_interval = match std::env::var("QTC_BLOCK_INTERVAL_MS") {
+        Ok(ms) => match ms.parse() {
+            Ok(ms) => ms,
+            Err(e) => {
+                eprintln!("Invalid QTC_BLOCK_INTERVAL_MS: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Err(_) => consensus::proposer::DEFAULT_BLOCK_INTERVAL_MS,
+    };
+    task::spawn(consensus::proposer::run(
+        blockchain.clone(),
+        node_key.clone(),
+        std::time::Duration::from_millis(block_interval),
+    ));
+
+    // Vote on blocks until a quorum finalizes them, taking other
+    // validators' votes through the REST API
+    let finality = Arc::new(Mutex::new(consensus::finality::Finality::new(node_key)));
+    task::spawn(consensus::finality::run(
+        finality.clone(),
+        blockchain.clone(),
+        std::time::Duration::from_millis(consensus::finality::FINALITY_TICK_MS),
+    ));
 
     // Start the REST API in a separate task
     let blockchain_api = blockchain.clone();
     task::spawn(async move {
         println!("Starting REST API on http://localhost:8080...");
-        rest::start_rest_api(blockchain_api).await;
+        rest::start_rest_api(blockchain_api, wallets, finality).await;
     });
 
     // Start the P2P network node
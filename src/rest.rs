@@ -1,88 +1,409 @@
+use crate::blockchain::block::TransactionKind;
 use crate::blockchain::chain::Blockchain;
+use crate::blockchain::state::{StateKey, ValidatorRegistration};
+use crate::consensus::bft::{QuorumCertificate, Vote};
+use crate::consensus::finality::Finality;
+use crate::consensus::pouw::{
+    Task, TaskCancel, TaskClaim, TaskCommit, TaskResult, TaskReveal, TaskSpec, TaskStatus,
+    TaskTypeSpec, WorkerInfo, WorkerRegistration, DEFAULT_CLAIM_BLOCKS,
+};
+use crate::consensus::slashing::DoubleSignEvidence;
+use crate::consensus::wasm;
 use crate::cryptography::kyber::KyberEncryption;
-use crate::cryptography::sphincs::Sphincs;
+use crate::cryptography::sha3::sha3_256;
 use crate::wallet;
 use hex;
 use pqcrypto_kyber::kyber512::{Ciphertext, SecretKey};
-use pqcrypto_sphincsplus::sphincssha2128fsimple::{
-    self, verify_detached_signature, PublicKey as SphincsPublicKey,
-};
 
-use pqcrypto_traits::{
-    kem::{Ciphertext as _, PublicKey as _, SecretKey as _, SharedSecret as _},
-    sign::{DetachedSignature as _, PublicKey as _},
-};
+use pqcrypto_traits::kem::{Ciphertext as _, PublicKey as _, SecretKey as _};
 use rocksdb::DB;
 use serde_json;
 use std::sync::{Arc, Mutex};
 use warp::Filter;
 
 type SharedBlockchain = Arc<Mutex<Blockchain>>;
+type Wallets = Arc<DB>;
+type SharedFinality = Arc<Mutex<Finality>>;
 
-pub async fn start_rest_api(blockchain: SharedBlockchain) {
+pub async fn start_rest_api(
+    blockchain: SharedBlockchain,
+    wallets: Wallets,
+    finality: SharedFinality,
+) {
     // Wallet creation
-    let wallet_create = warp::path!("wallet" / "create").and(warp::post()).map(|| {
-        let (public_key, _) = wallet::create_wallet();
-        warp::reply::json(&serde_json::json!({
-            "message": "Wallet created successfully!",
-            "public_key": public_key
-        }))
-    });
+    let wallet_create = warp::path!("wallet" / "create")
+        .and(warp::post())
+        .and(with_wallets(wallets.clone()))
+        .map(|wallets: Wallets| match wallet::create_wallet(&wallets) {
+            Ok((public_key, _)) => warp::reply::json(&serde_json::json!({
+                "message": "Wallet created successfully!",
+                "public_key": public_key
+            })),
+            Err(error) => warp::reply::json(&serde_json::json!({ "error": error })),
+        });
 
     // Sending transactions
     let wallet_send = warp::path!("wallet" / "send")
         .and(warp::post())
         .and(warp::body::json())
         .and(with_blockchain(blockchain.clone()))
-        .map(|body: serde_json::Value, blockchain: SharedBlockchain| {
-            let sender = body["sender"].as_str().unwrap_or_default();
-            let to = body["to"].as_str().unwrap_or_default();
-            let amount = body["amount"].as_u64().unwrap_or(0);
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let sender = body["sender"].as_str().unwrap_or_default();
+                let to = body["to"].as_str().unwrap_or_default();
+                let amount = body["amount"].as_u64().unwrap_or(0);
+
+                match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    sender,
+                    to,
+                    amount,
+                    TransactionKind::Transfer,
+                ) {
+                    Ok(tx_hash) => warp::reply::json(&serde_json::json!({
+                        "message": "Transaction queued for the next block",
+                        "tx_hash": hex::encode(tx_hash),
+                        "sender": sender,
+                        "to": to,
+                        "amount": amount
+                    })),
+                    Err(error) => warp::reply::json(&serde_json::json!({ "error": error })),
+                }
+            },
+        );
+
+    // Staking: moves funds from the wallet's balance to its stake
+    let stake = warp::path!("stake")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let wallet = body["wallet"].as_str().unwrap_or_default().to_string();
+                let amount = body["amount"].as_u64().unwrap_or(0);
+
+                match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &wallet,
+                    "",
+                    amount,
+                    TransactionKind::Stake,
+                ) {
+                    Ok(tx_hash) => warp::reply::json(&serde_json::json!({
+                        "message": "Stake queued for the next block",
+                        "tx_hash": hex::encode(tx_hash),
+                        "wallet": wallet,
+                        "amount": amount
+                    })),
+                    Err(error) => warp::reply::json(&serde_json::json!({ "error": error })),
+                }
+            },
+        );
+
+    // Unstaking
+    let unstake = warp::path!("unstake")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let wallet = body["wallet"].as_str().unwrap_or_default().to_string();
+                let amount = body["amount"].as_u64().unwrap_or(0);
+
+                match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &wallet,
+                    "",
+                    amount,
+                    TransactionKind::Unstake,
+                ) {
+                    Ok(tx_hash) => warp::reply::json(&serde_json::json!({
+                        "message": "Unstake queued for the next block",
+                        "tx_hash": hex::encode(tx_hash),
+                        "wallet": wallet,
+                        "amount": amount
+                    })),
+                    Err(error) => warp::reply::json(&serde_json::json!({ "error": error })),
+                }
+            },
+        );
+
+    // Delegating stake to a validator
+    let delegate = warp::path!("delegate")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let delegator = body["delegator"].as_str().unwrap_or_default().to_string();
+                let validator = body["validator"].as_str().unwrap_or_default().to_string();
+                let amount = body["amount"].as_u64().unwrap_or(0);
+
+                match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &delegator,
+                    &validator,
+                    amount,
+                    TransactionKind::Delegate,
+                ) {
+                    Ok(tx_hash) => warp::reply::json(&serde_json::json!({
+                        "message": "Delegation queued for the next block",
+                        "tx_hash": hex::encode(tx_hash),
+                        "delegator": delegator,
+                        "validator": validator,
+                        "amount": amount
+                    })),
+                    Err(error) => warp::reply::json(&serde_json::json!({ "error": error })),
+                }
+            },
+        );
+
+    // Withdrawing a delegation
+    let undelegate = warp::path!("undelegate")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let delegator = body["delegator"].as_str().unwrap_or_default().to_string();
+                let validator = body["validator"].as_str().unwrap_or_default().to_string();
+                let amount = body["amount"].as_u64().unwrap_or(0);
+
+                match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &delegator,
+                    &validator,
+                    amount,
+                    TransactionKind::Undelegate,
+                ) {
+                    Ok(tx_hash) => warp::reply::json(&serde_json::json!({
+                        "message": "Undelegation queued for the next block",
+                        "tx_hash": hex::encode(tx_hash),
+                        "delegator": delegator,
+                        "validator": validator,
+                        "amount": amount
+                    })),
+                    Err(error) => warp::reply::json(&serde_json::json!({ "error": error })),
+                }
+            },
+        );
+
+    // Registering a validator, signed with the operator's wallet key
+    let register_validator = warp::path!("validators" / "register")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let operator = body["operator"].as_str().unwrap_or_default().to_string();
+                let registration = ValidatorRegistration {
+                    consensus_key: body["consensus_key"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    name: body["name"].as_str().unwrap_or_default().to_string(),
+                };
+
+                let tx_hash = match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &operator,
+                    "",
+                    0,
+                    TransactionKind::ValidatorRegister(registration.clone()),
+                ) {
+                    Ok(hash) => hash,
+                    Err(error) => return warp::reply::json(&serde_json::json!({ "error": error })),
+                };
+
+                warp::reply::json(&serde_json::json!({
+                    "message": "Validator registration queued for the next block",
+                    "tx_hash": hex::encode(tx_hash),
+                    "operator": operator,
+                    "consensus_key": registration.consensus_key,
+                    "name": registration.name
+                }))
+            },
+        );
+
+    // Registered validators and the active set
+    let validators = warp::path!("validators")
+        .and(warp::get())
+        .and(with_blockchain(blockchain.clone()))
+        .map(|blockchain: SharedBlockchain| {
+            let blockchain = blockchain.lock().unwrap();
+            let staking = &blockchain.staking_state;
+            let params = &blockchain.genesis.consensus;
+            let height = blockchain.latest_block().header.height;
+
+            let mut registered: Vec<_> = staking.registry.iter().collect();
+            registered.sort_by_key(|(operator, _)| {
+                staking
+                    .validators
+                    .iter()
+                    .position(|v| v == *operator)
+                    .map_or((1, 0, *operator), |rank| (0, rank, *operator))
+            });
+            let validators: Vec<_> = registered
+                .into_iter()
+                .map(|(operator, info)| {
+                    let self_stake = staking.staked_balances.get(operator).cloned().unwrap_or(0);
+                    serde_json::json!({
+                        "operator": operator,
+                        "name": info.name,
+                        "consensus_key": info.consensus_key,
+                        "active": staking.validators.contains(operator),
+                        "self_stake": self_stake,
+                        "delegated": staking.delegated_to(operator),
+                        "voting_power": staking.voting_power(operator),
+                        "commission_bps": staking.get_commission(operator),
+                        "jailed_until": staking.jailed.get(operator),
+                        "missed_slots": staking.missed_slots.get(operator).cloned().unwrap_or(0)
+                    })
+                })
+                .collect();
 
-            wallet::send_transaction(blockchain, sender, to, amount);
             warp::reply::json(&serde_json::json!({
-                "message": "Transaction sent!",
-                "sender": sender,
-                "to": to,
-                "amount": amount
+                "height": height,
+                "next_update": (height / params.epoch_length + 1) * params.epoch_length,
+                "min_validator_stake": params.min_validator_stake,
+                "max_validators": params.max_validators,
+                "active_count": staking.validators.len(),
+                "next_proposer": blockchain.next_proposer(),
+                "validators": validators
             }))
         });
 
-    // Staking
-    let stake = warp::path!("stake")
+    // Setting a validator's commission, signed with the validator's wallet key
+    let commission = warp::path!("validators" / "commission")
         .and(warp::post())
         .and(warp::body::json())
         .and(with_blockchain(blockchain.clone()))
-        .map(|body: serde_json::Value, blockchain: SharedBlockchain| {
-            let wallet = body["wallet"].as_str().unwrap_or_default().to_string();
-            let amount = body["amount"].as_u64().unwrap_or(0);
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let validator = body["validator"].as_str().unwrap_or_default().to_string();
+                let commission_bps = body["commission_bps"].as_u64().unwrap_or(0);
 
-            let mut blockchain = blockchain.lock().unwrap();
-            blockchain.stake(wallet.clone(), amount);
+                let tx_hash = match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &validator,
+                    "",
+                    0,
+                    TransactionKind::SetCommission { commission_bps },
+                ) {
+                    Ok(hash) => hash,
+                    Err(error) => return warp::reply::json(&serde_json::json!({ "error": error })),
+                };
+
+                warp::reply::json(&serde_json::json!({
+                    "message": "Commission change queued for the next block",
+                    "tx_hash": hex::encode(tx_hash),
+                    "validator": validator,
+                    "commission_bps": commission_bps
+                }))
+            },
+        );
+
+    // Delegations made by a wallet
+    let wallet_delegations = warp::path!("staking" / "delegations")
+        .and(warp::get())
+        .and(warp::query::<serde_json::Value>())
+        .and(with_blockchain(blockchain.clone()))
+        .map(|query: serde_json::Value, blockchain: SharedBlockchain| {
+            let wallet = query["wallet"].as_str().unwrap_or_default().to_string();
+
+            let blockchain = blockchain.lock().unwrap();
+            let staking = &blockchain.staking_state;
+            let delegations = staking.delegations_of(&wallet);
+            let total = delegations.iter().map(|(_, amount)| amount).sum::<u64>();
+            let delegations: Vec<_> = delegations
+                .into_iter()
+                .map(|(validator, amount)| {
+                    serde_json::json!({
+                        "commission_bps": staking.get_commission(&validator),
+                        "validator": validator,
+                        "amount": amount
+                    })
+                })
+                .collect();
 
             warp::reply::json(&serde_json::json!({
-                "message": "Stake successful",
                 "wallet": wallet,
-                "amount": amount
+                "total": total,
+                "delegations": delegations
             }))
         });
 
-    // Unstaking
-    let unstake = warp::path!("unstake")
-        .and(warp::post())
-        .and(warp::body::json())
+    // Delegations to a validator
+    let validator_delegations = warp::path!("validators" / String / "delegations")
+        .and(warp::get())
         .and(with_blockchain(blockchain.clone()))
-        .map(|body: serde_json::Value, blockchain: SharedBlockchain| {
-            let wallet = body["wallet"].as_str().unwrap_or_default().to_string();
-            let amount = body["amount"].as_u64().unwrap_or(0);
+        .map(|validator: String, blockchain: SharedBlockchain| {
+            let blockchain = blockchain.lock().unwrap();
+            let staking = &blockchain.staking_state;
+            let delegations: Vec<_> = staking
+                .delegators_of(&validator)
+                .into_iter()
+                .map(|(delegator, amount)| {
+                    serde_json::json!({ "delegator": delegator, "amount": amount })
+                })
+                .collect();
 
-            let mut blockchain = blockchain.lock().unwrap();
-            let success = blockchain.unstake(wallet.clone(), amount);
+            warp::reply::json(&serde_json::json!({
+                "validator": validator,
+                "self_stake": staking.staked_balances.get(&validator).cloned().unwrap_or(0),
+                "delegated": staking.delegated_to(&validator),
+                "voting_power": staking.voting_power(&validator),
+                "commission_bps": staking.get_commission(&validator),
+                "delegations": delegations
+            }))
+        });
+
+    // Unstaked funds waiting to be released
+    let unbonding = warp::path!("staking" / "unbonding")
+        .and(warp::get())
+        .and(warp::query::<serde_json::Value>())
+        .and(with_blockchain(blockchain.clone()))
+        .map(|query: serde_json::Value, blockchain: SharedBlockchain| {
+            let wallet = query["wallet"].as_str().unwrap_or_default().to_string();
+
+            let blockchain = blockchain.lock().unwrap();
+            let height = blockchain.latest_block().header.height;
+            let entries = blockchain.get_unbonding(&wallet);
+            let total = entries
+                .iter()
+                .fold(0u64, |total, entry| total.saturating_add(entry.amount));
+
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|entry| {
+                    serde_json::json!({
+                        "amount": entry.amount,
+                        "release_height": entry.release_height,
+                        "blocks_remaining": entry.release_height.saturating_sub(height),
+                        "validator": entry.validator
+                    })
+                })
+                .collect();
 
             warp::reply::json(&serde_json::json!({
-                "message": if success { "Unstake successful" } else { "Unstake failed" },
                 "wallet": wallet,
-                "amount": amount
+                "height": height,
+                "total": total,
+                "entries": entries
             }))
         });
 
@@ -99,22 +420,232 @@ pub async fn start_rest_api(blockchain: SharedBlockchain) {
 
             warp::reply::json(&serde_json::json!({
                 "wallet": wallet,
-                "rewards": rewards
+                "accrued": rewards.accrued,
+                "claimed": rewards.claimed,
+                "pending": rewards.pending
             }))
         });
 
+    // Reporting a validator that signed two blocks at one height
+    let double_sign = warp::path!("evidence" / "double_sign")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let reporter = body["reporter"].as_str().unwrap_or_default().to_string();
+                let evidence: DoubleSignEvidence = match serde_json::from_value(body.clone()) {
+                    Ok(evidence) => evidence,
+                    Err(e) => {
+                        return warp::reply::json(&serde_json::json!({
+                            "error": format!("Invalid evidence: {}", e)
+                        }))
+                    }
+                };
+
+                let consensus_key = evidence.offender().to_string();
+                match wallet::submit_transaction(
+                    &wallets,
+                    blockchain.clone(),
+                    &reporter,
+                    "",
+                    0,
+                    TransactionKind::DoubleSign(evidence),
+                ) {
+                    Ok(tx_hash) => {
+                        let blockchain = blockchain.lock().unwrap();
+                        let offender = blockchain
+                            .staking_state
+                            .operator_of(&consensus_key)
+                            .unwrap_or_default()
+                            .to_string();
+                        warp::reply::json(&serde_json::json!({
+                            "message": "Evidence queued for the next block",
+                            "tx_hash": hex::encode(tx_hash),
+                            "offender": offender,
+                            "consensus_key": consensus_key
+                        }))
+                    }
+                    Err(error) => warp::reply::json(&serde_json::json!({ "error": error })),
+                }
+            },
+        );
+
     // Check all wallets
-    let wallets_route = warp::path("wallets").and(warp::get()).map(|| {
-        let db = DB::open_default("./wallets").expect("Failed to open wallet database");
-        let wallets: Vec<String> = db
-            .iterator(rocksdb::IteratorMode::Start)
-            .map(|item| match item {
-                Ok((key, _)) => String::from_utf8(key.to_vec()).unwrap(),
-                Err(_) => "Invalid key".to_string(),
-            })
-            .collect();
-        warp::reply::json(&serde_json::json!({ "wallets": wallets }))
-    });
+    let wallets_route = warp::path("wallets")
+        .and(warp::get())
+        .and(with_wallets(wallets.clone()))
+        .map(|db: Wallets| {
+            let wallets: Vec<String> = db
+                .iterator(rocksdb::IteratorMode::Start)
+                .map(|item| match item {
+                    Ok((key, _)) => String::from_utf8(key.to_vec()).unwrap(),
+                    Err(_) => "Invalid key".to_string(),
+                })
+                .collect();
+            warp::reply::json(&serde_json::json!({ "wallets": wallets }))
+        });
+
+    // Chain identity
+    let chain_info = warp::path!("chain" / "info")
+        .and(warp::get())
+        .and(with_blockchain(blockchain.clone()))
+        .map(|blockchain: SharedBlockchain| {
+            let blockchain = blockchain.lock().unwrap();
+            warp::reply::json(&serde_json::json!({
+                "chain_id": blockchain.genesis.chain_id,
+                "genesis_hash": hex::encode(blockchain.genesis_hash()),
+                "height": blockchain.latest_block().header.height,
+                "finalized_height": blockchain.finalized_height,
+                "engine": blockchain.consensus.kind().name(),
+                "total_supply": blockchain.accounts.total_supply,
+                "total_rewards_paid": blockchain.staking_state.total_rewards_paid,
+                "total_slashed": blockchain.staking_state.total_slashed,
+                "consensus": blockchain.genesis.consensus
+            }))
+        });
+
+    // Finalize a block with the quorum certificate its voting round produced
+    let commit_block = warp::path!("blocks" / "commit")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .map(|body: serde_json::Value, blockchain: SharedBlockchain| {
+            let qc: QuorumCertificate = match serde_json::from_value(body) {
+                Ok(qc) => qc,
+                Err(e) => {
+                    return warp::reply::json(&serde_json::json!({
+                        "error": format!("Invalid quorum certificate: {}", e)
+                    }))
+                }
+            };
+            let mut blockchain = blockchain.lock().unwrap();
+            match blockchain.finalize(qc) {
+                Ok(()) => warp::reply::json(&serde_json::json!({
+                    "message": "Block finalized",
+                    "finalized_height": blockchain.finalized_height
+                })),
+                Err(e) => warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
+            }
+        });
+
+    // A finality vote from another validator
+    let bft_vote = warp::path!("bft" / "votes")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_finality(finality.clone()))
+        .and(with_blockchain(blockchain.clone()))
+        .map(
+            |body: serde_json::Value, finality: SharedFinality, blockchain: SharedBlockchain| {
+                let vote: Vote = match serde_json::from_value(body) {
+                    Ok(vote) => vote,
+                    Err(e) => {
+                        return warp::reply::json(&serde_json::json!({
+                            "error": format!("Invalid vote: {}", e)
+                        }))
+                    }
+                };
+                let mut finality = finality.lock().unwrap();
+                let mut blockchain = blockchain.lock().unwrap();
+                match finality.on_vote(&mut blockchain, vote) {
+                    Ok(()) => warp::reply::json(&serde_json::json!({
+                        "message": "Vote accepted",
+                        "finalized_height": blockchain.finalized_height
+                    })),
+                    Err(e) => warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
+                }
+            },
+        );
+
+    // The finality round in progress, this node's votes in it and the
+    // certificate of the last block it finalized
+    let bft_round = warp::path!("bft" / "round")
+        .and(warp::get())
+        .and(with_finality(finality.clone()))
+        .map(|finality: SharedFinality| {
+            let finality = finality.lock().unwrap();
+            let round = finality.round();
+            warp::reply::json(&serde_json::json!({
+                "height": round.map(|round| round.height),
+                "round": round.map(|round| round.round),
+                "step": round.map(|round| round.step),
+                "votes": finality.votes(),
+                "last_commit": finality.last_commit()
+            }))
+        });
+
+    // Merkle inclusion proof for a transaction
+    let tx_proof = warp::path!("blocks" / u64 / "tx" / usize / "proof")
+        .and(warp::get())
+        .and(with_blockchain(blockchain.clone()))
+        .map(|height: u64, index: usize, blockchain: SharedBlockchain| {
+            let blockchain = blockchain.lock().unwrap();
+            match blockchain.transaction_proof(height, index) {
+                Some((block, tx, proof)) => warp::reply::json(&serde_json::json!({
+                    "height": height,
+                    "block_hash": hex::encode(block.hash),
+                    "header": block.header,
+                    "transaction": tx,
+                    "tx_hash": hex::encode(tx.hash()),
+                    "merkle_root": hex::encode(block.header.merkle_root),
+                    "proof": proof
+                })),
+                None => warp::reply::json(&serde_json::json!({
+                    "error": "Transaction not found"
+                })),
+            }
+        });
+
+    // Sparse Merkle proof for an entry in the state tree
+    let state_proof = warp::path!("state" / String / String / "proof")
+        .and(warp::get())
+        .and(with_blockchain(blockchain.clone()))
+        .map(|kind: String, id: String, blockchain: SharedBlockchain| {
+            let key = match kind.as_str() {
+                "accounts" => StateKey::Account(&id),
+                "stakes" => StateKey::Stake(&id),
+                "unbonding" => StateKey::Unbonding(&id),
+                "jailed" => StateKey::Jail(&id),
+                "commissions" => StateKey::Commission(&id),
+                "commission_raises" => StateKey::CommissionRaised(&id),
+                "validators" => StateKey::Validator(&id),
+                "rewards" => StateKey::Reward(&id),
+                "missed_slots" => StateKey::MissedSlots(&id),
+                "punished" => StateKey::Punished(&id),
+                "totals" => match id.as_str() {
+                    "total_supply" => StateKey::TotalSupply,
+                    "total_slashed" => StateKey::TotalSlashed,
+                    "total_rewards_paid" => StateKey::TotalRewardsPaid,
+                    _ => {
+                        return warp::reply::json(&serde_json::json!({
+                            "error": "Unknown total"
+                        }))
+                    }
+                },
+                "tasks" => StateKey::Task(&id),
+                "task_types" => StateKey::TaskType(&id),
+                "workers" => StateKey::Worker(&id),
+                _ => {
+                    return warp::reply::json(&serde_json::json!({
+                        "error": "Unknown state entry kind"
+                    }))
+                }
+            };
+            let blockchain = blockchain.lock().unwrap();
+            let (state_root, value, proof) = blockchain.state_proof(key);
+            let tip = blockchain.latest_block();
+            warp::reply::json(&serde_json::json!({
+                "key": hex::encode(key.encode()),
+                "included": value.is_some(),
+                "value": value.map(hex::encode),
+                "state_root": hex::encode(state_root),
+                "height": tip.header.height,
+                "block_hash": hex::encode(tip.hash),
+                "committed": state_root == tip.header.state_root,
+                "proof": proof
+            }))
+        });
 
     // Check all blocks
     let blocks_route = warp::path("blocks")
@@ -154,19 +685,8 @@ pub async fn start_rest_api(blockchain: SharedBlockchain) {
             let wallet = query["wallet"].as_str().unwrap_or_default().to_string();
             let blockchain = blockchain.lock().unwrap();
 
-            let spendable = blockchain
-                .chain
-                .iter()
-                .flat_map(|block| block.transactions.iter())
-                .fold(0, |balance, tx| {
-                    if tx.sender == wallet {
-                        balance - tx.amount
-                    } else if tx.receiver == wallet {
-                        balance + tx.amount
-                    } else {
-                        balance
-                    }
-                });
+            let account = blockchain.accounts.get(&wallet);
+            let spendable = account.balance;
 
             let staked = blockchain
                 .staking_state
@@ -174,12 +694,18 @@ pub async fn start_rest_api(blockchain: SharedBlockchain) {
                 .get(&wallet)
                 .cloned()
                 .unwrap_or(0);
+            let unbonding = blockchain
+                .get_unbonding(&wallet)
+                .iter()
+                .fold(0u64, |total, entry| total.saturating_add(entry.amount));
 
             warp::reply::json(&serde_json::json!({
                 "wallet": wallet,
                 "spendable_balance": spendable,
+                "nonce": account.nonce,
                 "staked_balance": staked,
-                "total_balance": spendable + staked,
+                "unbonding_balance": unbonding,
+                "total_balance": spendable.saturating_add(staked).saturating_add(unbonding),
             }))
         });
 
@@ -188,202 +714,693 @@ pub async fn start_rest_api(blockchain: SharedBlockchain) {
         .and(warp::post())
         .and(warp::body::json())
         .and(with_blockchain(blockchain.clone()))
-        .map(|body: serde_json::Value, blockchain: SharedBlockchain| {
-            println!("Received request to add task: {:?}", body);
-
-            // Extract task data
-            let id = body["id"].as_str().unwrap_or_default().to_string();
-            let description = body["description"].as_str().unwrap_or_default().to_string();
-            let _data = body["data"].as_str().unwrap_or_default().as_bytes(); // Prefix with `_` to suppress warning
-            let reward = body["reward"].as_u64().unwrap_or(0);
-
-            // Kyber: Generate key pair and encrypt data
-            let (public_key, secret_key) = KyberEncryption::generate_keypair();
-            let (_shared_secret, ciphertext) =
-                KyberEncryption::encrypt(&public_key).expect("Encryption failed");
-
-            // SPHINCS+: Generate key pair and sign task metadata
-            let (sphincs_public_key, sphincs_secret_key) =
-                Sphincs::generate_keypair().expect("Failed to generate SPHINCS+ keypair");
-            let task_metadata = format!("{}:{}", id, description);
-            let signature = Sphincs::sign(task_metadata.as_bytes(), &sphincs_secret_key)
-                .expect("Failed to sign metadata");
-
-            // Convert keys and data to hex for storage
-            let secret_key_bytes = SecretKey::as_bytes(&secret_key);
-            let secret_key_hex = hex::encode(secret_key_bytes);
-
-            let ciphertext_bytes = Ciphertext::as_bytes(&ciphertext);
-            let encrypted_data_hex = hex::encode(ciphertext_bytes);
-
-            let sphincs_public_key_bytes = SphincsPublicKey::as_bytes(&sphincs_public_key);
-            let sphincs_public_key_hex = hex::encode(sphincs_public_key_bytes);
-            let sphincs_signature_hex = hex::encode(&signature);
-
-            // Store the task in the blockchain
-            let mut blockchain = blockchain.lock().unwrap();
-            blockchain.add_task(
-                id.clone(),
-                description.clone(),
-                encrypted_data_hex.clone().into_bytes(),
-                reward,
-                sphincs_public_key_bytes.to_vec(),
-                signature.clone(), // Clone here to avoid move errors
-            );
-
-            // Respond with task details
-            warp::reply::json(&serde_json::json!({
-                "message": "Task added successfully",
-                "task_id": id,
-                "public_key": hex::encode(&public_key.as_bytes()),
-                "secret_key": secret_key_hex,
-                "sphincs_public_key": sphincs_public_key_hex,
-                "sphincs_signature": sphincs_signature_hex,
-                "encrypted_data": encrypted_data_hex
-            }))
-        });
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                // Extract task data
+                let creator = body["creator"].as_str().unwrap_or_default().to_string();
+                let id = body["id"].as_str().unwrap_or_default().to_string();
+                let description = body["description"].as_str().unwrap_or_default().to_string();
+                let data = body["data"].as_str().unwrap_or_default();
+                let task_type = body["task_type"].as_str().map(str::to_string);
+                let reward = body["reward"].as_u64().unwrap_or(0);
+                let verifier_fee = body["verifier_fee"].as_u64().unwrap_or(0);
+                let expected_result = match body["expected_result_hash"].as_str() {
+                    Some(hash) => match hex::decode(hash).ok().and_then(|b| b.try_into().ok()) {
+                        Some(hash) => Some(hash),
+                        None => {
+                            return warp::reply::json(&serde_json::json!({
+                                "error": "expected_result_hash must be a hex-encoded SHA3-256 hash"
+                            }))
+                        }
+                    },
+                    None => None,
+                };
+                let claim_blocks = body["claim_blocks"]
+                    .as_u64()
+                    .unwrap_or(DEFAULT_CLAIM_BLOCKS);
+                let replicas = match body["replicas"].as_u64().unwrap_or(1).try_into() {
+                    Ok(replicas) => replicas,
+                    Err(_) => {
+                        return warp::reply::json(&serde_json::json!({
+                            "error": "replicas must be a 32-bit number"
+                        }))
+                    }
+                };
+                let ttl_blocks = body["ttl_blocks"].as_u64();
+                let min_reputation = body["min_reputation"].as_u64().unwrap_or(0);
+
+                // Kyber: Generate key pair and encrypt data
+                let (public_key, secret_key) = KyberEncryption::generate_keypair();
+                let (_shared_secret, ciphertext) =
+                    KyberEncryption::encrypt(&public_key).expect("Encryption failed");
+
+                // Convert keys and data to hex for storage
+                let secret_key_bytes = SecretKey::as_bytes(&secret_key);
+                let secret_key_hex = hex::encode(secret_key_bytes);
+
+                let ciphertext_bytes = Ciphertext::as_bytes(&ciphertext);
+                let encrypted_data_hex = hex::encode(ciphertext_bytes);
+
+                // A typed task's program runs on the data as given
+                let data = match &task_type {
+                    Some(_) => match hex::decode(data) {
+                        Ok(data) => data,
+                        Err(_) => {
+                            return warp::reply::json(&serde_json::json!({
+                                "error": "data must be hex-encoded for a typed task"
+                            }))
+                        }
+                    },
+                    None => encrypted_data_hex.clone().into_bytes(),
+                };
+
+                // The creator signs the task and funds its escrow
+                let spec = TaskSpec {
+                    id: id.clone(),
+                    description,
+                    data,
+                    expected_result,
+                    task_type,
+                    replicas,
+                    claim_blocks,
+                    ttl_blocks,
+                    verifier_fee,
+                    min_reputation,
+                };
+                let escrow = match reward.checked_add(verifier_fee) {
+                    Some(escrow) => escrow,
+                    None => {
+                        return warp::reply::json(&serde_json::json!({
+                            "error": "Reward plus verifier fee overflows"
+                        }))
+                    }
+                };
+                let tx_hash = match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &creator,
+                    "",
+                    escrow,
+                    TransactionKind::TaskCreate(spec),
+                ) {
+                    Ok(hash) => hash,
+                    Err(error) => return warp::reply::json(&serde_json::json!({ "error": error })),
+                };
+
+                // Respond with task details
+                warp::reply::json(&serde_json::json!({
+                    "message": "Task queued for the next block",
+                    "tx_hash": hex::encode(tx_hash),
+                    "task_id": id,
+                    "creator": creator,
+                    "escrow": escrow,
+                    "public_key": hex::encode(&public_key.as_bytes()),
+                    "secret_key": secret_key_hex,
+                    "encrypted_data": encrypted_data_hex
+                }))
+            },
+        );
 
-    // Complete Task
-    let complete_task = warp::path!("tasks" / "complete")
+    // Claim a task for a worker
+    let claim_task = warp::path!("tasks" / "claim")
         .and(warp::post())
         .and(warp::body::json())
         .and(with_blockchain(blockchain.clone()))
-        .map(|body: serde_json::Value, blockchain: SharedBlockchain| {
-            println!("Received request to complete task: {:?}", body);
-
-            // Extract task data
-            let id = body["id"].as_str().unwrap_or_default().to_string();
-            let encrypted_result = body["result"].as_str().unwrap_or_default();
-            let secret_key_hex = body["secret_key"].as_str().unwrap_or_default();
-
-            // Decode encrypted result and secret key
-            let ciphertext_bytes = hex::decode(encrypted_result).expect("Invalid ciphertext");
-            let secret_key_bytes = hex::decode(secret_key_hex).expect("Invalid secret key");
-
-            // Convert bytes back to Kyber types using proper methods
-            let ciphertext = KyberEncryption::ciphertext_from_bytes(&ciphertext_bytes)
-                .expect("Invalid ciphertext format");
-            let secret_key = KyberEncryption::secret_key_from_bytes(&secret_key_bytes)
-                .expect("Invalid secret key format");
-
-            // Kyber: Decrypt result
-            let shared_secret =
-                KyberEncryption::decrypt(&ciphertext, &secret_key).expect("Decryption failed");
-
-            // Get task details from blockchain
-            let blockchain_guard = blockchain.lock().unwrap();
-            let task = blockchain_guard
-                .get_task(&id)
-                .expect("Task not found")
-                .clone(); // Clone it before dropping the lock
-            drop(blockchain_guard); // Now it's safe to release the lock
-            let description = task.description.clone();
-
-            // Recreate task metadata for SPHINCS+ signature verification
-            let task_metadata = format!("{}:{}", id, description);
-
-            // Decode SPHINCS+ public key and signature from the task
-            let sphincs_public_key_bytes =
-                hex::decode(&task.sphincs_public_key).expect("Invalid SPHINCS+ public key");
-            let sphincs_signature =
-                hex::decode(&task.signature).expect("Invalid SPHINCS+ signature");
-
-            // Convert bytes to SPHINCS+ types using proper methods
-            let detached_signature =
-                sphincssha2128fsimple::DetachedSignature::from_bytes(&sphincs_signature)
-                    .expect("Invalid signature format");
-            let sphincs_public_key =
-                sphincssha2128fsimple::PublicKey::from_bytes(&sphincs_public_key_bytes)
-                    .expect("Invalid public key format");
-
-            // Verify SPHINCS+ signature using detached signature
-            if !verify_detached_signature(
-                &detached_signature,
-                &task_metadata.as_bytes(),
-                &sphincs_public_key,
-            )
-            .is_ok()
-            {
-                return warp::reply::json(&serde_json::json!({
-                    "error": "Invalid SPHINCS+ signature"
-                }));
-            }
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let worker = body["worker"].as_str().unwrap_or_default().to_string();
+                let id = body["id"].as_str().unwrap_or_default().to_string();
 
-            // Mark task as complete in the blockchain
-            let mut blockchain = blockchain.lock().unwrap();
-            if let Some(task) = blockchain.complete_task(&id) {
+                let claim = TaskClaim {
+                    task_id: id.clone(),
+                };
+                let tx_hash = match wallet::submit_transaction(
+                    &wallets,
+                    blockchain.clone(),
+                    &worker,
+                    "",
+                    0,
+                    TransactionKind::TaskClaim(claim),
+                ) {
+                    Ok(hash) => hash,
+                    Err(error) => return warp::reply::json(&serde_json::json!({ "error": error })),
+                };
+
+                let blockchain = blockchain.lock().unwrap();
+                let task = match blockchain.get_task(&id) {
+                    Some(task) => task,
+                    None => return task_missing(),
+                };
                 warp::reply::json(&serde_json::json!({
-                    "message": "Task completed successfully",
-                    "task": task,
-                    "decrypted_data": String::from_utf8(shared_secret.as_bytes().to_vec())
-                        .unwrap_or_default()
+                    "message": "Claim queued for the next block",
+                    "tx_hash": hex::encode(tx_hash),
+                    "task_id": id,
+                    "worker": worker,
+                    "replicated": task.is_replicated(),
+                    "claim_blocks": task.claim_blocks
                 }))
-            } else {
-                warp::reply::json(&serde_json::json!({ "error": "Task not found" }))
-            }
-        });
+            },
+        );
 
-    // Get Pending Tasks
-    let get_pending_tasks = warp::path!("tasks" / "pending")
+    // Submit a worker's result for a task
+    let submit_result = warp::path!("tasks" / "submit")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let worker = body["worker"].as_str().unwrap_or_default().to_string();
+                let id = body["id"].as_str().unwrap_or_default().to_string();
+                let result = match hex::decode(body["result"].as_str().unwrap_or_default()) {
+                    Ok(result) => result,
+                    Err(_) => {
+                        return warp::reply::json(&serde_json::json!({
+                            "error": "Result must be hex-encoded"
+                        }))
+                    }
+                };
+                let result_hash = sha3_256(&result);
+
+                let submission = TaskResult {
+                    task_id: id.clone(),
+                    result,
+                };
+                let tx_hash = match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &worker,
+                    "",
+                    0,
+                    TransactionKind::TaskResult(submission),
+                ) {
+                    Ok(hash) => hash,
+                    Err(error) => return warp::reply::json(&serde_json::json!({ "error": error })),
+                };
+
+                warp::reply::json(&serde_json::json!({
+                    "message": "Result queued; it is verified in the block after the one that includes it",
+                    "tx_hash": hex::encode(tx_hash),
+                    "task_id": id,
+                    "worker": worker,
+                    "result_hash": hex::encode(result_hash)
+                }))
+            },
+        );
+
+    // Commit to a result for a replicated task
+    let commit_result = warp::path!("tasks" / "commit")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let worker = body["worker"].as_str().unwrap_or_default().to_string();
+                let id = body["id"].as_str().unwrap_or_default().to_string();
+                let commitment = match hex::decode(body["commitment"].as_str().unwrap_or_default())
+                    .ok()
+                    .and_then(|bytes| bytes.try_into().ok())
+                {
+                    Some(commitment) => commitment,
+                    None => {
+                        return warp::reply::json(&serde_json::json!({
+                            "error": "commitment must be a hex-encoded SHA3-256 hash"
+                        }))
+                    }
+                };
+
+                let commit = TaskCommit {
+                    task_id: id.clone(),
+                    commitment,
+                };
+                let tx_hash = match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &worker,
+                    "",
+                    0,
+                    TransactionKind::TaskCommit(commit),
+                ) {
+                    Ok(hash) => hash,
+                    Err(error) => return warp::reply::json(&serde_json::json!({ "error": error })),
+                };
+
+                warp::reply::json(&serde_json::json!({
+                    "message": "Commitment queued for the next block",
+                    "tx_hash": hex::encode(tx_hash),
+                    "task_id": id,
+                    "worker": worker
+                }))
+            },
+        );
+
+    // Reveal the result behind a commitment
+    let reveal_result = warp::path!("tasks" / "reveal")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let worker = body["worker"].as_str().unwrap_or_default().to_string();
+                let id = body["id"].as_str().unwrap_or_default().to_string();
+                let (result, salt) = match (
+                    hex::decode(body["result"].as_str().unwrap_or_default()),
+                    hex::decode(body["salt"].as_str().unwrap_or_default()),
+                ) {
+                    (Ok(result), Ok(salt)) => (result, salt),
+                    _ => {
+                        return warp::reply::json(&serde_json::json!({
+                            "error": "Result and salt must be hex-encoded"
+                        }))
+                    }
+                };
+                let result_hash = sha3_256(&result);
+
+                let reveal = TaskReveal {
+                    task_id: id.clone(),
+                    result,
+                    salt,
+                };
+                let tx_hash = match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &worker,
+                    "",
+                    0,
+                    TransactionKind::TaskReveal(reveal),
+                ) {
+                    Ok(hash) => hash,
+                    Err(error) => return warp::reply::json(&serde_json::json!({ "error": error })),
+                };
+
+                warp::reply::json(&serde_json::json!({
+                    "message": "Reveal queued for the next block",
+                    "tx_hash": hex::encode(tx_hash),
+                    "task_id": id,
+                    "worker": worker,
+                    "result_hash": hex::encode(result_hash)
+                }))
+            },
+        );
+
+    // Registered task types
+    let task_types = warp::path!("tasks" / "types")
         .and(warp::get())
         .and(with_blockchain(blockchain.clone()))
         .map(|blockchain: SharedBlockchain| {
             let blockchain = blockchain.lock().unwrap();
-            let tasks: Vec<_> = blockchain
-                .get_pending_tasks()
+            let mut types: Vec<_> = blockchain.task_queue.task_types.values().collect();
+            types.sort_by(|a, b| a.name.cmp(&b.name));
+            let types: Vec<_> = types
                 .into_iter()
-                .map(|t| {
+                .map(|task_type| {
                     serde_json::json!({
-                        "id": t.id,
-                        "description": t.description,
-                        "reward": t.reward
+                        "name": task_type.name,
+                        "owner": task_type.owner,
+                        "code_hash": hex::encode(task_type.code_hash),
+                        "code_size": task_type.code.len(),
+                        "fuel_limit": task_type.fuel_limit
+                    })
+                })
+                .collect();
+            warp::reply::json(&types)
+        });
+
+    // Register a task type backed by a WebAssembly program
+    let register_task_type = warp::path!("tasks" / "types" / "register")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let owner = body["owner"].as_str().unwrap_or_default().to_string();
+                let name = body["name"].as_str().unwrap_or_default().to_string();
+                let fuel_limit = body["fuel_limit"].as_u64().unwrap_or(0);
+                let code = match hex::decode(body["code"].as_str().unwrap_or_default()) {
+                    Ok(code) => code,
+                    Err(_) => {
+                        return warp::reply::json(&serde_json::json!({
+                            "error": "code must be a hex-encoded WebAssembly module"
+                        }))
+                    }
+                };
+                let code_hash = sha3_256(&code);
+                let spec = TaskTypeSpec {
+                    name: name.clone(),
+                    code,
+                    fuel_limit,
+                };
+
+                let tx_hash = match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &owner,
+                    "",
+                    0,
+                    TransactionKind::TaskTypeRegister(spec),
+                ) {
+                    Ok(hash) => hash,
+                    Err(error) => return warp::reply::json(&serde_json::json!({ "error": error })),
+                };
+
+                warp::reply::json(&serde_json::json!({
+                    "message": "Task type registration queued for the next block",
+                    "tx_hash": hex::encode(tx_hash),
+                    "name": name,
+                    "owner": owner,
+                    "code_hash": hex::encode(code_hash),
+                    "fuel_limit": fuel_limit
+                }))
+            },
+        );
+
+    // Run a typed task's program locally, as a worker would
+    let execute_task = warp::path!("tasks" / "execute")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and_then(
+            |body: serde_json::Value, blockchain: SharedBlockchain| async move {
+                let id = body["id"].as_str().unwrap_or_default().to_string();
+                let (task_type, data) = {
+                    let blockchain = blockchain.lock().unwrap();
+                    let task = match blockchain.get_task(&id) {
+                        Some(task) => task,
+                        None => return Ok::<_, warp::Rejection>(task_missing()),
+                    };
+                    let task_type = task
+                        .task_type
+                        .as_deref()
+                        .and_then(|name| blockchain.get_task_type(name));
+                    match task_type {
+                        Some(task_type) => (task_type.clone(), task.data.clone()),
+                        None => {
+                            return Ok(warp::reply::json(&serde_json::json!({
+                                "error": "Task has no type to execute"
+                            })))
+                        }
+                    }
+                };
+
+                // The chain is not locked while the program runs, and the run is
+                // kept off the async workers so a long program cannot stall them
+                let code = task_type.code.clone();
+                let fuel_limit = task_type.fuel_limit;
+                let execution =
+                    tokio::task::spawn_blocking(move || wasm::execute(&code, &data, fuel_limit))
+                        .await;
+                Ok(match execution {
+                    Ok(Ok(execution)) => warp::reply::json(&serde_json::json!({
+                        "task_id": id,
+                        "task_type": task_type.name,
+                        "result": hex::encode(&execution.output),
+                        "result_hash": hex::encode(sha3_256(&execution.output)),
+                        "fuel_used": execution.fuel_used
+                    })),
+                    Ok(Err(error)) => warp::reply::json(&serde_json::json!({
+                        "error": error.to_string()
+                    })),
+                    Err(error) => warp::reply::json(&serde_json::json!({
+                        "error": format!("Task execution failed: {}", error)
+                    })),
+                })
+            },
+        );
+
+    // Workers flagged for disagreeing with the majority
+    let dissenters = warp::path!("tasks" / "dissenters")
+        .and(warp::get())
+        .and(with_blockchain(blockchain.clone()))
+        .map(|blockchain: SharedBlockchain| {
+            let blockchain = blockchain.lock().unwrap();
+            warp::reply::json(&blockchain.task_queue.dissent_counts())
+        });
+
+    // Cancel a task no worker is busy with
+    let cancel_task = warp::path!("tasks" / "cancel")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let creator = body["creator"].as_str().unwrap_or_default().to_string();
+                let id = body["id"].as_str().unwrap_or_default().to_string();
+                let cancel = TaskCancel {
+                    task_id: id.clone(),
+                };
+                let tx_hash = match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &creator,
+                    "",
+                    0,
+                    TransactionKind::TaskCancel(cancel),
+                ) {
+                    Ok(hash) => hash,
+                    Err(error) => return warp::reply::json(&serde_json::json!({ "error": error })),
+                };
+                warp::reply::json(&serde_json::json!({
+                    "message": "Cancellation queued; the escrow is refunded in the block that includes it",
+                    "tx_hash": hex::encode(tx_hash),
+                    "task_id": id,
+                    "creator": creator
+                }))
+            },
+        );
+
+    // Rejected submissions
+    let rejected_results = warp::path!("tasks" / "rejected")
+        .and(warp::get())
+        .and(with_blockchain(blockchain.clone()))
+        .map(|blockchain: SharedBlockchain| {
+            let blockchain = blockchain.lock().unwrap();
+            let rejections: Vec<_> = blockchain
+                .task_queue
+                .tasks
+                .values()
+                .flat_map(|task| {
+                    task.rejections.iter().map(move |rejection| {
+                        serde_json::json!({
+                            "task_id": task.id,
+                            "worker": rejection.worker,
+                            "result_hash": hex::encode(rejection.result_hash),
+                            "height": rejection.height,
+                            "reason": rejection.reason,
+                            "message": rejection.reason.to_string()
+                        })
                     })
                 })
                 .collect();
+            warp::reply::json(&rejections)
+        });
 
-            warp::reply::json(&tasks)
+    // Get Pending Tasks
+    let get_pending_tasks = warp::path!("tasks" / "pending")
+        .and(warp::get())
+        .and(warp::query::<serde_json::Value>())
+        .and(with_blockchain(blockchain.clone()))
+        .map(|query: serde_json::Value, blockchain: SharedBlockchain| {
+            list_tasks(&blockchain.lock().unwrap(), &query, false)
         });
 
     // Get Completed Tasks
     let get_completed_tasks = warp::path!("tasks" / "completed")
         .and(warp::get())
+        .and(warp::query::<serde_json::Value>())
         .and(with_blockchain(blockchain.clone()))
-        .map(|blockchain: SharedBlockchain| {
+        .map(|query: serde_json::Value, blockchain: SharedBlockchain| {
+            list_tasks(&blockchain.lock().unwrap(), &query, true)
+        });
+
+    // Registered workers, best reputation first
+    let workers = warp::path!("workers")
+        .and(warp::get())
+        .and(warp::query::<serde_json::Value>())
+        .and(with_blockchain(blockchain.clone()))
+        .map(|query: serde_json::Value, blockchain: SharedBlockchain| {
             let blockchain = blockchain.lock().unwrap();
-            let tasks: Vec<_> = blockchain
+            let capability = query["capability"].as_str();
+            let mut workers: Vec<&WorkerInfo> = blockchain
                 .task_queue
-                .tasks
+                .workers
                 .values()
-                .filter(|task| task.is_complete)
-                .map(|task| {
+                .filter(|info| info.active)
+                .filter(|info| {
+                    capability.is_none_or(|capability| {
+                        info.capabilities.iter().any(|name| name == capability)
+                    })
+                })
+                .collect();
+            workers.sort_by(|a, b| {
+                b.reputation()
+                    .cmp(&a.reputation())
+                    .then_with(|| a.address.cmp(&b.address))
+            });
+            let workers: Vec<_> = workers.into_iter().map(worker_summary).collect();
+            warp::reply::json(&workers)
+        });
+
+    // A worker's registry entry and the outcomes of its latest tasks
+    let worker = warp::path!("workers" / String)
+        .and(warp::get())
+        .and(with_blockchain(blockchain.clone()))
+        .map(|address: String, blockchain: SharedBlockchain| {
+            let blockchain = blockchain.lock().unwrap();
+            let info = match blockchain.get_worker(&address) {
+                Some(info) => info,
+                None => {
+                    return warp::reply::json(&serde_json::json!({
+                        "error": "Worker is not registered"
+                    }))
+                }
+            };
+            let history: Vec<_> = info
+                .history
+                .iter()
+                .rev()
+                .map(|event| {
                     serde_json::json!({
-                        "id": task.id,
-                        "description": task.description,
-                        "reward": task.reward,
-                        "is_complete": task.is_complete,
-                        "sphincs_public_key": hex::encode(&task.sphincs_public_key),
-                        "sphincs_signature": hex::encode(&task.signature)
+                        "task_id": event.task_id,
+                        "height": event.height,
+                        "outcome": event.outcome,
+                        "reason": event.reason,
+                        "message": event.reason.map(|reason| reason.to_string())
                     })
                 })
                 .collect();
-            warp::reply::json(&tasks)
+            let mut summary = worker_summary(info);
+            summary["sphincs_public_key"] = hex::encode(&info.sphincs_public_key).into();
+            summary["history"] = history.into();
+            warp::reply::json(&summary)
         });
 
+    // Enter a worker in the registry, bonding optional collateral
+    let register_worker = warp::path!("workers" / "register")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let worker = body["worker"].as_str().unwrap_or_default().to_string();
+                let collateral = body["collateral"].as_u64().unwrap_or(0);
+                let capabilities: Vec<String> = body["capabilities"]
+                    .as_array()
+                    .map(|names| {
+                        names
+                            .iter()
+                            .filter_map(|name| name.as_str().map(str::to_string))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                let registration = WorkerRegistration { capabilities };
+                let tx_hash = match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &worker,
+                    "",
+                    collateral,
+                    TransactionKind::WorkerRegister(registration),
+                ) {
+                    Ok(hash) => hash,
+                    Err(error) => return warp::reply::json(&serde_json::json!({ "error": error })),
+                };
+
+                warp::reply::json(&serde_json::json!({
+                    "message": "Worker registration queued for the next block",
+                    "tx_hash": hex::encode(tx_hash),
+                    "worker": worker,
+                    "collateral": collateral
+                }))
+            },
+        );
+
+    // Take a worker out of the registry, refunding its collateral
+    let unregister_worker = warp::path!("workers" / "unregister")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_blockchain(blockchain.clone()))
+        .and(with_wallets(wallets.clone()))
+        .map(
+            |body: serde_json::Value, blockchain: SharedBlockchain, wallets: Wallets| {
+                let worker = body["worker"].as_str().unwrap_or_default().to_string();
+                let collateral = {
+                    let blockchain = blockchain.lock().unwrap();
+                    blockchain
+                        .get_worker(&worker)
+                        .map_or(0, |info| info.collateral)
+                };
+                let tx_hash = match wallet::submit_transaction(
+                    &wallets,
+                    blockchain,
+                    &worker,
+                    "",
+                    0,
+                    TransactionKind::WorkerUnregister,
+                ) {
+                    Ok(hash) => hash,
+                    Err(error) => return warp::reply::json(&serde_json::json!({ "error": error })),
+                };
+                warp::reply::json(&serde_json::json!({
+                    "message": "Unregistration queued; the collateral is refunded in the block that includes it",
+                    "tx_hash": hex::encode(tx_hash),
+                    "worker": worker,
+                    "refunded": collateral
+                }))
+            },
+        );
+
+    // Task routes are combined on their own to keep the filter types shallow
+    let task_routes = add_task
+        .or(claim_task)
+        .or(submit_result)
+        .or(commit_result)
+        .or(reveal_result)
+        .or(cancel_task)
+        .or(dissenters)
+        .or(rejected_results)
+        .or(task_types)
+        .or(register_task_type)
+        .or(execute_task)
+        .or(get_pending_tasks)
+        .or(get_completed_tasks);
+    let worker_routes = workers.or(register_worker).or(unregister_worker).or(worker);
+
     // Run the server
     warp::serve(
         wallet_create
             .or(wallet_send)
             .or(stake)
             .or(unstake)
+            .or(delegate)
+            .or(undelegate)
+            .or(register_validator)
+            .or(validators)
+            .or(commission)
+            .or(wallet_delegations)
+            .or(validator_delegations)
+            .or(unbonding)
             .or(rewards)
+            .or(double_sign)
             .or(wallets_route)
+            .or(chain_info)
+            .or(commit_block)
+            .or(bft_vote)
+            .or(bft_round)
+            .or(tx_proof)
+            .or(state_proof)
             .or(blocks_route)
             .or(transactions)
             .or(wallet_balance)
-            .or(add_task)
-            .or(complete_task)
-            .or(get_pending_tasks)
-            .or(get_completed_tasks),
+            .or(task_routes)
+            .or(worker_routes),
     )
     .run(([0, 0, 0, 0], 8080))
     .await;
@@ -394,3 +1411,91 @@ fn with_blockchain(
 ) -> impl Filter<Extract = (SharedBlockchain,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || blockchain.clone())
 }
+
+fn with_finality(
+    finality: SharedFinality,
+) -> impl Filter<Extract = (SharedFinality,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || finality.clone())
+}
+
+fn with_wallets(
+    wallets: Wallets,
+) -> impl Filter<Extract = (Wallets,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || wallets.clone())
+}
+
+/// Reply for a task that is not on the chain.
+fn task_missing() -> warp::reply::Json {
+    warp::reply::json(&serde_json::json!({ "error": "Task does not exist" }))
+}
+
+/// A worker's registry entry without its history.
+fn worker_summary(info: &WorkerInfo) -> serde_json::Value {
+    serde_json::json!({
+        "address": info.address,
+        "capabilities": info.capabilities,
+        "collateral": info.collateral,
+        "active": info.active,
+        "registered_at": info.registered_at,
+        "reputation": info.reputation(),
+        "verified": info.verified,
+        "rejected": info.rejected,
+        "expired": info.expired
+    })
+}
+
+/// Tasks that are finished (`finished`) or still pending, sorted by id and
+/// narrowed to the status named in the `status` query parameter, if any.
+fn list_tasks(
+    blockchain: &Blockchain,
+    query: &serde_json::Value,
+    finished: bool,
+) -> warp::reply::Json {
+    let status = match query["status"].as_str().map(str::parse::<TaskStatus>) {
+        Some(Ok(status)) if status.is_final() != finished => {
+            return warp::reply::json(&serde_json::json!({
+                "error": format!("A {} task is not listed here", status)
+            }))
+        }
+        Some(Err(error)) => return warp::reply::json(&serde_json::json!({ "error": error })),
+        Some(Ok(status)) => Some(status),
+        None => None,
+    };
+    let mut tasks: Vec<&Task> = if finished {
+        blockchain
+            .task_queue
+            .tasks
+            .values()
+            .filter(|task| task.status.is_final())
+            .collect()
+    } else {
+        blockchain.get_pending_tasks()
+    };
+    tasks.retain(|task| status.is_none_or(|status| task.status == status));
+    tasks.sort_by(|a, b| a.id.cmp(&b.id));
+    let tasks: Vec<_> = tasks
+        .into_iter()
+        .map(|task| {
+            serde_json::json!({
+                "id": task.id,
+                "description": task.description,
+                "task_type": task.task_type,
+                "min_reputation": task.min_reputation,
+                "reward": task.reward,
+                "creator": task.creator,
+                "verifier_fee": task.verifier_fee,
+                "escrow": task.escrow,
+                "status": task.status,
+                "replicas": task.replicas,
+                "assignments": task.assignments,
+                "reveal_deadline": task.reveal_deadline,
+                "accepted_workers": task.accepted_workers(),
+                "expires_at": task.expires_at,
+                "result_hash": task.result_hash.map(hex::encode),
+                "sphincs_public_key": hex::encode(&task.sphincs_public_key),
+                "sphincs_signature": hex::encode(&task.signature)
+            })
+        })
+        .collect();
+    warp::reply::json(&tasks)
+}
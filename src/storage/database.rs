@@ -1,28 +1,341 @@
-use rocksdb::{DB, Options};
 use crate::blockchain::block::Block;
+use crate::blockchain::state::{Account, AccountState, StakingState, StateSnapshot};
+use crate::consensus::pouw::{Task, TaskQueue, TaskType, WorkerInfo};
+use crate::cryptography::sha3::Hash;
+use rocksdb::{ColumnFamily, IteratorMode, Options, WriteBatch, DB};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+pub const CF_BLOCKS: &str = "blocks"; // Canonical blocks by big-endian height
+pub const CF_ACCOUNTS: &str = "accounts"; // Balances and nonces by address
+pub const CF_STAKING: &str = "staking"; // Serialized StakingState
+pub const CF_TASKS: &str = "tasks"; // PoUW tasks by id
+pub const CF_TASK_TYPES: &str = "task_types"; // PoUW task types by name
+pub const CF_WORKERS: &str = "workers"; // PoUW worker registry by address
+pub const CF_META: &str = "meta"; // Bookkeeping, e.g. the tip the state belongs to
+
+const STAKING_KEY: &[u8] = b"state";
+const STATE_TIP_KEY: &[u8] = b"state_tip";
+const TOTAL_SUPPLY_KEY: &[u8] = b"total_supply";
+
+#[derive(Debug)]
+pub enum StorageError {
+    Db(rocksdb::Error),
+    Codec(serde_json::Error),
+    /// A stored record could not be decoded.
+    Corrupted {
+        key: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Db(e) => write!(f, "database error: {}", e),
+            StorageError::Codec(e) => write!(f, "encoding error: {}", e),
+            StorageError::Corrupted { key, reason } => {
+                write!(f, "record {} is corrupted: {}", key, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<rocksdb::Error> for StorageError {
+    fn from(e: rocksdb::Error) -> Self {
+        StorageError::Db(e)
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(e: serde_json::Error) -> Self {
+        StorageError::Codec(e)
+    }
+}
+
+/// State that is written in the same batch as the blocks it belongs to.
+pub struct StateRecord<'a> {
+    pub tip: Hash,
+    pub accounts: &'a AccountState,
+    pub staking_state: &'a StakingState,
+    pub task_queue: &'a TaskQueue,
+    /// The state already on disk. Only entries that differ from it are
+    /// written; `None` rewrites every entry.
+    pub stored: Option<&'a StateSnapshot>,
+}
+
+/// Node state persisted alongside the chain, tagged with the tip it matches.
+pub struct StoredState {
+    pub tip: Hash,
+    pub accounts: AccountState,
+    pub staking_state: StakingState,
+    pub task_queue: TaskQueue,
+}
 
 pub struct BlockchainDB {
     db: DB,
 }
 
+impl fmt::Debug for BlockchainDB {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockchainDB").finish_non_exhaustive()
+    }
+}
+
 impl BlockchainDB {
-    pub fn new(path: &str) -> Self {
-        let db = DB::open_default(path).expect("Failed to open RocksDB");
-        BlockchainDB { db }
+    pub fn new(path: &str) -> Result<Self, StorageError> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        let db = DB::open_cf(
+            &options,
+            path,
+            [
+                CF_BLOCKS,
+                CF_ACCOUNTS,
+                CF_STAKING,
+                CF_TASKS,
+                CF_TASK_TYPES,
+                CF_WORKERS,
+                CF_META,
+            ],
+        )?;
+        Ok(BlockchainDB { db })
     }
 
-    pub fn save_block(&self, block: &Block) {
-        let serialized = serde_json::to_string(block).expect("Failed to serialize block");
-        self.db.put(block.index.to_string(), serialized).expect("Failed to save block");
+    fn cf(&self, name: &str) -> &ColumnFamily {
+        self.db
+            .cf_handle(name)
+            .expect("column family is created on open")
     }
 
-    pub fn load_blocks(&self) -> Vec<Block> {
+    /// Loads the canonical chain in height order.
+    pub fn load_blocks(&self) -> Result<Vec<Block>, StorageError> {
         let mut blocks = Vec::new();
-        let iter = self.db.iterator(rocksdb::IteratorMode::Start);
-        for (_, value) in iter {
-            let block: Block = serde_json::from_slice(&value).expect("Failed to deserialize block");
+        for item in self.db.iterator_cf(self.cf(CF_BLOCKS), IteratorMode::Start) {
+            let (key, value) = item?;
+            let block = serde_json::from_slice(&value).map_err(|e| StorageError::Corrupted {
+                key: hex::encode(&key),
+                reason: e.to_string(),
+            })?;
             blocks.push(block);
         }
-        blocks
+        Ok(blocks)
+    }
+
+    /// Loads the persisted accounts, staking state and tasks, if any were
+    /// written.
+    pub fn load_state(&self) -> Result<Option<StoredState>, StorageError> {
+        let tip = match self.db.get_cf(self.cf(CF_META), STATE_TIP_KEY)? {
+            Some(tip) => tip.try_into().map_err(|_| StorageError::Corrupted {
+                key: "state_tip".to_string(),
+                reason: "expected a 32-byte hash".to_string(),
+            })?,
+            None => return Ok(None),
+        };
+
+        let mut accounts = AccountState::new();
+        for item in self
+            .db
+            .iterator_cf(self.cf(CF_ACCOUNTS), IteratorMode::Start)
+        {
+            let (key, value) = item?;
+            let address = String::from_utf8_lossy(&key).into_owned();
+            let account: Account =
+                serde_json::from_slice(&value).map_err(|e| StorageError::Corrupted {
+                    key: address.clone(),
+                    reason: e.to_string(),
+                })?;
+            accounts.accounts.insert(address, account);
+        }
+        if let Some(value) = self.db.get_cf(self.cf(CF_META), TOTAL_SUPPLY_KEY)? {
+            let bytes = value.try_into().map_err(|_| StorageError::Corrupted {
+                key: "total_supply".to_string(),
+                reason: "expected an 8-byte integer".to_string(),
+            })?;
+            accounts.total_supply = u64::from_be_bytes(bytes);
+        }
+
+        let staking_state = match self.db.get_cf(self.cf(CF_STAKING), STAKING_KEY)? {
+            Some(value) => serde_json::from_slice(&value)?,
+            None => StakingState::new(),
+        };
+
+        let mut task_queue = TaskQueue::new();
+        for item in self.db.iterator_cf(self.cf(CF_TASKS), IteratorMode::Start) {
+            let (key, value) = item?;
+            let task: Task =
+                serde_json::from_slice(&value).map_err(|e| StorageError::Corrupted {
+                    key: String::from_utf8_lossy(&key).into_owned(),
+                    reason: e.to_string(),
+                })?;
+            task_queue.tasks.insert(task.id.clone(), task);
+        }
+        for item in self
+            .db
+            .iterator_cf(self.cf(CF_TASK_TYPES), IteratorMode::Start)
+        {
+            let (key, value) = item?;
+            let task_type: TaskType =
+                serde_json::from_slice(&value).map_err(|e| StorageError::Corrupted {
+                    key: String::from_utf8_lossy(&key).into_owned(),
+                    reason: e.to_string(),
+                })?;
+            task_queue
+                .task_types
+                .insert(task_type.name.clone(), task_type);
+        }
+        for item in self
+            .db
+            .iterator_cf(self.cf(CF_WORKERS), IteratorMode::Start)
+        {
+            let (key, value) = item?;
+            let worker: WorkerInfo =
+                serde_json::from_slice(&value).map_err(|e| StorageError::Corrupted {
+                    key: String::from_utf8_lossy(&key).into_owned(),
+                    reason: e.to_string(),
+                })?;
+            task_queue.workers.insert(worker.address.clone(), worker);
+        }
+
+        Ok(Some(StoredState {
+            tip,
+            accounts,
+            staking_state,
+            task_queue,
+        }))
+    }
+
+    /// Atomically writes `blocks` at their heights, removes stored blocks from
+    /// `truncate_from` up to (not including) `truncate_to`, and replaces the
+    /// persisted accounts, staking state and tasks with `state`, if given.
+    pub fn commit(
+        &self,
+        blocks: &[Block],
+        truncate_from: u64,
+        truncate_to: u64,
+        state: Option<&StateRecord>,
+    ) -> Result<(), StorageError> {
+        let mut batch = WriteBatch::default();
+
+        let cf_blocks = self.cf(CF_BLOCKS);
+        for block in blocks {
+            batch.put_cf(
+                cf_blocks,
+                block_key(block.header.height),
+                serde_json::to_vec(block)?,
+            );
+        }
+        for height in truncate_from..truncate_to {
+            batch.delete_cf(cf_blocks, block_key(height));
+        }
+
+        if let Some(state) = state {
+            self.write_state(&mut batch, state)?;
+        }
+        self.db.write(batch)?;
+        Ok(())
     }
+
+    /// Adds the writes for `state` to `batch`: every entry, or only those that
+    /// differ from `state.stored`.
+    fn write_state(&self, batch: &mut WriteBatch, state: &StateRecord) -> Result<(), StorageError> {
+        let stored = state.stored;
+        self.write_entries(
+            batch,
+            CF_ACCOUNTS,
+            &state.accounts.accounts,
+            stored.map(|s| &s.accounts.accounts),
+        )?;
+        batch.put_cf(
+            self.cf(CF_META),
+            TOTAL_SUPPLY_KEY,
+            state.accounts.total_supply.to_be_bytes(),
+        );
+        if stored.is_none_or(|s| s.staking_state != *state.staking_state) {
+            batch.put_cf(
+                self.cf(CF_STAKING),
+                STAKING_KEY,
+                serde_json::to_vec(state.staking_state)?,
+            );
+        }
+        let tasks = state.task_queue;
+        let stored_tasks = stored.map(|s| &s.task_queue);
+        self.write_entries(
+            batch,
+            CF_TASKS,
+            &tasks.tasks,
+            stored_tasks.map(|t| &t.tasks),
+        )?;
+        self.write_entries(
+            batch,
+            CF_TASK_TYPES,
+            &tasks.task_types,
+            stored_tasks.map(|t| &t.task_types),
+        )?;
+        self.write_entries(
+            batch,
+            CF_WORKERS,
+            &tasks.workers,
+            stored_tasks.map(|t| &t.workers),
+        )?;
+
+        batch.put_cf(self.cf(CF_META), STATE_TIP_KEY, state.tip);
+        Ok(())
+    }
+
+    /// Adds writes to `batch` that make column family `name` hold exactly
+    /// `entries`, given that it holds `stored`: entries that were added or
+    /// changed are put and those that went away deleted. Without `stored`
+    /// every entry is rewritten.
+    fn write_entries<T: Serialize + PartialEq>(
+        &self,
+        batch: &mut WriteBatch,
+        name: &str,
+        entries: &HashMap<String, T>,
+        stored: Option<&HashMap<String, T>>,
+    ) -> Result<(), StorageError> {
+        let Some(stored) = stored else {
+            return self.replace_entries(batch, name, entries);
+        };
+        let cf = self.cf(name);
+        for key in stored.keys().filter(|key| !entries.contains_key(*key)) {
+            batch.delete_cf(cf, key.as_bytes());
+        }
+        for (key, value) in entries {
+            if stored.get(key) != Some(value) {
+                batch.put_cf(cf, key.as_bytes(), serde_json::to_vec(value)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds writes to `batch` that make column family `name` hold exactly
+    /// `entries`, keyed by their map key.
+    fn replace_entries<T: Serialize>(
+        &self,
+        batch: &mut WriteBatch,
+        name: &str,
+        entries: &HashMap<String, T>,
+    ) -> Result<(), StorageError> {
+        let cf = self.cf(name);
+        for item in self.db.iterator_cf(cf, IteratorMode::Start) {
+            let (key, _) = item?;
+            if !entries.contains_key(String::from_utf8_lossy(&key).as_ref()) {
+                batch.delete_cf(cf, &key);
+            }
+        }
+        for (key, value) in entries {
+            batch.put_cf(cf, key.as_bytes(), serde_json::to_vec(value)?);
+        }
+        Ok(())
+    }
+}
+
+/// Blocks are keyed by big-endian height so RocksDB iterates them in order.
+fn block_key(height: u64) -> [u8; 8] {
+    height.to_be_bytes()
 }
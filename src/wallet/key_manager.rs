@@ -0,0 +1,62 @@
+use crate::cryptography::sphincs::Sphincs;
+use pqcrypto_sphincsplus::sphincssha2128fsimple::{PublicKey, SecretKey};
+use pqcrypto_traits::sign::{PublicKey as _, SecretKey as _};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// The node's own SPHINCS+ identity, used to sign the blocks it proposes.
+pub struct NodeKey {
+    pub public_key: PublicKey,
+    pub secret_key: SecretKey,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    public_key: String,
+    secret_key: String,
+}
+
+impl NodeKey {
+    pub fn generate() -> Result<Self, Box<dyn Error>> {
+        let (public_key, secret_key) = Sphincs::generate_keypair()?;
+        Ok(NodeKey {
+            public_key,
+            secret_key,
+        })
+    }
+
+    /// Loads the key stored at `path`, generating and saving a new one if the
+    /// file does not exist yet.
+    pub fn load_or_generate(path: &str) -> Result<Self, Box<dyn Error>> {
+        if Path::new(path).exists() {
+            let stored: StoredKey = serde_json::from_str(&fs::read_to_string(path)?)?;
+            let public_key = Sphincs::public_key_from_bytes(&hex::decode(stored.public_key)?)
+                .ok_or("Invalid node public key")?;
+            let secret_key = Sphincs::secret_key_from_bytes(&hex::decode(stored.secret_key)?)
+                .ok_or("Invalid node secret key")?;
+            return Ok(NodeKey {
+                public_key,
+                secret_key,
+            });
+        }
+
+        let key = Self::generate()?;
+        let stored = StoredKey {
+            public_key: hex::encode(key.public_key.as_bytes()),
+            secret_key: hex::encode(key.secret_key.as_bytes()),
+        };
+        fs::write(path, serde_json::to_string(&stored)?)?;
+        Ok(key)
+    }
+
+    /// The proposer id placed in block headers: the hex-encoded public key.
+    pub fn id(&self) -> String {
+        hex::encode(self.public_key.as_bytes())
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        Sphincs::sign(message, &self.secret_key)
+    }
+}
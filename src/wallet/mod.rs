@@ -1,4 +1,4 @@
 pub mod wallet;
 
-pub use wallet::{create_wallet, send_transaction};
-pub mod key_manager;
\ No newline at end of file
+pub use wallet::{create_wallet, open_wallets, submit_transaction};
+pub mod key_manager;
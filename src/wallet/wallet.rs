@@ -1,68 +1,77 @@
-use crate::blockchain::block::{Block, Transaction};
+use crate::blockchain::block::{Transaction, TransactionKind};
 use crate::blockchain::chain::Blockchain;
-use base64::engine::general_purpose::STANDARD;
-use base64::Engine;
-use pqcrypto_kyber::kyber512;
-use pqcrypto_traits::kem::{PublicKey, SecretKey};
+use crate::cryptography::sha3::Hash;
+use crate::cryptography::sphincs::Sphincs;
+use pqcrypto_traits::sign::{PublicKey, SecretKey};
 use rocksdb::DB;
 use std::sync::{Arc, Mutex};
 
-pub fn create_wallet() -> (String, String) {
-    let (public_key, private_key) = kyber512::keypair();
-    let public_key_b64 = STANDARD.encode(public_key.as_bytes());
-    let private_key_b64 = STANDARD.encode(private_key.as_bytes());
+/// Opens the wallet database. RocksDB allows one handle per database, so
+/// the node opens it once at startup and shares the handle.
+pub fn open_wallets(path: &str) -> Result<Arc<DB>, String> {
+    DB::open_default(path)
+        .map(Arc::new)
+        .map_err(|e| format!("Failed to open wallet database: {}", e))
+}
 
-    // Save wallet to RocksDB
-    let db = DB::open_default("./wallets").expect("Failed to open wallet database");
-    db.put(&public_key_b64, &private_key_b64)
-        .expect("Failed to save wallet");
+/// Creates a SPHINCS+ signing wallet in `wallets`. The address is the
+/// hex-encoded public key.
+pub fn create_wallet(wallets: &DB) -> Result<(String, String), String> {
+    let (public_key, private_key) =
+        Sphincs::generate_keypair().expect("Failed to generate SPHINCS+ keypair");
+    let public_key_hex = hex::encode(public_key.as_bytes());
+    let private_key_hex = hex::encode(private_key.as_bytes());
 
-    (public_key_b64, private_key_b64)
-}
+    wallets
+        .put(&public_key_hex, &private_key_hex)
+        .map_err(|e| format!("Failed to save wallet: {}", e))?;
 
-pub fn send_transaction(blockchain: Arc<Mutex<Blockchain>>, sender: &str, to: &str, amount: u64) {
-    println!(
-        "Creating a transaction to send {} QuantumCoins from {} to {}",
-        amount, sender, to
-    );
+    Ok((public_key_hex, private_key_hex))
+}
 
+/// Signs a transaction of `kind` with `sender`'s stored key and adds it to
+/// the pending pool, from which the elected proposer builds its next block.
+/// Returns the transaction's hash. `receiver` and `amount` carry what the
+/// kind needs: the recipient and sum of a transfer, the validator and sum of
+/// a delegation, a task's escrow or a worker's collateral, and are otherwise
+/// empty and zero.
+pub fn submit_transaction(
+    wallets: &DB,
+    blockchain: Arc<Mutex<Blockchain>>,
+    sender: &str,
+    receiver: &str,
+    amount: u64,
+    kind: TransactionKind,
+) -> Result<Hash, String> {
     // Retrieve sender's private key from RocksDB
-    let db = DB::open_default("./wallets").expect("Failed to open wallet database");
-    let _private_key = match db.get(sender) {
+    let private_key = match wallets.get(sender) {
         Ok(Some(key)) => String::from_utf8(key.to_vec()).expect("Failed to parse private key"),
-        Ok(None) => {
-            println!("Wallet not found for sender: {}", sender);
-            return;
-        }
-        Err(_) => {
-            println!("Failed to retrieve wallet from database.");
-            return;
-        }
+        Ok(None) => return Err(format!("Wallet not found for sender: {}", sender)),
+        Err(_) => return Err("Failed to retrieve wallet from database.".to_string()),
     };
+    let secret_key = hex::decode(&private_key)
+        .ok()
+        .and_then(|bytes| Sphincs::secret_key_from_bytes(&bytes))
+        .ok_or_else(|| format!("Stored key for {} is not a signing key", sender))?;
 
-    // Create a cryptographic signature (placeholder logic)
-    let signature = format!("SIGNATURE({}:{}:{})", sender, to, amount);
-
-    let transaction = Transaction {
+    let mut blockchain = blockchain.lock().unwrap();
+    let mut transaction = Transaction {
         sender: sender.to_string(),
-        receiver: to.to_string(),
+        receiver: receiver.to_string(),
         amount,
-        signature: signature.into_bytes(),
+        nonce: blockchain.next_nonce(sender),
+        kind,
+        signature: vec![],
     };
+    transaction.signature = Sphincs::sign(
+        &transaction.signing_bytes(&blockchain.genesis_hash()),
+        &secret_key,
+    )
+    .map_err(|e| e.to_string())?;
 
-    let mut blockchain = blockchain.lock().unwrap();
-    let previous_hash = blockchain
-        .chain
-        .last()
-        .unwrap_or(&Block::genesis())
-        .hash
-        .clone();
-    let new_block = Block::new(
-        blockchain.chain.len() as u64,
-        previous_hash,
-        vec![transaction],
-    );
-
-    blockchain.add_block(new_block);
-    println!("Transaction added to the blockchain!");
+    let hash = transaction.hash();
+    blockchain
+        .add_pending_transaction(transaction)
+        .map_err(|e| format!("Transaction rejected: {}", e))?;
+    Ok(hash)
 }
@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Finished jobs kept in the journal. Older ones are dropped first.
+pub const MAX_FINISHED_JOBS: usize = 256;
+
+/// How far the worker has got with a task it claimed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStage {
+    /// The claim is sent, or about to be, and the program has not run yet.
+    Claimed,
+    /// The program has run and its output is waiting to be handed in.
+    Executed,
+    /// The result of a single-replica task is submitted.
+    Submitted,
+    /// The commitment to the result of a replicated task is on chain.
+    Committed,
+    /// The result behind the commitment is revealed.
+    Revealed,
+    /// Nothing more to do; see the job's outcome.
+    Finished,
+}
+
+/// How a finished job ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobOutcome {
+    /// The result was accepted and the worker paid.
+    Accepted,
+    /// The result was handed in but not accepted.
+    Rejected,
+    /// The claim was released, or the task expired, was cancelled or went
+    /// away, before a result was handed in.
+    Lapsed,
+    /// The program failed on the task's data, or the claim could not be sent.
+    Failed,
+}
+
+/// A task the worker has taken on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Job {
+    pub task_id: String,
+    pub task_type: String,
+    pub stage: JobStage,
+    /// The program's output, once it has run.
+    #[serde(default)]
+    pub result: Option<Vec<u8>>,
+    /// Secret salt of the commitment to `result` for a replicated task.
+    /// Kept so that the result can still be revealed after a restart.
+    #[serde(default)]
+    pub salt: Option<Vec<u8>>,
+    #[serde(default)]
+    pub outcome: Option<JobOutcome>,
+    /// The last error met while working on the task.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl Job {
+    pub fn new(task_id: &str, task_type: &str) -> Self {
+        Job {
+            task_id: task_id.to_string(),
+            task_type: task_type.to_string(),
+            stage: JobStage::Claimed,
+            result: None,
+            salt: None,
+            outcome: None,
+            error: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.stage != JobStage::Finished
+    }
+
+    pub fn finish(&mut self, outcome: JobOutcome) {
+        self.stage = JobStage::Finished;
+        self.outcome = Some(outcome);
+    }
+}
+
+/// The worker's jobs, kept in a JSON file so that work in progress survives
+/// a restart. Every change is written through, replacing the file
+/// atomically.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    jobs: Vec<Job>,
+}
+
+impl Journal {
+    /// Opens the journal at `path`, starting an empty one if the file does
+    /// not exist yet.
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let jobs = if Path::new(path).exists() {
+            serde_json::from_str(&fs::read_to_string(path)?)?
+        } else {
+            Vec::new()
+        };
+        Ok(Journal {
+            path: PathBuf::from(path),
+            jobs,
+        })
+    }
+
+    /// Every job, in the order they were taken on.
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    /// The latest job on `task_id`. A task that reopens after a finished
+    /// job may be taken on again, so earlier jobs are kept as history.
+    pub fn get(&self, task_id: &str) -> Option<&Job> {
+        self.jobs.iter().rev().find(|job| job.task_id == task_id)
+    }
+
+    pub fn get_mut(&mut self, task_id: &str) -> Option<&mut Job> {
+        self.jobs
+            .iter_mut()
+            .rev()
+            .find(|job| job.task_id == task_id)
+    }
+
+    /// Jobs that are not finished yet.
+    pub fn active(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter().filter(|job| job.is_active())
+    }
+
+    pub fn active_mut(&mut self) -> impl Iterator<Item = &mut Job> {
+        self.jobs.iter_mut().filter(|job| job.is_active())
+    }
+
+    pub fn insert(&mut self, job: Job) {
+        self.jobs.push(job);
+    }
+
+    /// Writes the journal to a temporary file and renames it over the old
+    /// one, so that a crash never leaves a half-written journal. The file
+    /// and, on Unix, its directory are synced so the rename survives a power
+    /// loss. Finished jobs beyond `MAX_FINISHED_JOBS` are dropped first,
+    /// oldest first.
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        let finished = self.jobs.iter().filter(|job| !job.is_active()).count();
+        let mut excess = finished.saturating_sub(MAX_FINISHED_JOBS);
+        self.jobs.retain(|job| {
+            if excess > 0 && !job.is_active() {
+                excess -= 1;
+                return false;
+            }
+            true
+        });
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec_pretty(&self.jobs)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        #[cfg(unix)]
+        {
+            let dir = match self.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
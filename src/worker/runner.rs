@@ -0,0 +1,387 @@
+use crate::blockchain::block::TransactionKind;
+use crate::blockchain::chain::Blockchain;
+use crate::consensus::pouw::{
+    result_commitment, Task, TaskClaim, TaskCommit, TaskResult, TaskReveal, TaskStatus, TaskType,
+};
+use crate::consensus::wasm::{self, Execution, WasmError};
+use crate::wallet;
+use crate::worker::journal::{Job, JobOutcome, JobStage, Journal};
+use log::{error, warn};
+use rand_core::{OsRng, RngCore};
+use rocksdb::DB;
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+/// Tasks worked on at once unless `QTC_WORKER_MAX_JOBS` says otherwise.
+pub const DEFAULT_MAX_JOBS: usize = 2;
+
+/// How often the chain is polled unless `QTC_WORKER_POLL_MS` says otherwise.
+pub const DEFAULT_POLL_MS: u64 = 2_000;
+
+/// How the node works on PoUW tasks.
+#[derive(Debug, Clone)]
+pub struct WorkerConfig {
+    /// Wallet that claims tasks, signs results and is paid for them. Its key
+    /// must be in the node's wallet store.
+    pub wallet: String,
+    /// Task types the worker runs. Empty means every registered type.
+    pub task_types: Vec<String>,
+    /// Most tasks worked on at once, which also bounds how many programs
+    /// run in parallel.
+    pub max_jobs: usize,
+    pub poll_interval: Duration,
+    pub journal_path: String,
+}
+
+impl WorkerConfig {
+    /// Reads the configuration from the environment. Worker mode is off,
+    /// and `None` returned, unless `QTC_WORKER` names a wallet.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let wallet = match std::env::var("QTC_WORKER") {
+            Ok(wallet) if !wallet.is_empty() => wallet,
+            _ => return Ok(None),
+        };
+        let task_types = std::env::var("QTC_WORKER_TASK_TYPES")
+            .map(|types| {
+                types
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let number = |name: &str, default: u64| match std::env::var(name) {
+            Ok(value) => value
+                .parse::<u64>()
+                .map_err(|_| format!("{} must be a number", name)),
+            Err(_) => Ok(default),
+        };
+        let max_jobs = number("QTC_WORKER_MAX_JOBS", DEFAULT_MAX_JOBS as u64)? as usize;
+        if max_jobs == 0 {
+            return Err("QTC_WORKER_MAX_JOBS must be at least 1".to_string());
+        }
+        let poll_ms = number("QTC_WORKER_POLL_MS", DEFAULT_POLL_MS)?.max(1);
+        Ok(Some(WorkerConfig {
+            wallet,
+            task_types,
+            max_jobs,
+            poll_interval: Duration::from_millis(poll_ms),
+            journal_path: std::env::var("QTC_WORKER_JOURNAL")
+                .unwrap_or_else(|_| "./worker_journal.json".into()),
+        }))
+    }
+
+    fn runs(&self, task_type: &str) -> bool {
+        self.task_types.is_empty() || self.task_types.iter().any(|name| name == task_type)
+    }
+}
+
+/// A program the worker has to run for its job on `task_id`.
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub task_id: String,
+    pub task_type: TaskType,
+    pub data: Vec<u8>,
+}
+
+impl Run {
+    pub fn execute(&self) -> Result<Execution, WasmError> {
+        wasm::execute(&self.task_type.code, &self.data, self.task_type.fuel_limit)
+    }
+}
+
+/// Works on PoUW tasks for a wallet: claims open tasks of the types it
+/// runs, runs their programs and hands in the results, keeping track of
+/// each job in its journal.
+#[derive(Debug)]
+pub struct Worker {
+    config: WorkerConfig,
+    journal: Journal,
+}
+
+impl Worker {
+    /// Opens the worker with the jobs left in its journal.
+    pub fn open(config: WorkerConfig) -> Result<Self, Box<dyn Error>> {
+        let journal = Journal::open(&config.journal_path)?;
+        Ok(Worker { config, journal })
+    }
+
+    pub fn config(&self) -> &WorkerConfig {
+        &self.config
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    /// Works out the next step of every job from the state of `blockchain`
+    /// and claims new tasks while fewer than `max_jobs` are in progress,
+    /// highest reward first, skipping those whose minimum reputation the
+    /// worker does not reach. Returns the transactions to send from the
+    /// worker's wallet, to be reported back through `confirm`, and the
+    /// programs to run, to be reported back through `record_execution`.
+    /// New jobs are journaled before their claims are returned.
+    pub fn plan(&mut self, blockchain: &Blockchain) -> (Vec<TransactionKind>, Vec<Run>) {
+        let worker = self.config.wallet.clone();
+        let mut transactions = Vec::new();
+        let mut runs = Vec::new();
+
+        for job in self.journal.active_mut() {
+            // The task does not reflect a transaction still waiting for a
+            // block, so the job waits with it.
+            if blockchain.has_pending_task_transaction(&worker, &job.task_id) {
+                continue;
+            }
+            let task = match blockchain.get_task(&job.task_id) {
+                Some(task) => task,
+                None => {
+                    job.finish(JobOutcome::Lapsed);
+                    continue;
+                }
+            };
+            if let Some(outcome) = settled(task, &worker, job.stage) {
+                job.finish(outcome);
+                continue;
+            }
+            let assignment = task
+                .assignments
+                .iter()
+                .find(|assignment| assignment.worker == worker);
+            let task_id = job.task_id.clone();
+            match job.stage {
+                JobStage::Claimed => match blockchain.get_task_type(&job.task_type) {
+                    Some(task_type) => runs.push(Run {
+                        task_id,
+                        task_type: task_type.clone(),
+                        data: task.data.clone(),
+                    }),
+                    None => job.finish(JobOutcome::Failed),
+                },
+                JobStage::Executed => {
+                    let result = job.result.clone().unwrap_or_default();
+                    let salt = job.salt.clone().unwrap_or_default();
+                    // The transaction may have gone through without being
+                    // confirmed, e.g. just before a restart.
+                    if task.is_replicated() {
+                        if assignment.is_some_and(|assignment| assignment.commitment.is_some()) {
+                            job.stage = JobStage::Committed;
+                        } else {
+                            let commitment = result_commitment(&worker, &result, &salt);
+                            transactions.push(TransactionKind::TaskCommit(TaskCommit {
+                                task_id,
+                                commitment,
+                            }));
+                        }
+                    } else if assignment.is_some_and(|assignment| assignment.result_hash.is_some())
+                    {
+                        job.stage = JobStage::Submitted;
+                    } else {
+                        transactions
+                            .push(TransactionKind::TaskResult(TaskResult { task_id, result }));
+                    }
+                }
+                JobStage::Committed => {
+                    if assignment.is_some_and(|assignment| assignment.result_hash.is_some()) {
+                        job.stage = JobStage::Revealed;
+                    } else if task.status == TaskStatus::Committed {
+                        transactions.push(TransactionKind::TaskReveal(TaskReveal {
+                            task_id,
+                            result: job.result.clone().unwrap_or_default(),
+                            salt: job.salt.clone().unwrap_or_default(),
+                        }));
+                    }
+                }
+                JobStage::Submitted | JobStage::Revealed | JobStage::Finished => {}
+            }
+        }
+
+        let free = self
+            .config
+            .max_jobs
+            .saturating_sub(self.journal.active().count());
+        let mut open: Vec<(&Task, &str)> = blockchain
+            .get_pending_tasks()
+            .into_iter()
+            .filter(|task| task.status.is_claimable())
+            .filter(|task| !self.journal.active().any(|job| job.task_id == task.id))
+            .filter(|task| task.assignments.iter().all(|a| a.worker != worker))
+            .filter(|task| {
+                let queue = &blockchain.task_queue;
+                queue.check_claimant(&worker, task).is_ok()
+            })
+            .filter_map(|task| {
+                let task_type = task.task_type.as_deref()?;
+                let runnable =
+                    self.config.runs(task_type) && blockchain.get_task_type(task_type).is_some();
+                runnable.then_some((task, task_type))
+            })
+            .collect();
+        open.sort_by(|(a, _), (b, _)| b.reward.cmp(&a.reward).then_with(|| a.id.cmp(&b.id)));
+        for (task, task_type) in open.into_iter().take(free) {
+            self.journal.insert(Job::new(&task.id, task_type));
+            transactions.push(TransactionKind::TaskClaim(TaskClaim {
+                task_id: task.id.clone(),
+            }));
+        }
+
+        self.save();
+        (transactions, runs)
+    }
+
+    /// Records whether a transaction returned by `plan` was taken into the
+    /// pending pool. A claim that was not ends its job; anything else is
+    /// tried again on the next poll.
+    pub fn confirm(&mut self, kind: &TransactionKind, result: Result<(), String>) {
+        let (task_id, stage) = match kind {
+            TransactionKind::TaskClaim(claim) => (&claim.task_id, JobStage::Claimed),
+            TransactionKind::TaskResult(submission) => (&submission.task_id, JobStage::Submitted),
+            TransactionKind::TaskCommit(commit) => (&commit.task_id, JobStage::Committed),
+            TransactionKind::TaskReveal(reveal) => (&reveal.task_id, JobStage::Revealed),
+            _ => return,
+        };
+        let job = match self.journal.get_mut(task_id) {
+            Some(job) if job.is_active() => job,
+            _ => return,
+        };
+        match result {
+            Ok(()) => {
+                job.stage = stage;
+                job.error = None;
+            }
+            Err(error) => {
+                if stage == JobStage::Claimed {
+                    job.finish(JobOutcome::Failed);
+                }
+                job.error = Some(error);
+            }
+        }
+        self.save();
+    }
+
+    /// Records the outcome of a program run returned by `plan`. The output
+    /// is kept with a fresh secret salt, for a commitment if the task is
+    /// replicated; a program that fails ends the job.
+    pub fn record_execution(&mut self, task_id: &str, execution: Result<Execution, WasmError>) {
+        let job = match self.journal.get_mut(task_id) {
+            Some(job) if job.stage == JobStage::Claimed => job,
+            _ => return,
+        };
+        match execution {
+            Ok(execution) => {
+                let mut salt = [0u8; 32];
+                OsRng.fill_bytes(&mut salt);
+                job.result = Some(execution.output);
+                job.salt = Some(salt.to_vec());
+                job.stage = JobStage::Executed;
+            }
+            Err(error) => {
+                job.error = Some(error.to_string());
+                job.finish(JobOutcome::Failed);
+            }
+        }
+        self.save();
+    }
+
+    fn save(&mut self) {
+        if let Err(e) = self.journal.save() {
+            warn!("Failed to save worker journal: {}", e);
+        }
+    }
+}
+
+/// How the job on `task` ended for `worker`, if it has.
+fn settled(task: &Task, worker: &str, stage: JobStage) -> Option<JobOutcome> {
+    let handed_in = matches!(
+        stage,
+        JobStage::Submitted | JobStage::Committed | JobStage::Revealed
+    );
+    let missed = if handed_in {
+        JobOutcome::Rejected
+    } else {
+        JobOutcome::Lapsed
+    };
+    let holds_slot = task.assignments.iter().any(|a| a.worker == worker);
+    match task.status {
+        TaskStatus::Verified if task.accepted_workers().contains(&worker) => {
+            Some(JobOutcome::Accepted)
+        }
+        TaskStatus::Verified => Some(missed),
+        TaskStatus::Expired | TaskStatus::Cancelled => Some(JobOutcome::Lapsed),
+        // A claim, or a commitment the other slots never matched, lapsed.
+        TaskStatus::Open | TaskStatus::Claimed if !holds_slot => Some(JobOutcome::Lapsed),
+        // Settling a task without accepting a result clears its claims.
+        _ if !holds_slot => Some(missed),
+        _ => None,
+    }
+}
+
+/// Runs `worker` until the node stops: every `poll_interval` it plans
+/// against the chain and queues the worker's transactions for the next
+/// proposer, and it runs programs, one at a time per job. Everything that
+/// locks, signs, runs a program or writes the journal happens on blocking
+/// threads.
+pub async fn run(worker: Worker, blockchain: Arc<Mutex<Blockchain>>, wallets: Arc<DB>) {
+    let mut ticker = tokio::time::interval(worker.config.poll_interval);
+    let worker = Arc::new(Mutex::new(worker));
+    let mut running: JoinSet<String> = JoinSet::new();
+    let mut in_flight: HashSet<String> = HashSet::new();
+    loop {
+        tokio::select! {
+            Some(joined) = running.join_next() => match joined {
+                Ok(task_id) => {
+                    in_flight.remove(&task_id);
+                }
+                Err(e) => error!("Worker program run panicked: {}", e),
+            },
+            _ = ticker.tick() => {
+                let planned = tokio::task::spawn_blocking({
+                    let worker = worker.clone();
+                    let blockchain = blockchain.clone();
+                    let wallets = wallets.clone();
+                    move || {
+                        let mut worker = worker.lock().unwrap_or_else(PoisonError::into_inner);
+                        tick(&mut worker, blockchain, &wallets)
+                    }
+                });
+                let runs = match planned.await {
+                    Ok(runs) => runs,
+                    // Try again on the next tick
+                    Err(e) => {
+                        error!("Worker planning panicked: {}", e);
+                        continue;
+                    }
+                };
+                for run in runs {
+                    if in_flight.insert(run.task_id.clone()) {
+                        let worker = worker.clone();
+                        running.spawn_blocking(move || {
+                            let execution = run.execute();
+                            worker
+                                .lock()
+                                .unwrap_or_else(PoisonError::into_inner)
+                                .record_execution(&run.task_id, execution);
+                            run.task_id
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Plans against the chain and queues the worker's transactions. Returns
+/// the programs to run.
+fn tick(worker: &mut Worker, blockchain: Arc<Mutex<Blockchain>>, wallets: &DB) -> Vec<Run> {
+    let (transactions, runs) = worker.plan(&blockchain.lock().unwrap());
+    let wallet = worker.config.wallet.clone();
+    for kind in transactions {
+        let result =
+            wallet::submit_transaction(wallets, blockchain.clone(), &wallet, "", 0, kind.clone());
+        worker.confirm(&kind, result.map(|_| ()));
+    }
+    runs
+}
@@ -1,14 +1,1508 @@
+mod common;
+
 #[test]
 fn test_staking_logic() {
-    let mut blockchain = Blockchain::new("./test_blockchain");
+    let validator = NodeKey::generate().unwrap();
+    let wallet = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: validator.id(),
+        stake: 10_000,
+        ..Default::default()
+    }];
+    genesis.allocations.insert(wallet.id(), 1_000);
+
+    let _ = std::fs::remove_dir_all("./test_blockchain_staking");
+    let mut blockchain = Blockchain::new("./test_blockchain_staking", genesis).unwrap();
+    let network = blockchain.genesis_hash();
+
+    let stake = signed_tx(&network, &wallet, TransactionKind::Stake, "", 100, 0);
+    include(&mut blockchain, &validator, vec![stake]).unwrap();
+    assert_eq!(blockchain.accounts.balance(&wallet.id()), 900);
+    assert_eq!(blockchain.staking_state.staked_balances[&wallet.id()], 100);
+    assert_eq!(blockchain.staking_state.total_staked, 10_100);
+
+    // Stake has to come out of the spendable balance.
+    let too_much = signed_tx(&network, &wallet, TransactionKind::Stake, "", 5_000, 1);
+    assert_eq!(
+        include(&mut blockchain, &validator, vec![too_much]),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::InsufficientBalance {
+                balance: 900,
+                amount: 5_000
+            }
+        })
+    );
+
+    let unstake = signed_tx(&network, &wallet, TransactionKind::Unstake, "", 50, 1);
+    include(&mut blockchain, &validator, vec![unstake]).unwrap();
+    assert_eq!(blockchain.staking_state.total_staked, 10_050);
+
+    let too_much = signed_tx(&network, &wallet, TransactionKind::Unstake, "", 500, 2);
+    assert_eq!(
+        include(&mut blockchain, &validator, vec![too_much]),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::InsufficientStake {
+                staked: 50,
+                amount: 500
+            }
+        })
+    );
+    // A signature made for another network does not verify here.
+    let replayed = signed_tx(&[7; 32], &wallet, TransactionKind::Unstake, "", 10, 2);
+    assert_eq!(
+        include(&mut blockchain, &validator, vec![replayed]),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::InvalidSignature
+        })
+    );
+}
+
+#[test]
+fn test_pending_transactions_wait_for_the_elected_proposer() {
+    let validator = NodeKey::generate().unwrap();
+    let wallet = NodeKey::generate().unwrap();
+    let outsider = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: validator.id(),
+        stake: 10_000,
+        ..Default::default()
+    }];
+    genesis.allocations.insert(wallet.id(), 100);
+
+    let _ = std::fs::remove_dir_all("./test_blockchain_pending");
+    let mut blockchain = Blockchain::new("./test_blockchain_pending", genesis).unwrap();
+    let network = blockchain.genesis_hash();
+    let transfer = |amount, nonce| {
+        signed_tx(
+            &network,
+            &wallet,
+            TransactionKind::Transfer,
+            &outsider.id(),
+            amount,
+            nonce,
+        )
+    };
+
+    // Queued transactions count towards the sender's next nonce and balance.
+    blockchain.add_pending_transaction(transfer(60, 0)).unwrap();
+    assert_eq!(blockchain.next_nonce(&wallet.id()), 1);
+    assert_eq!(
+        blockchain.add_pending_transaction(transfer(10, 0)),
+        Err(TransactionError::InvalidNonce {
+            expected: 1,
+            found: 0
+        })
+    );
+    assert_eq!(
+        blockchain.add_pending_transaction(transfer(60, 1)),
+        Err(TransactionError::InsufficientBalance {
+            balance: 40,
+            amount: 60
+        })
+    );
+    let replayed = signed_tx(&[7; 32], &wallet, TransactionKind::Transfer, "x", 1, 1);
+    assert_eq!(
+        blockchain.add_pending_transaction(replayed),
+        Err(TransactionError::InvalidSignature)
+    );
+    blockchain.add_pending_transaction(transfer(40, 1)).unwrap();
+
+    // Only the elected proposer turns the pool into a block.
+    assert!(blockchain.propose_pending(&outsider).unwrap().is_none());
+    assert_eq!(blockchain.pending_transactions.len(), 2);
+    let block = blockchain.propose_pending(&validator).unwrap().unwrap();
+    assert_eq!(block.transactions.len(), 2);
+    blockchain.add_block(block).unwrap();
+    assert!(blockchain.pending_transactions.is_empty());
+    assert_eq!(blockchain.accounts.balance(&outsider.id()), 100);
+    assert_eq!(blockchain.next_nonce(&wallet.id()), 2);
+}
+
+#[test]
+fn test_epoch_rewards_are_paid_to_balances() {
+    let validator = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: validator.id(),
+        stake: 10_000,
+        ..Default::default()
+    }];
+    genesis.consensus.epoch_length = 2;
+    genesis.consensus.epoch_reward_bps = 100;
+
+    let path = "./test_blockchain_rewards";
+    let _ = std::fs::remove_dir_all(path);
+    let mut blockchain = Blockchain::new(path, genesis).unwrap();
+    let supply = blockchain.accounts.total_supply;
+    assert_eq!(supply, 10_000);
+
+    let block = blockchain.propose_block(vec![], &validator).unwrap();
+    blockchain.add_block(block).unwrap();
+    let rewards = blockchain.get_rewards(&validator.id());
+    assert_eq!((rewards.claimed, rewards.pending), (0, 50));
+    assert_eq!(blockchain.accounts.balance(&validator.id()), 0);
+
+    // Block 2 closes the epoch and pays 1% of the stake.
+    let block = blockchain.propose_block(vec![], &validator).unwrap();
+    blockchain.add_block(block).unwrap();
+    let rewards = blockchain.get_rewards(&validator.id());
+    assert_eq!((rewards.claimed, rewards.pending), (100, 0));
+    assert_eq!(blockchain.accounts.balance(&validator.id()), 100);
+    assert_eq!(blockchain.accounts.total_supply, supply + 100);
+}
+
+#[test]
+fn test_unstaked_funds_are_released_after_unbonding() {
+    let validator = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: validator.id(),
+        stake: 10_000,
+        ..Default::default()
+    }];
+    genesis.consensus.epoch_reward_bps = 0;
+    genesis.consensus.unbonding_blocks = 2;
+
+    let path = "./test_blockchain_unbonding";
+    let _ = std::fs::remove_dir_all(path);
+    let mut blockchain = Blockchain::new(path, genesis).unwrap();
+    let network = blockchain.genesis_hash();
+
+    let unstake = signed_tx(&network, &validator, TransactionKind::Unstake, "", 1_000, 0);
+    include(&mut blockchain, &validator, vec![unstake]).unwrap();
+    assert_eq!(blockchain.staking_state.total_staked, 9_000);
+    assert_eq!(
+        blockchain.get_unbonding(&validator.id()),
+        &[UnbondingEntry {
+            amount: 1_000,
+            release_height: 3,
+            validator: None
+        }]
+    );
+
+    let block = blockchain.propose_block(vec![], &validator).unwrap();
+    blockchain.add_block(block).unwrap();
+    assert_eq!(blockchain.accounts.balance(&validator.id()), 0);
+
+    let block = blockchain.propose_block(vec![], &validator).unwrap();
+    blockchain.add_block(block).unwrap();
+    assert_eq!(blockchain.accounts.balance(&validator.id()), 1_000);
+    assert!(blockchain.get_unbonding(&validator.id()).is_empty());
+}
+
+#[test]
+fn test_active_set_is_bounded_and_updated_at_epoch_boundaries() {
+    let keys: Vec<NodeKey> = (0..3).map(|_| NodeKey::generate().unwrap()).collect();
+    let newcomer = NodeKey::generate().unwrap();
+    let small = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.allocations.insert(newcomer.id(), 5_000);
+    genesis.allocations.insert(small.id(), 500);
+    genesis.validators = keys
+        .iter()
+        .zip([3_000, 2_000, 1_000])
+        .map(|(key, stake)| GenesisValidator {
+            address: key.id(),
+            stake,
+            ..Default::default()
+        })
+        .collect();
+    genesis.consensus.epoch_length = 2;
+    genesis.consensus.max_validators = 2;
+
+    let path = "./test_blockchain_active_set";
+    let _ = std::fs::remove_dir_all(path);
+    let mut blockchain = Blockchain::new(path, genesis).unwrap();
+    let network = blockchain.genesis_hash();
+    assert_eq!(
+        blockchain.staking_state.validators,
+        vec![keys[0].id(), keys[1].id()]
+    );
+
+    // A newcomer outranks everyone but only joins at the epoch boundary, and
+    // a validator below the minimum self-stake never joins. Each consensus
+    // key belongs to one operator.
+    let register = |operator: &NodeKey, consensus_key: &NodeKey, name: &str| {
+        let registration = ValidatorRegistration {
+            consensus_key: consensus_key.id(),
+            name: name.to_string(),
+        };
+        signed_tx(
+            &network,
+            operator,
+            TransactionKind::ValidatorRegister(registration),
+            "",
+            0,
+            0,
+        )
+    };
+    let other = NodeKey::generate().unwrap();
+    let proposer = common::elected(&blockchain, &[&keys[0], &keys[1]]);
+    assert_eq!(
+        include(
+            &mut blockchain,
+            proposer,
+            vec![register(&other, &keys[0], "Other")]
+        ),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::InvalidValidatorRegistration
+        })
+    );
+
+    let stakes = vec![
+        register(&newcomer, &newcomer, "Newcomer"),
+        register(&small, &small, "Small"),
+        signed_tx(&network, &newcomer, TransactionKind::Stake, "", 5_000, 1),
+        signed_tx(&network, &small, TransactionKind::Stake, "", 500, 1),
+    ];
+    let proposer = common::elected(&blockchain, &[&keys[0], &keys[1]]);
+    include(&mut blockchain, proposer, stakes).unwrap();
+    assert!(!blockchain.staking_state.validators.contains(&newcomer.id()));
+
+    let proposer = common::elected(&blockchain, &[&keys[0], &keys[1]]);
+    include(&mut blockchain, proposer, vec![]).unwrap();
+    assert_eq!(
+        blockchain.staking_state.validators,
+        vec![newcomer.id(), keys[0].id()]
+    );
+    assert!(blockchain
+        .propose_block(vec![], &keys[1])
+        .and_then(|block| blockchain.add_block(block))
+        .is_err());
+}
+
+#[test]
+fn test_delegation_rewards_are_split_by_commission() {
+    let validator = NodeKey::generate().unwrap();
+    let delegator = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.allocations.insert(delegator.id(), 10_000);
+    genesis.validators = vec![GenesisValidator {
+        address: validator.id(),
+        stake: 10_000,
+        ..Default::default()
+    }];
+    genesis.consensus.epoch_length = 2;
+    genesis.consensus.epoch_reward_bps = 100;
+    genesis.consensus.max_commission_change_bps = 1_000;
+
+    let path = "./test_blockchain_delegation";
+    let _ = std::fs::remove_dir_all(path);
+    let mut blockchain = Blockchain::new(path, genesis).unwrap();
+    let network = blockchain.genesis_hash();
+
+    let unknown = signed_tx(
+        &network,
+        &delegator,
+        TransactionKind::Delegate,
+        "nobody",
+        10_000,
+        0,
+    );
+    assert_eq!(
+        include(&mut blockchain, &validator, vec![unknown]),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::UnknownValidator
+        })
+    );
+
+    // The commission rises by at most 1,000 basis points, once per epoch.
+    let commission = |bps, nonce| {
+        signed_tx(
+            &network,
+            &validator,
+            TransactionKind::SetCommission {
+                commission_bps: bps,
+            },
+            "",
+            0,
+            nonce,
+        )
+    };
+    assert_eq!(
+        include(&mut blockchain, &validator, vec![commission(2_000, 0)]),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::InvalidCommission {
+                current: 0,
+                requested: 2_000
+            }
+        })
+    );
+    assert_eq!(
+        include(
+            &mut blockchain,
+            &validator,
+            vec![commission(1_000, 0), commission(1_100, 1)]
+        ),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 1,
+            error: TransactionError::InvalidCommission {
+                current: 1_000,
+                requested: 1_100
+            }
+        })
+    );
+
+    let delegate = signed_tx(
+        &network,
+        &delegator,
+        TransactionKind::Delegate,
+        &validator.id(),
+        10_000,
+        0,
+    );
+    include(
+        &mut blockchain,
+        &validator,
+        vec![commission(1_000, 0), delegate],
+    )
+    .unwrap();
+    assert_eq!(
+        blockchain.staking_state.get_commission(&validator.id()),
+        1_000
+    );
+    assert_eq!(blockchain.accounts.balance(&delegator.id()), 0);
+    assert_eq!(
+        blockchain.staking_state.voting_power(&validator.id()),
+        20_000
+    );
+    assert_eq!(
+        blockchain.staking_state.delegations_of(&delegator.id()),
+        vec![(validator.id(), 10_000)]
+    );
+
+    // Block 2 closes the epoch. 1% of 20,000 is paid, and the validator keeps
+    // 10% of the delegator's half.
+    let block = blockchain.propose_block(vec![], &validator).unwrap();
+    blockchain.add_block(block).unwrap();
+    assert_eq!(blockchain.accounts.balance(&validator.id()), 110);
+    assert_eq!(blockchain.accounts.balance(&delegator.id()), 90);
+
+    let undelegate = signed_tx(
+        &network,
+        &delegator,
+        TransactionKind::Undelegate,
+        &validator.id(),
+        4_000,
+        1,
+    );
+    include(&mut blockchain, &validator, vec![undelegate]).unwrap();
+    assert_eq!(
+        blockchain.staking_state.delegated_to(&validator.id()),
+        6_000
+    );
+    assert_eq!(
+        blockchain.get_unbonding(&delegator.id()),
+        &[UnbondingEntry {
+            amount: 4_000,
+            release_height: 203,
+            validator: Some(validator.id())
+        }]
+    );
+}
+
+#[test]
+fn test_double_sign_evidence_slashes_and_jails() {
+    let offender = NodeKey::generate().unwrap();
+    let proposer = NodeKey::generate().unwrap();
+    let reporter = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![
+        GenesisValidator {
+            address: offender.id(),
+            stake: 10_000,
+            ..Default::default()
+        },
+        GenesisValidator {
+            address: proposer.id(),
+            stake: 10_000,
+            ..Default::default()
+        },
+    ];
+
+    let path = "./test_blockchain_slashing";
+    let _ = std::fs::remove_dir_all(path);
+    let mut blockchain = Blockchain::new(path, genesis).unwrap();
+    let supply = blockchain.accounts.total_supply;
+
+    // Two different blocks signed by the offender at height 1.
+    let tip = blockchain.latest_block().hash;
+    let first = Block::new(1, tip, ZERO_HASH, vec![], &offender);
+    let second = Block::new(1, tip, blockchain.state_root(), vec![], &offender);
+    let evidence = DoubleSignEvidence {
+        first: SignedHeader {
+            header: first.header,
+            signature: first.signature,
+        },
+        second: SignedHeader {
+            header: second.header,
+            signature: second.signature,
+        },
+    };
+
+    let mut tx = Transaction {
+        sender: reporter.id(),
+        receiver: String::new(),
+        amount: 0,
+        nonce: 0,
+        kind: TransactionKind::DoubleSign(evidence),
+        signature: vec![],
+    };
+    tx.signature = reporter
+        .sign(&tx.signing_bytes(&blockchain.genesis_hash()))
+        .unwrap();
+    let elected = common::elected(&blockchain, &[&offender, &proposer]);
+    include(&mut blockchain, elected, vec![tx]).unwrap();
+
+    // The default penalty is 5%, burned, with a 1000-block jail term.
+    let staking = &blockchain.staking_state;
+    assert_eq!(staking.staked_balances[&offender.id()], 9_500);
+    assert_eq!(staking.jailed[&offender.id()], 1_001);
+    assert!(!staking.validators.contains(&offender.id()));
+    assert_eq!(blockchain.accounts.total_supply, supply - 500);
+}
+
+#[test]
+fn test_staking_state_survives_restart() {
+    let validator = NodeKey::generate().unwrap();
+    let wallet = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: validator.id(),
+        stake: 10_000,
+        ..Default::default()
+    }];
+    genesis.allocations.insert(wallet.id(), 1_000);
+
+    let path = "./test_blockchain_restart";
+    let _ = std::fs::remove_dir_all(path);
+    {
+        let mut blockchain = Blockchain::new(path, genesis.clone()).unwrap();
+        let network = blockchain.genesis_hash();
+        let stake = signed_tx(&network, &wallet, TransactionKind::Stake, "", 100, 0);
+        let task = task_spec("task1");
+        let create = signed_tx(
+            &network,
+            &wallet,
+            TransactionKind::TaskCreate(task),
+            "",
+            10,
+            1,
+        );
+        let register = TaskTypeSpec {
+            name: "echo".to_string(),
+            code: common::task_program(common::ECHO),
+            fuel_limit: 10_000,
+        };
+        let register = signed_tx(
+            &network,
+            &wallet,
+            TransactionKind::TaskTypeRegister(register),
+            "",
+            0,
+            2,
+        );
+        let registration = WorkerRegistration {
+            capabilities: vec!["echo".to_string()],
+        };
+        let join = signed_tx(
+            &network,
+            &wallet,
+            TransactionKind::WorkerRegister(registration),
+            "",
+            20,
+            3,
+        );
+        include(
+            &mut blockchain,
+            &validator,
+            vec![stake, create, register, join],
+        )
+        .unwrap();
+    }
+
+    let blockchain = Blockchain::new(path, genesis).unwrap();
+    assert_eq!(blockchain.staking_state.staked_balances[&wallet.id()], 100);
+    assert_eq!(blockchain.staking_state.total_staked, 10_100);
+    assert_eq!(blockchain.accounts.balance(&wallet.id()), 870);
+    assert_eq!(blockchain.get_task("task1").unwrap().escrow, 10);
+    assert_eq!(blockchain.get_task_type("echo").unwrap().owner, wallet.id());
+    assert_eq!(blockchain.get_worker(&wallet.id()).unwrap().collateral, 20);
+}
+
+#[test]
+fn test_block_hash_commits_to_header() {
+    let genesis = common::test_genesis().genesis_block();
+    let proposer = NodeKey::generate().unwrap();
+    let block = Block::new(
+        1,
+        genesis.hash,
+        genesis.header.state_root,
+        vec![],
+        &proposer,
+    );
+    assert!(block.verify_signature());
+    assert_eq!(block.hash, block.header.hash());
+    assert_eq!(block.header.previous_hash, genesis.hash);
+
+    let mut tampered = block.header.clone();
+    tampered.previous_hash = [1u8; 32];
+    assert_ne!(tampered.hash(), block.hash);
+
+    // The transaction count is the leaf count proofs are checked against,
+    // so it must match the transactions.
+    let mut miscounted = block.header.clone();
+    miscounted.tx_count = 1;
+    let mut miscounted = Block::from_header(miscounted, vec![]);
+    miscounted.signature = proposer.sign(&miscounted.hash).unwrap();
+    assert_eq!(
+        check_block_contents(&miscounted, &genesis.hash),
+        Err(BlockValidationError::MerkleRootMismatch { height: 1 })
+    );
+}
+
+#[test]
+fn test_merkle_proof_verifies_against_root() {
+    let leaves: Vec<_> = (0u8..5).map(|i| leaf_hash(&[i])).collect();
+    let root = merkle_root(&leaves);
+
+    for index in 0..leaves.len() {
+        let proof = merkle_proof(&leaves, index).unwrap();
+        assert!(verify_merkle_proof(&leaves[index], &proof, 5, &root));
+    }
+
+    let proof = merkle_proof(&leaves, 1).unwrap();
+    assert!(!verify_merkle_proof(&leaves[2], &proof, 5, &root));
+
+    // The path is tied to the position it was built for and the tree size
+    // the header states.
+    let mut moved = merkle_proof(&leaves, 0).unwrap();
+    moved.index = 3;
+    assert!(!verify_merkle_proof(&leaves[0], &moved, 5, &root));
+    let mut out_of_range = merkle_proof(&leaves, 4).unwrap();
+    out_of_range.index = 5;
+    assert!(!verify_merkle_proof(&leaves[4], &out_of_range, 5, &root));
+    let last = merkle_proof(&leaves, 4).unwrap();
+    assert!(!verify_merkle_proof(&leaves[4], &last, 6, &root));
+}
+
+#[test]
+fn test_state_proofs_verify_against_root() {
+    let mut tree = SparseMerkleTree::new();
+    for i in 0u64..20 {
+        tree.insert(
+            &StateKey::Stake(&format!("wallet{}", i)).encode(),
+            &i.to_be_bytes(),
+        );
+    }
+    let root = tree.root();
+
+    let key = StateKey::Stake("wallet7").encode();
+    let proof = tree.prove(&key);
+    assert!(verify_inclusion(&root, &key, &7u64.to_be_bytes(), &proof));
+    assert!(!verify_inclusion(&root, &key, &8u64.to_be_bytes(), &proof));
+    assert!(!verify_exclusion(&root, &key, &proof));
+
+    let missing = StateKey::Stake("wallet99").encode();
+    let proof = tree.prove(&missing);
+    assert!(verify_exclusion(&root, &missing, &proof));
+}
+
+#[test]
+fn test_state_root_commits_to_rewards_and_totals() {
+    let proposer = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: proposer.id(),
+        stake: 1_000,
+        ..Default::default()
+    }];
+    let mut blockchain = Blockchain::new("./test_blockchain_state_entries", genesis).unwrap();
+    let (root, supply, proof) = blockchain.state_proof(StateKey::TotalSupply);
+    assert_eq!(supply, Some(1_000u64.to_be_bytes().to_vec()));
+    assert!(verify_inclusion(
+        &root,
+        &StateKey::TotalSupply.encode(),
+        &1_000u64.to_be_bytes(),
+        &proof
+    ));
+
+    // An empty block still accrues rewards, so it moves the root.
+    let block = blockchain.propose_block(vec![], &proposer).unwrap();
+    assert_ne!(block.header.state_root, root);
+    blockchain.add_block(block).unwrap();
+
+    let (root, reward, proof) = blockchain.state_proof(StateKey::Reward(&proposer.id()));
+    let mut expected = 1_000u128.to_be_bytes().to_vec();
+    expected.extend_from_slice(&0u64.to_be_bytes());
+    assert_eq!(reward, Some(expected.clone()));
+    assert!(verify_inclusion(
+        &root,
+        &StateKey::Reward(&proposer.id()).encode(),
+        &expected,
+        &proof
+    ));
+    let (root, slashed, proof) = blockchain.state_proof(StateKey::TotalSlashed);
+    assert_eq!(slashed, None);
+    assert!(verify_exclusion(
+        &root,
+        &StateKey::TotalSlashed.encode(),
+        &proof
+    ));
+}
+
+#[test]
+fn test_genesis_hash_depends_on_chain_id() {
+    let mut other = common::test_genesis();
+    other.chain_id = "quantumcoin-other".to_string();
+    assert_ne!(
+        common::test_genesis().genesis_block().hash,
+        other.genesis_block().hash
+    );
+}
+
+#[test]
+fn test_genesis_rejects_long_validator_names() {
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: "wallet1".to_string(),
+        stake: 10_000,
+        name: "v".repeat(MAX_VALIDATOR_NAME_LEN),
+        ..Default::default()
+    }];
+    assert!(genesis.validate().is_ok());
+
+    genesis.validators[0].name.push('v');
+    assert!(genesis.validate().is_err());
+}
+
+#[test]
+fn test_genesis_rejects_proposer_timeout_within_clock_drift() {
+    let mut genesis = common::test_genesis();
+    assert!(genesis.validate().is_ok());
+
+    genesis.consensus.proposer_timeout_ms = MAX_FUTURE_DRIFT_MS as u64;
+    assert!(genesis.validate().is_err());
+}
+
+#[test]
+fn test_task_results_are_verified_before_reward() {
+    let validator = NodeKey::generate().unwrap();
+    let creator = NodeKey::generate().unwrap();
+    let worker = NodeKey::generate().unwrap();
+    let rival = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: validator.id(),
+        stake: 10_000,
+        ..Default::default()
+    }];
+    genesis.allocations.insert(creator.id(), 100);
+
+    let _ = std::fs::remove_dir_all("./test_blockchain_task_results");
+    let mut blockchain = Blockchain::new("./test_blockchain_task_results", genesis).unwrap();
+    let network = blockchain.genesis_hash();
+    let supply = blockchain.accounts.total_supply;
+    let mut task = task_spec("sum");
+    task.expected_result = Some(sha3_256(b"4"));
+    task.claim_blocks = 2;
+    task.verifier_fee = 5;
+    let create = signed_tx(
+        &network,
+        &creator,
+        TransactionKind::TaskCreate(task),
+        "",
+        55,
+        0,
+    );
+
+    let claim = |key: &NodeKey, nonce: u64| {
+        let claim = TaskClaim {
+            task_id: "sum".to_string(),
+        };
+        signed_tx(
+            &network,
+            key,
+            TransactionKind::TaskClaim(claim),
+            "",
+            0,
+            nonce,
+        )
+    };
+    let submit = |key: &NodeKey, result: &[u8], nonce: u64| {
+        let submission = TaskResult {
+            task_id: "sum".to_string(),
+            result: result.to_vec(),
+        };
+        signed_tx(
+            &network,
+            key,
+            TransactionKind::TaskResult(submission),
+            "",
+            0,
+            nonce,
+        )
+    };
+
+    // The reward and verifier fee are locked when the task is created.
+    include(&mut blockchain, &validator, vec![create, claim(&worker, 0)]).unwrap();
+    assert_eq!(blockchain.accounts.balance(&creator.id()), 45);
+    let task = blockchain.get_task("sum").unwrap();
+    assert_eq!((task.reward, task.escrow), (50, 55));
+    assert_eq!(task.status, TaskStatus::Claimed);
+    assert_eq!(task.assignments[0].claim_deadline, 3);
+
+    // A wrong answer is verified in the next block, recorded against the
+    // worker and earns nothing.
+    include(&mut blockchain, &validator, vec![submit(&worker, b"5", 1)]).unwrap();
+    assert_eq!(
+        blockchain.get_task("sum").unwrap().status,
+        TaskStatus::Submitted
+    );
+    include(&mut blockchain, &validator, vec![]).unwrap();
+    let task = blockchain.get_task("sum").unwrap();
+    assert_eq!(task.status, TaskStatus::Disputed);
+    assert_eq!(task.rejections.len(), 1);
+    assert_eq!(task.rejections[0].worker, worker.id());
+    assert_eq!(task.rejections[0].height, 2);
+    assert_eq!(task.rejections[0].reason, RejectReason::ResultMismatch);
+    assert_eq!(blockchain.accounts.balance(&worker.id()), 0);
+
+    // Only the claimant may submit, and an abandoned claim lapses.
+    include(&mut blockchain, &validator, vec![claim(&rival, 0)]).unwrap();
+    assert_eq!(
+        include(&mut blockchain, &validator, vec![submit(&worker, b"4", 2)]),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::InvalidTask(TaskError::NotClaimant)
+        })
+    );
+    include(&mut blockchain, &validator, vec![]).unwrap();
+    include(&mut blockchain, &validator, vec![]).unwrap();
+    assert_eq!(
+        blockchain.get_task("sum").unwrap().status,
+        TaskStatus::Claimed
+    );
+    include(&mut blockchain, &validator, vec![]).unwrap();
+    let task = blockchain.get_task("sum").unwrap();
+    assert_eq!(task.status, TaskStatus::Open);
+    assert!(task.assignments.is_empty());
+
+    include(&mut blockchain, &validator, vec![claim(&worker, 2)]).unwrap();
+    include(&mut blockchain, &validator, vec![submit(&worker, b"4", 3)]).unwrap();
+    assert_eq!(blockchain.accounts.balance(&worker.id()), 0);
+    include(&mut blockchain, &validator, vec![]).unwrap();
+    let task = blockchain.get_task("sum").unwrap();
+    assert_eq!(task.status, TaskStatus::Verified);
+    assert_eq!(task.accepted_workers(), [worker.id().as_str()]);
+    assert_eq!(task.result_hash, Some(sha3_256(b"4")));
+    assert_eq!(blockchain.accounts.balance(&worker.id()), 50);
+    assert_eq!(blockchain.accounts.balance(&validator.id()), 5);
+    assert_eq!(task.escrow, 0);
+    assert_eq!(blockchain.accounts.total_supply, supply);
+
+    // The reward is paid once.
+    assert_eq!(
+        include(&mut blockchain, &validator, vec![claim(&rival, 1)]),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::InvalidTask(TaskError::NotClaimable(TaskStatus::Verified))
+        })
+    );
+    let cancel = TaskCancel {
+        task_id: "sum".to_string(),
+    };
+    assert_eq!(
+        include(
+            &mut blockchain,
+            &validator,
+            vec![signed_tx(
+                &network,
+                &creator,
+                TransactionKind::TaskCancel(cancel),
+                "",
+                0,
+                1
+            )]
+        ),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::InvalidTask(TaskError::NotCancellable(TaskStatus::Verified))
+        })
+    );
+
+    let missing = TaskClaim {
+        task_id: "missing".to_string(),
+    };
+    assert_eq!(
+        include(
+            &mut blockchain,
+            &validator,
+            vec![signed_tx(
+                &network,
+                &worker,
+                TransactionKind::TaskClaim(missing),
+                "",
+                0,
+                4
+            )]
+        ),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::InvalidTask(TaskError::UnknownTask)
+        })
+    );
+}
+
+#[test]
+fn test_replicated_tasks_commit_then_reveal() {
+    let validator = NodeKey::generate().unwrap();
+    let creator = NodeKey::generate().unwrap();
+    let workers: Vec<NodeKey> = (0..3).map(|_| NodeKey::generate().unwrap()).collect();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: validator.id(),
+        stake: 10_000,
+        ..Default::default()
+    }];
+
+    genesis.allocations.insert(creator.id(), 100);
+    for worker in &workers {
+        genesis.allocations.insert(worker.id(), 10);
+    }
+
+    let _ = std::fs::remove_dir_all("./test_blockchain_replicated_tasks");
+    let mut blockchain = Blockchain::new("./test_blockchain_replicated_tasks", genesis).unwrap();
+    let network = blockchain.genesis_hash();
+    let mut task = task_spec("vote");
+    task.expected_result = None;
+    task.replicas = 3;
+    task.claim_blocks = 2;
+
+    let task_tx = |key: &NodeKey, kind: TransactionKind, nonce: u64| {
+        signed_tx(&network, key, kind, "", 0, nonce)
+    };
+    let commit = |key: &NodeKey, commitment: [u8; 32]| {
+        let commit = TaskCommit {
+            task_id: "vote".to_string(),
+            commitment,
+        };
+        task_tx(key, TransactionKind::TaskCommit(commit), 2)
+    };
+    let reveal = |key: &NodeKey, salt: &[u8]| {
+        let reveal = TaskReveal {
+            task_id: "vote".to_string(),
+            result: b"4".to_vec(),
+            salt: salt.to_vec(),
+        };
+        task_tx(key, TransactionKind::TaskReveal(reveal), 3)
+    };
+
+    let mut txs = vec![signed_tx(
+        &network,
+        &creator,
+        TransactionKind::TaskCreate(task),
+        "",
+        100,
+        0,
+    )];
+    // Replicated tasks only take claims from workers with collateral.
+    txs.extend(workers.iter().map(|key| {
+        let registration = WorkerRegistration {
+            capabilities: vec![],
+        };
+        signed_tx(
+            &network,
+            key,
+            TransactionKind::WorkerRegister(registration),
+            "",
+            10,
+            0,
+        )
+    }));
+    txs.extend(workers.iter().map(|key| {
+        let claim = TaskClaim {
+            task_id: "vote".to_string(),
+        };
+        task_tx(key, TransactionKind::TaskClaim(claim), 1)
+    }));
+    include(&mut blockchain, &validator, txs).unwrap();
+    assert_eq!(
+        blockchain.get_task("vote").unwrap().status,
+        TaskStatus::Claimed
+    );
+
+    let first = result_commitment(&workers[0].id(), b"4", b"salt0");
+    let second = result_commitment(&workers[1].id(), b"4", b"salt1");
+    include(
+        &mut blockchain,
+        &validator,
+        vec![commit(&workers[0], first), commit(&workers[1], second)],
+    )
+    .unwrap();
 
-    blockchain.stake("wallet1".to_string(), 100);
-    assert_eq!(blockchain.staking_state.total_staked, 100);
+    // Nothing is revealed until every worker has committed.
+    assert_eq!(
+        include(
+            &mut blockchain,
+            &validator,
+            vec![reveal(&workers[0], b"salt0")]
+        ),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::InvalidTask(TaskError::RevealNotOpen)
+        })
+    );
 
-    let rewards = blockchain.get_rewards(&"wallet1".to_string());
-    assert!(rewards > 0);
+    // A copied commitment is bound to the worker that made it, so the
+    // copier cannot open it.
+    include(
+        &mut blockchain,
+        &validator,
+        vec![commit(&workers[2], first)],
+    )
+    .unwrap();
+    let task = blockchain.get_task("vote").unwrap();
+    assert_eq!(task.status, TaskStatus::Committed);
+    assert_eq!(task.reveal_deadline, Some(5));
+    assert_eq!(
+        include(
+            &mut blockchain,
+            &validator,
+            vec![reveal(&workers[2], b"salt0")]
+        ),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::InvalidTask(TaskError::CommitmentMismatch)
+        })
+    );
+
+    include(
+        &mut blockchain,
+        &validator,
+        vec![reveal(&workers[0], b"salt0"), reveal(&workers[1], b"salt1")],
+    )
+    .unwrap();
+    include(&mut blockchain, &validator, vec![]).unwrap();
+    assert_eq!(
+        blockchain.get_task("vote").unwrap().status,
+        TaskStatus::Committed
+    );
+
+    // Two of three agree once the reveal deadline passes.
+    include(&mut blockchain, &validator, vec![]).unwrap();
+    let task = blockchain.get_task("vote").unwrap();
+    assert_eq!(task.status, TaskStatus::Verified);
+    assert_eq!(task.result_hash, Some(sha3_256(b"4")));
+    assert_eq!(task.rejections.len(), 1);
+    assert_eq!(task.rejections[0].reason, RejectReason::NotRevealed);
+    assert_eq!(blockchain.accounts.balance(&workers[0].id()), 50);
+    assert_eq!(blockchain.accounts.balance(&workers[1].id()), 50);
+    assert_eq!(blockchain.accounts.balance(&workers[2].id()), 0);
+    assert_eq!(
+        blockchain.task_queue.dissent_counts().get(&workers[2].id()),
+        Some(&1)
+    );
+}
+
+#[test]
+fn test_typed_task_results_are_checked_by_running_the_program() {
+    let validator = NodeKey::generate().unwrap();
+    let creator = NodeKey::generate().unwrap();
+    let worker = NodeKey::generate().unwrap();
+    let honest = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: validator.id(),
+        stake: 10_000,
+        ..Default::default()
+    }];
+    genesis.allocations.insert(creator.id(), 100);
+
+    let _ = std::fs::remove_dir_all("./test_blockchain_typed_tasks");
+    let mut blockchain = Blockchain::new("./test_blockchain_typed_tasks", genesis).unwrap();
+    let network = blockchain.genesis_hash();
+    let register = TaskTypeSpec {
+        name: "echo".to_string(),
+        code: common::task_program(common::ECHO),
+        fuel_limit: 10_000,
+    };
+    let mut task = task_spec("copy");
+    task.expected_result = None;
+    task.task_type = Some("echo".to_string());
+    task.claim_blocks = 2;
+
+    let claim = |key: &NodeKey, nonce: u64| {
+        let claim = TaskClaim {
+            task_id: "copy".to_string(),
+        };
+        signed_tx(
+            &network,
+            key,
+            TransactionKind::TaskClaim(claim),
+            "",
+            0,
+            nonce,
+        )
+    };
+    let submit = |key: &NodeKey, result: &[u8], nonce: u64| {
+        let submission = TaskResult {
+            task_id: "copy".to_string(),
+            result: result.to_vec(),
+        };
+        signed_tx(
+            &network,
+            key,
+            TransactionKind::TaskResult(submission),
+            "",
+            0,
+            nonce,
+        )
+    };
+
+    // A task can only name a registered type.
+    assert_eq!(
+        include(
+            &mut blockchain,
+            &validator,
+            vec![signed_tx(
+                &network,
+                &creator,
+                TransactionKind::TaskCreate(task.clone()),
+                "",
+                50,
+                0
+            )]
+        ),
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::InvalidTask(TaskError::UnknownTaskType)
+        })
+    );
+    include(
+        &mut blockchain,
+        &validator,
+        vec![
+            signed_tx(
+                &network,
+                &creator,
+                TransactionKind::TaskTypeRegister(register),
+                "",
+                0,
+                0,
+            ),
+            signed_tx(
+                &network,
+                &creator,
+                TransactionKind::TaskCreate(task),
+                "",
+                50,
+                1,
+            ),
+            claim(&worker, 0),
+        ],
+    )
+    .unwrap();
+    let task_type = blockchain.get_task_type("echo").unwrap();
+    assert_eq!(task_type.owner, creator.id());
+    assert_eq!(
+        task_type.code_hash,
+        sha3_256(&common::task_program(common::ECHO))
+    );
+
+    // A result the program does not produce is rejected.
+    include(
+        &mut blockchain,
+        &validator,
+        vec![submit(&worker, &[3, 2, 1], 1)],
+    )
+    .unwrap();
+    include(&mut blockchain, &validator, vec![]).unwrap();
+    let task = blockchain.get_task("copy").unwrap();
+    assert_eq!(task.status, TaskStatus::Disputed);
+    assert_eq!(task.rejections[0].reason, RejectReason::ExecutionMismatch);
+
+    include(&mut blockchain, &validator, vec![claim(&honest, 0)]).unwrap();
+    include(
+        &mut blockchain,
+        &validator,
+        vec![submit(&honest, &[1, 2, 3], 1)],
+    )
+    .unwrap();
+    include(&mut blockchain, &validator, vec![]).unwrap();
+    let task = blockchain.get_task("copy").unwrap();
+    assert_eq!(task.status, TaskStatus::Verified);
+    assert_eq!(blockchain.accounts.balance(&honest.id()), 50);
+    assert_eq!(blockchain.accounts.balance(&worker.id()), 0);
+}
+
+#[test]
+fn test_worker_registry_bonds_collateral_and_gates_claims() {
+    let validator = NodeKey::generate().unwrap();
+    let creator = NodeKey::generate().unwrap();
+    let worker = NodeKey::generate().unwrap();
+    let newcomer = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: validator.id(),
+        stake: 10_000,
+        ..Default::default()
+    }];
+    genesis.allocations.insert(creator.id(), 100);
+    genesis.allocations.insert(worker.id(), 50);
+
+    let _ = std::fs::remove_dir_all("./test_blockchain_worker_registry");
+    let mut blockchain = Blockchain::new("./test_blockchain_worker_registry", genesis).unwrap();
+    let network = blockchain.genesis_hash();
+    let gated = TaskSpec {
+        min_reputation: 6_000,
+        claim_blocks: 1,
+        ..task_spec("gated")
+    };
+    let registration = WorkerRegistration {
+        capabilities: vec!["echo".to_string()],
+    };
+    include(
+        &mut blockchain,
+        &validator,
+        vec![
+            signed_tx(
+                &network,
+                &creator,
+                TransactionKind::TaskCreate(task_spec("open")),
+                "",
+                10,
+                0,
+            ),
+            signed_tx(
+                &network,
+                &creator,
+                TransactionKind::TaskCreate(gated),
+                "",
+                10,
+                1,
+            ),
+            signed_tx(
+                &network,
+                &worker,
+                TransactionKind::WorkerRegister(registration),
+                "",
+                30,
+                0,
+            ),
+        ],
+    )
+    .unwrap();
+    let info = blockchain.get_worker(&worker.id()).unwrap();
+    assert_eq!(info.collateral, 30);
+    assert_eq!(info.sphincs_public_key, hex::decode(worker.id()).unwrap());
+    assert_eq!(info.reputation(), 5_000);
+    assert_eq!(blockchain.accounts.balance(&worker.id()), 20);
+
+    let claim = |key: &NodeKey, id: &str, nonce: u64| {
+        let claim = TaskClaim {
+            task_id: id.to_string(),
+        };
+        signed_tx(
+            &network,
+            key,
+            TransactionKind::TaskClaim(claim),
+            "",
+            0,
+            nonce,
+        )
+    };
+    let rejected = |error: TaskError| {
+        Err(BlockValidationError::InvalidTransaction {
+            index: 0,
+            error: TransactionError::InvalidTask(error),
+        })
+    };
+    assert_eq!(
+        include(
+            &mut blockchain,
+            &validator,
+            vec![claim(&newcomer, "gated", 0)]
+        ),
+        rejected(TaskError::UnknownWorker)
+    );
+    assert_eq!(
+        include(
+            &mut blockchain,
+            &validator,
+            vec![claim(&worker, "gated", 1)]
+        ),
+        rejected(TaskError::InsufficientReputation {
+            required: 6_000,
+            reputation: 5_000
+        })
+    );
+
+    // A verified result lifts the worker above the task's minimum.
+    let submission = TaskResult {
+        task_id: "open".to_string(),
+        result: vec![1, 2, 3],
+    };
+    include(&mut blockchain, &validator, vec![claim(&worker, "open", 1)]).unwrap();
+    include(
+        &mut blockchain,
+        &validator,
+        vec![signed_tx(
+            &network,
+            &worker,
+            TransactionKind::TaskResult(submission),
+            "",
+            0,
+            2,
+        )],
+    )
+    .unwrap();
+    include(
+        &mut blockchain,
+        &validator,
+        vec![claim(&worker, "gated", 3)],
+    )
+    .unwrap();
+    let info = blockchain.get_worker(&worker.id()).unwrap();
+    assert_eq!((info.verified, info.reputation()), (1, 6_666));
+    assert_eq!(
+        include(
+            &mut blockchain,
+            &validator,
+            vec![signed_tx(
+                &network,
+                &worker,
+                TransactionKind::WorkerUnregister,
+                "",
+                0,
+                4
+            )]
+        ),
+        rejected(TaskError::WorkerBusy)
+    );
+
+    // The claim lapses, which counts against the worker and burns a tenth
+    // of its collateral.
+    let supply = blockchain.accounts.total_supply;
+    include(&mut blockchain, &validator, vec![]).unwrap();
+    include(&mut blockchain, &validator, vec![]).unwrap();
+    let info = blockchain.get_worker(&worker.id()).unwrap();
+    assert_eq!((info.expired, info.reputation()), (1, 5_000));
+    assert_eq!(info.collateral, 27);
+    assert_eq!(blockchain.accounts.total_supply, supply - 3);
+
+    include(
+        &mut blockchain,
+        &validator,
+        vec![signed_tx(
+            &network,
+            &worker,
+            TransactionKind::WorkerUnregister,
+            "",
+            0,
+            4,
+        )],
+    )
+    .unwrap();
+    let info = blockchain.get_worker(&worker.id()).unwrap();
+    assert_eq!((info.active, info.collateral, info.verified), (false, 0, 1));
+    assert_eq!(blockchain.accounts.balance(&worker.id()), 57);
+}
+
+#[test]
+fn test_worker_claims_runs_and_submits_typed_tasks() {
+    let validator = NodeKey::generate().unwrap();
+    let creator = NodeKey::generate().unwrap();
+    let worker_key = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: validator.id(),
+        stake: 10_000,
+        ..Default::default()
+    }];
+    genesis.allocations.insert(creator.id(), 100);
+
+    let _ = std::fs::remove_dir_all("./test_blockchain_worker");
+    let _ = std::fs::remove_file("./test_worker_journal.json");
+    let mut blockchain = Blockchain::new("./test_blockchain_worker", genesis).unwrap();
+    let network = blockchain.genesis_hash();
+    let register = TaskTypeSpec {
+        name: "echo".to_string(),
+        code: common::task_program(common::ECHO),
+        fuel_limit: 10_000,
+    };
+    let typed = |id: &str| TaskSpec {
+        task_type: Some("echo".to_string()),
+        ..task_spec(id)
+    };
+    include(
+        &mut blockchain,
+        &validator,
+        vec![
+            signed_tx(
+                &network,
+                &creator,
+                TransactionKind::TaskTypeRegister(register),
+                "",
+                0,
+                0,
+            ),
+            signed_tx(
+                &network,
+                &creator,
+                TransactionKind::TaskCreate(typed("small")),
+                "",
+                10,
+                1,
+            ),
+            signed_tx(
+                &network,
+                &creator,
+                TransactionKind::TaskCreate(typed("big")),
+                "",
+                40,
+                2,
+            ),
+            signed_tx(
+                &network,
+                &creator,
+                TransactionKind::TaskCreate(task_spec("plain")),
+                "",
+                50,
+                3,
+            ),
+        ],
+    )
+    .unwrap();
+
+    let config = WorkerConfig {
+        wallet: worker_key.id(),
+        task_types: vec!["echo".to_string()],
+        max_jobs: 1,
+        poll_interval: std::time::Duration::from_millis(10),
+        journal_path: "./test_worker_journal.json".to_string(),
+    };
+    let mut worker = Worker::open(config.clone()).unwrap();
+    let mut nonce = 0;
+    let mut send =
+        |worker: &mut Worker, blockchain: &mut Blockchain, kinds: Vec<TransactionKind>| {
+            for kind in kinds {
+                let tx = signed_tx(&network, &worker_key, kind.clone(), "", 0, nonce);
+                nonce += 1;
+                include(blockchain, &validator, vec![tx]).unwrap();
+                worker.confirm(&kind, Ok(()));
+            }
+        };
+
+    // Only one job at a time, the best paid task of a type it runs first.
+    let (transactions, runs) = worker.plan(&blockchain);
+    assert!(runs.is_empty());
+    assert_eq!(
+        transactions,
+        vec![TransactionKind::TaskClaim(TaskClaim {
+            task_id: "big".to_string()
+        })]
+    );
+    send(&mut worker, &mut blockchain, transactions);
+
+    let (transactions, runs) = worker.plan(&blockchain);
+    assert!(transactions.is_empty());
+    assert_eq!(runs.len(), 1);
+    worker.record_execution(&runs[0].task_id, runs[0].execute());
+    let (transactions, _) = worker.plan(&blockchain);
+    assert_eq!(
+        transactions,
+        vec![TransactionKind::TaskResult(TaskResult {
+            task_id: "big".to_string(),
+            result: vec![1, 2, 3]
+        })]
+    );
+    send(&mut worker, &mut blockchain, transactions);
+    include(&mut blockchain, &validator, vec![]).unwrap();
+    assert_eq!(blockchain.accounts.balance(&worker_key.id()), 40);
+
+    // Once paid, the job is finished and the next task is claimed.
+    let (transactions, _) = worker.plan(&blockchain);
+    let claim_small = vec![TransactionKind::TaskClaim(TaskClaim {
+        task_id: "small".to_string(),
+    })];
+    assert_eq!(transactions, claim_small);
+
+    // A claim that was turned down ends its job, and the task is taken on
+    // again while it is still open.
+    worker.confirm(&transactions[0], Err("rejected".to_string()));
+    let (transactions, _) = worker.plan(&blockchain);
+    assert_eq!(transactions, claim_small);
+
+    // A claim waiting in the pool keeps its job open until a block has it.
+    let nonce = blockchain.next_nonce(&worker_key.id());
+    let claim = signed_tx(&network, &worker_key, transactions[0].clone(), "", 0, nonce);
+    blockchain.add_pending_transaction(claim).unwrap();
+    worker.confirm(&transactions[0], Ok(()));
+    let (transactions, runs) = worker.plan(&blockchain);
+    assert!(transactions.is_empty() && runs.is_empty());
+
+    // The journal survives a restart.
+    let journal = Journal::open(&config.journal_path).unwrap();
+    let big = journal.get("big").unwrap();
+    assert_eq!(big.outcome, Some(JobOutcome::Accepted));
+    assert_eq!(big.result, Some(vec![1, 2, 3]));
+    assert_eq!(journal.get("small").unwrap().stage, JobStage::Claimed);
+    assert!(journal.get("plain").is_none());
+}
+
+/// A single-replica task with no expected result that never expires.
+fn task_spec(id: &str) -> TaskSpec {
+    TaskSpec {
+        id: id.to_string(),
+        description: "Train model".to_string(),
+        data: vec![1, 2, 3],
+        expected_result: Some(sha3_256(&[1, 2, 3])),
+        task_type: None,
+        replicas: 1,
+        claim_blocks: 100,
+        ttl_blocks: None,
+        verifier_fee: 0,
+        min_reputation: 0,
+    }
+}
+
+/// A transaction from `sender` signed with its key for the network whose
+/// genesis block hashes to `network`.
+fn signed_tx(
+    network: &Hash,
+    sender: &NodeKey,
+    kind: TransactionKind,
+    receiver: &str,
+    amount: u64,
+    nonce: u64,
+) -> Transaction {
+    let mut tx = Transaction {
+        sender: sender.id(),
+        receiver: receiver.to_string(),
+        amount,
+        nonce,
+        kind,
+        signature: vec![],
+    };
+    tx.signature = sender.sign(&tx.signing_bytes(network)).unwrap();
+    tx
+}
 
-    let success = blockchain.unstake("wallet1".to_string(), 50);
-    assert!(success);
-    assert_eq!(blockchain.staking_state.total_staked, 50);
+/// Proposes a block carrying `transactions` and adds it to the chain.
+fn include(
+    blockchain: &mut Blockchain,
+    proposer: &NodeKey,
+    transactions: Vec<Transaction>,
+) -> Result<BlockStatus, BlockValidationError> {
+    let block = blockchain.propose_block(transactions, proposer)?;
+    blockchain.add_block(block)
 }
@@ -0,0 +1,48 @@
+/// `run` body echoing its input: returns `ptr << 32 | len`.
+pub const ECHO: &[u8] = &[
+    0x00, 0x20, 0x00, 0xad, 0x42, 0x20, 0x86, 0x20, 0x01, 0xad, 0x84, 0x0b,
+];
+
+/// A task program in the WebAssembly binary format with one page of
+/// memory, an `alloc` that always places the input at offset 1024 and
+/// `run` as the body of its `run` export.
+pub fn task_program(run: &[u8]) -> Vec<u8> {
+    let mut code = b"\0asm\x01\0\0\0".to_vec();
+    // Types (i32) -> i32 and (i32, i32) -> i64, and a function of each.
+    code.extend([0x01, 0x0c, 0x02, 0x60, 0x01, 0x7f, 0x01, 0x7f]);
+    code.extend([0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7e]);
+    code.extend([0x03, 0x03, 0x02, 0x00, 0x01]);
+    code.extend([0x05, 0x03, 0x01, 0x00, 0x01]);
+    code.extend([0x07, 0x18, 0x03, 0x06]);
+    code.extend(b"memory\x02\0\x05alloc\0\0\x03run\0\x01");
+    code.extend([
+        0x0a,
+        8 + run.len() as u8,
+        0x02,
+        0x05,
+        0x00,
+        0x41,
+        0x80,
+        0x08,
+        0x0b,
+    ]);
+    code.push(run.len() as u8);
+    code.extend_from_slice(run);
+    code
+}
+
+/// The key among `keys` elected to propose the next block.
+pub fn elected<'a>(blockchain: &Blockchain, keys: &[&'a NodeKey]) -> &'a NodeKey {
+    let expected = blockchain.next_proposer().unwrap();
+    keys.iter().find(|key| key.id() == expected).unwrap()
+}
+
+pub fn test_genesis() -> GenesisConfig {
+    GenesisConfig {
+        chain_id: "quantumcoin-test".to_string(),
+        genesis_time: 0,
+        allocations: Default::default(),
+        validators: vec![],
+        consensus: Default::default(),
+    }
+}
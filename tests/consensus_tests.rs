@@ -1,7 +1,1107 @@
+mod common;
+
 #[test]
 fn test_validate_block() {
-    let blockchain = Blockchain::new("./test_blockchain");
+    let proposer = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: proposer.id(),
+        stake: 1_000,
+        ..Default::default()
+    }];
+    let blockchain = Blockchain::new("./test_blockchain", genesis).unwrap();
+
+    let block = blockchain.propose_block(vec![], &proposer).unwrap();
+    assert!(blockchain.validate_block(&block).is_ok());
+}
+
+#[test]
+fn test_rejects_block_with_wrong_parent() {
+    let proposer = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: proposer.id(),
+        stake: 1_000,
+        ..Default::default()
+    }];
+    let blockchain = Blockchain::new("./test_blockchain", genesis).unwrap();
+
+    let block = Block::new(1, [7u8; 32], blockchain.state_root(), vec![], &proposer);
+    assert_eq!(
+        blockchain.validate_block(&block),
+        Err(BlockValidationError::PreviousHashMismatch { height: 1 })
+    );
+}
+
+#[test]
+fn test_rejects_block_from_non_validator() {
+    let blockchain = Blockchain::new("./test_blockchain", common::test_genesis()).unwrap();
+    let proposer = NodeKey::generate().unwrap();
+
+    let block = Block::new(
+        1,
+        blockchain.latest_block().hash,
+        blockchain.state_root(),
+        vec![],
+        &proposer,
+    );
+    assert_eq!(
+        blockchain.validate_block(&block),
+        Err(BlockValidationError::UnknownProposer {
+            height: 1,
+            proposer: proposer.id(),
+        })
+    );
+}
+
+#[test]
+fn test_rejects_block_with_wrong_state_root() {
+    let proposer = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: proposer.id(),
+        stake: 1_000,
+        ..Default::default()
+    }];
+    let mut blockchain = Blockchain::new("./test_blockchain_state_root", genesis).unwrap();
+
+    let block = Block::new(
+        1,
+        blockchain.latest_block().hash,
+        [9u8; 32],
+        vec![],
+        &proposer,
+    );
+    assert_eq!(
+        blockchain.validate_block(&block),
+        Err(BlockValidationError::StateRootMismatch { height: 1 })
+    );
+    assert_eq!(
+        blockchain.add_block(block),
+        Err(BlockValidationError::StateRootMismatch { height: 1 })
+    );
+    assert_eq!(blockchain.chain.len(), 1);
+
+    let block = blockchain.propose_block(vec![], &proposer).unwrap();
+    assert_eq!(blockchain.add_block(block).unwrap(), BlockStatus::Extended);
+}
+
+#[test]
+fn test_rejects_block_from_validator_not_elected() {
+    let first = NodeKey::generate().unwrap();
+    let second = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = [&first, &second]
+        .iter()
+        .map(|key| GenesisValidator {
+            address: key.id(),
+            stake: 1_000,
+            ..Default::default()
+        })
+        .collect();
+    let mut blockchain = Blockchain::new("./test_blockchain_election", genesis).unwrap();
+
+    // The genesis timestamp is long past, so any ranked validator may fill
+    // the first slot. Check the second, right after its parent.
+    let block = blockchain
+        .propose_block(vec![], common::elected(&blockchain, &[&first, &second]))
+        .unwrap();
+    blockchain.add_block(block).unwrap();
+
+    let leader = common::elected(&blockchain, &[&first, &second]);
+    let other = if leader.id() == first.id() {
+        &second
+    } else {
+        &first
+    };
+    let block = Block::new(
+        2,
+        blockchain.latest_block().hash,
+        blockchain.state_root(),
+        vec![],
+        other,
+    );
+    assert_eq!(
+        blockchain.validate_block(&block),
+        Err(BlockValidationError::WrongProposer {
+            height: 2,
+            expected: leader.id(),
+            proposer: other.id(),
+        })
+    );
+}
+
+#[test]
+fn test_ranked_proposer_takes_over_after_timeout() {
+    let first = NodeKey::generate().unwrap();
+    let second = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = [&first, &second]
+        .iter()
+        .map(|key| GenesisValidator {
+            address: key.id(),
+            stake: 1_000,
+            ..Default::default()
+        })
+        .collect();
+    genesis.consensus.proposer_timeout_ms = 20_000;
+    let mut blockchain = Blockchain::new("./test_blockchain_timeout", genesis).unwrap();
+
+    // Backdate block 1 so the runner-up's turn has already come.
+    let proposer = common::elected(&blockchain, &[&first, &second]);
+    let mut header = blockchain.propose_block(vec![], proposer).unwrap().header;
+    header.timestamp -= 20_000;
+    let mut block = Block::from_header(header, vec![]);
+    block.signature = proposer.sign(&block.hash).unwrap();
+    blockchain.add_block(block).unwrap();
+
+    let leader = common::elected(&blockchain, &[&first, &second]);
+    let other = if leader.id() == first.id() {
+        &second
+    } else {
+        &first
+    };
+    let mut header = blockchain.propose_block(vec![], other).unwrap().header;
+    header.timestamp = blockchain.latest_block().header.timestamp + 1;
+    let mut early = Block::from_header(header.clone(), vec![]);
+    early.signature = other.sign(&early.hash).unwrap();
+    assert!(matches!(
+        blockchain.add_block(early),
+        Err(BlockValidationError::WrongProposer { height: 2, .. })
+    ));
+
+    // Once the elected proposer has had its turn, the runner-up may propose,
+    // and the leader is charged with a missed slot.
+    header.timestamp = blockchain.latest_block().header.timestamp + 20_000;
+    let mut late = Block::from_header(header, vec![]);
+    late.signature = other.sign(&late.hash).unwrap();
+    assert_eq!(blockchain.add_block(late).unwrap(), BlockStatus::Extended);
+    assert_eq!(blockchain.staking_state.missed_slots[&leader.id()], 1);
+}
+
+#[test]
+fn test_proposer_election_is_stake_weighted() {
+    let light = NodeKey::generate().unwrap();
+    let heavy = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![
+        GenesisValidator {
+            address: light.id(),
+            stake: 1_000,
+            ..Default::default()
+        },
+        GenesisValidator {
+            address: heavy.id(),
+            stake: 3_000,
+            ..Default::default()
+        },
+    ];
+    let blockchain = Blockchain::new("./test_blockchain_weighted", genesis).unwrap();
+    let staking = &blockchain.staking_state;
+
+    let parent = blockchain.latest_block().hash;
+    assert_eq!(
+        select_proposer(staking, &parent, 1),
+        select_proposer(staking, &parent, 1)
+    );
+
+    let heavy_slots = (1..=1_000)
+        .filter(|height| select_proposer(staking, &parent, *height) == Some(heavy.id().as_str()))
+        .count();
+    assert!((650..=850).contains(&heavy_slots), "{}", heavy_slots);
+}
+
+#[test]
+fn test_heavier_branch_triggers_reorg() {
+    let light = NodeKey::generate().unwrap();
+    let heavy = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![
+        GenesisValidator {
+            address: light.id(),
+            stake: 1_000,
+            ..Default::default()
+        },
+        GenesisValidator {
+            address: heavy.id(),
+            stake: 10_000,
+            ..Default::default()
+        },
+    ];
+    let mut blockchain = Blockchain::new("./test_blockchain_reorg", genesis.clone()).unwrap();
+    let mut fork = Blockchain::new("./test_blockchain_reorg_fork", genesis).unwrap();
+
+    let leader = common::elected(&blockchain, &[&light, &heavy]);
+    let a1 = blockchain.propose_block(vec![], leader).unwrap();
+    assert_eq!(
+        blockchain.add_block(a1.clone()).unwrap(),
+        BlockStatus::Extended
+    );
+
+    // A competing block for the same slot, and a second block on top of it.
+    let mut header = a1.header.clone();
+    header.timestamp += 1;
+    let mut b1 = Block::from_header(header, vec![]);
+    b1.signature = leader.sign(&b1.hash).unwrap();
+    assert_eq!(
+        blockchain.add_block(b1.clone()).unwrap(),
+        BlockStatus::SideChain
+    );
+
+    let next = select_proposer(&blockchain.staking_state, &b1.hash, 2).unwrap();
+    let next = [&light, &heavy]
+        .into_iter()
+        .find(|key| key.id() == next)
+        .unwrap();
+    fork.add_block(b1).unwrap();
+    let b2 = fork.propose_block(vec![], next).unwrap();
+    let b2_hash = b2.hash;
+    assert_eq!(
+        blockchain.add_block(b2).unwrap(),
+        BlockStatus::Reorganized { depth: 1 }
+    );
+    assert_eq!(blockchain.latest_block().hash, b2_hash);
+    assert_eq!(blockchain.side_blocks.len(), 1);
+}
+
+#[test]
+fn test_bft_round_commits_and_finalizes_block() {
+    let keys = validator_keys(4);
+    let mut blockchain = Blockchain::new("./test_blockchain_finality", bft_genesis(&keys)).unwrap();
+    let parent = blockchain.latest_block().hash;
+    let mut nodes: Vec<RoundState> = keys
+        .iter()
+        .map(|key| {
+            RoundState::new(
+                &blockchain.staking_state,
+                &parent,
+                1,
+                BftParams::default(),
+                Some(key.clone()),
+            )
+        })
+        .collect();
+
+    // Deliver every proposal and vote to every node until the queue drains.
+    let mut queue = std::collections::VecDeque::new();
+    for (i, node) in nodes.iter_mut().enumerate() {
+        queue.extend(node.start().into_iter().map(|action| (i, action)));
+    }
+    let mut proposed = None;
+    let mut commits = Vec::new();
+    while let Some((from, action)) = queue.pop_front() {
+        match action {
+            Action::Propose { round, valid_block } => {
+                assert_eq!((round, valid_block), (0, None));
+                let block = blockchain.propose_block(vec![], &keys[from]).unwrap();
+                let valid = blockchain.validate_block(&block).is_ok();
+                for (i, node) in nodes.iter_mut().enumerate() {
+                    let actions = node.on_proposal(round, block.hash, None, valid);
+                    queue.extend(actions.into_iter().map(|action| (i, action)));
+                }
+                proposed = Some(block);
+            }
+            Action::Broadcast(vote) => {
+                for (i, node) in nodes.iter_mut().enumerate().filter(|(i, _)| *i != from) {
+                    let actions = node.on_vote(vote.clone()).unwrap();
+                    queue.extend(actions.into_iter().map(|action| (i, action)));
+                }
+            }
+            Action::ScheduleTimeout { .. } => {}
+            Action::Commit(qc) => commits.push(qc),
+        }
+    }
+
+    let block = proposed.unwrap();
+    assert_eq!(commits.len(), 4);
+    assert!(commits
+        .iter()
+        .all(|qc| qc.block_hash == block.hash && qc.round == 0));
+
+    let leader = keys
+        .iter()
+        .find(|key| key.id() == block.header.proposer)
+        .unwrap();
+    blockchain.add_block(block.clone()).unwrap();
+    blockchain.finalize(commits[0].clone()).unwrap();
+    assert_eq!(blockchain.finalized_height, 1);
+    assert_eq!(blockchain.chain[1].commit, Some(commits[0].clone()));
+    assert_eq!(
+        blockchain.finalize(commits[1].clone()),
+        Err(BftError::AlreadyFinalized { height: 1 })
+    );
+
+    // A competing block for the finalized slot is refused outright.
+    let mut header = block.header;
+    header.timestamp += 1;
+    let mut competing = Block::from_header(header, vec![]);
+    competing.signature = leader.sign(&competing.hash).unwrap();
+    assert_eq!(
+        blockchain.add_block(competing),
+        Err(BlockValidationError::ConflictsWithFinalized { height: 1 })
+    );
+}
+
+#[test]
+fn test_finality_driver_votes_on_canonical_block_and_finalizes_it() {
+    let keys = validator_keys(4);
+    let genesis = bft_genesis(&keys);
+    let mut chains: Vec<Blockchain> = (0..keys.len())
+        .map(|i| {
+            let path = format!("./test_blockchain_finality_driver_{}", i);
+            Blockchain::new(&path, genesis.clone()).unwrap()
+        })
+        .collect();
+    let mut nodes: Vec<Finality> = keys.iter().map(|key| Finality::new(key.clone())).collect();
+
+    // Nothing to vote on until the next block is on the chain.
+    nodes[0].tick(&mut chains[0], std::time::Instant::now());
+    assert!(nodes[0].round().is_none());
+
+    let signers: Vec<&NodeKey> = keys.iter().map(|key| key.as_ref()).collect();
+    let block = chains[0]
+        .propose_block(vec![], common::elected(&chains[0], &signers))
+        .unwrap();
+    for (node, chain) in nodes.iter_mut().zip(chains.iter_mut()) {
+        chain.add_block(block.clone()).unwrap();
+        node.tick(chain, std::time::Instant::now());
+        assert_eq!(node.round().unwrap().height, 1);
+        assert_eq!(node.votes().len(), 1);
+    }
+
+    // Relay every node's votes to the others, and the certificate of a node
+    // that has finalized to those that have not, until each has finalized.
+    for _ in 0..4 {
+        for from in 0..nodes.len() {
+            let votes = nodes[from].votes().to_vec();
+            let commit = nodes[from].last_commit().cloned();
+            for to in (0..nodes.len()).filter(|to| *to != from) {
+                for vote in &votes {
+                    let _ = nodes[to].on_vote(&mut chains[to], vote.clone());
+                }
+                if let Some(qc) = &commit {
+                    let _ = chains[to].finalize(qc.clone());
+                }
+            }
+        }
+    }
+    for (node, chain) in nodes.iter_mut().zip(chains.iter_mut()) {
+        assert_eq!(chain.finalized_height, 1);
+        assert!(chain.get_block(1).unwrap().commit.is_some());
+        node.tick(chain, std::time::Instant::now());
+        assert!(node.round().is_none());
+    }
+}
+
+#[test]
+fn test_finalize_requires_two_thirds_of_stake() {
+    let keys = validator_keys(4);
+    let mut blockchain = Blockchain::new("./test_blockchain_quorum", bft_genesis(&keys)).unwrap();
+    let leader = common::elected(
+        &blockchain,
+        &keys.iter().map(|key| &**key).collect::<Vec<_>>(),
+    );
+    let block = blockchain.propose_block(vec![], leader).unwrap();
+    blockchain.add_block(block.clone()).unwrap();
+
+    let precommit = |key: &NodeKey| {
+        let vote = Vote::new(VoteType::Precommit, 1, 0, Some(block.hash), key);
+        CommitSignature {
+            validator: vote.validator,
+            signature: vote.signature,
+        }
+    };
+    let mut qc = QuorumCertificate {
+        height: 1,
+        round: 0,
+        block_hash: block.hash,
+        signatures: keys[..2].iter().map(|key| precommit(key)).collect(),
+    };
+    assert_eq!(
+        blockchain.finalize(qc.clone()),
+        Err(BftError::InsufficientQuorum {
+            power: 2_000,
+            total: 4_000
+        })
+    );
+
+    qc.signatures.push(qc.signatures[0].clone());
+    assert_eq!(
+        blockchain.finalize(qc.clone()),
+        Err(BftError::DuplicateVote(keys[0].id()))
+    );
+
+    qc.signatures[2] = precommit(&keys[2]);
+    blockchain.finalize(qc).unwrap();
+    assert_eq!(blockchain.finalized_height, 1);
+}
+
+#[test]
+fn test_bft_timeouts_move_to_next_round() {
+    let keys = validator_keys(4);
+    let blockchain = Blockchain::new("./test_blockchain_bft_timeouts", bft_genesis(&keys)).unwrap();
+    let parent = blockchain.latest_block().hash;
+    let params = BftParams::default();
+    let mut node = RoundState::new(
+        &blockchain.staking_state,
+        &parent,
+        1,
+        params.clone(),
+        Some(keys[0].clone()),
+    );
+    let other_block = [7u8; 32];
+
+    let actions = node.start();
+    assert!(actions.contains(&Action::ScheduleTimeout {
+        step: Step::Propose,
+        round: 0,
+        after_ms: 3_000,
+    }));
+
+    // No proposal arrives, so the node prevotes nil.
+    let actions = node.on_timeout(Step::Propose, 1, 0);
+    assert!(matches!(
+        &actions[..],
+        [Action::Broadcast(Vote {
+            vote_type: VoteType::Prevote,
+            block_hash: None,
+            ..
+        })]
+    ));
+
+    // Two thirds prevote but split, so the node waits before precommitting nil.
+    node.on_vote(Vote::new(VoteType::Prevote, 1, 0, None, &keys[1]))
+        .unwrap();
+    let actions = node
+        .on_vote(Vote::new(
+            VoteType::Prevote,
+            1,
+            0,
+            Some(other_block),
+            &keys[2],
+        ))
+        .unwrap();
+    assert_eq!(
+        actions,
+        vec![Action::ScheduleTimeout {
+            step: Step::Prevote,
+            round: 0,
+            after_ms: 1_000,
+        }]
+    );
+    let actions = node.on_timeout(Step::Prevote, 1, 0);
+    assert!(matches!(
+        &actions[..],
+        [Action::Broadcast(Vote {
+            vote_type: VoteType::Precommit,
+            block_hash: None,
+            ..
+        })]
+    ));
+
+    node.on_vote(Vote::new(VoteType::Precommit, 1, 0, None, &keys[1]))
+        .unwrap();
+    let actions = node
+        .on_vote(Vote::new(
+            VoteType::Precommit,
+            1,
+            0,
+            Some(other_block),
+            &keys[2],
+        ))
+        .unwrap();
+    assert_eq!(
+        actions,
+        vec![Action::ScheduleTimeout {
+            step: Step::Precommit,
+            round: 0,
+            after_ms: 1_000,
+        }]
+    );
+    let actions = node.on_timeout(Step::Precommit, 1, 0);
+    assert_eq!((node.round, node.step), (1, Step::Propose));
+    assert!(actions.contains(&Action::ScheduleTimeout {
+        step: Step::Propose,
+        round: 1,
+        after_ms: 3_500,
+    }));
+    assert!(node.on_timeout(Step::Propose, 1, 0).is_empty());
+
+    // Votes from over a third of the stake in a later round pull the node
+    // forward.
+    node.on_vote(Vote::new(VoteType::Prevote, 1, 5, None, &keys[1]))
+        .unwrap();
+    assert_eq!(node.round, 1);
+    node.on_vote(Vote::new(VoteType::Prevote, 1, 5, None, &keys[2]))
+        .unwrap();
+    assert_eq!(node.round, 5);
+
+    assert_eq!(
+        node.on_vote(Vote::new(
+            VoteType::Prevote,
+            1,
+            5,
+            Some(other_block),
+            &keys[1]
+        )),
+        Err(BftError::DuplicateVote(keys[1].id()))
+    );
+    assert_eq!(
+        node.on_vote(Vote::new(VoteType::Prevote, 2, 0, None, &keys[1])),
+        Err(BftError::WrongHeight {
+            expected: 1,
+            found: 2
+        })
+    );
+}
+
+#[test]
+fn test_dev_engine_needs_no_validator_set() {
+    let authority = NodeKey::generate().unwrap();
+    let outsider = NodeKey::generate().unwrap();
+    assert_eq!("dev".parse::<EngineKind>(), Ok(EngineKind::Dev));
+    let mut blockchain = Blockchain::with_consensus(
+        "./test_blockchain_dev",
+        common::test_genesis(),
+        EngineKind::Dev.build(&authority.id()),
+    )
+    .unwrap();
+    assert!(blockchain.staking_state.validators.is_empty());
+    assert_eq!(blockchain.next_proposer(), Some(authority.id().as_str()));
+
+    let block = blockchain.propose_block(vec![], &outsider).unwrap();
+    assert_eq!(
+        blockchain.add_block(block),
+        Err(BlockValidationError::WrongProposer {
+            height: 1,
+            expected: authority.id(),
+            proposer: outsider.id(),
+        })
+    );
+
+    for height in 1..=3 {
+        let block = blockchain.propose_block(vec![], &authority).unwrap();
+        assert_eq!(blockchain.add_block(block).unwrap(), BlockStatus::Extended);
+        assert_eq!(blockchain.finalized_height, height);
+    }
+}
+
+#[test]
+fn test_only_pos_engine_pays_staking_rewards() {
+    let validator = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: validator.id(),
+        stake: 1_000,
+        ..Default::default()
+    }];
+    genesis.consensus.epoch_length = 2;
+    genesis.consensus.epoch_reward_bps = 1_000;
+
+    for (engine, path, paid) in [
+        (
+            EngineKind::ProofOfStake,
+            "./test_blockchain_pos_rewards",
+            100,
+        ),
+        (
+            EngineKind::ProofOfUsefulWork,
+            "./test_blockchain_pouw_rewards",
+            0,
+        ),
+    ] {
+        let mut blockchain =
+            Blockchain::with_consensus(path, genesis.clone(), engine.build("")).unwrap();
+        for _ in 0..2 {
+            let block = blockchain.propose_block(vec![], &validator).unwrap();
+            blockchain.add_block(block).unwrap();
+        }
+        assert_eq!(blockchain.staking_state.total_rewards_paid, paid);
+        assert_eq!(blockchain.finalized_height, 0);
+    }
+}
+
+#[test]
+fn test_pouw_engine_pays_issuance_for_verified_work() {
+    let validator = NodeKey::generate().unwrap();
+    let creator = NodeKey::generate().unwrap();
+    let worker = NodeKey::generate().unwrap();
+    let mut genesis = common::test_genesis();
+    genesis.validators = vec![GenesisValidator {
+        address: validator.id(),
+        stake: 10_000,
+        ..Default::default()
+    }];
+    genesis.allocations.insert(creator.id(), 100);
+    genesis.allocations.insert(worker.id(), 50);
+    genesis.consensus.epoch_length = 10;
+    genesis.consensus.epoch_reward_bps = 1_000;
+
+    let mut blockchain = Blockchain::with_consensus(
+        "./test_blockchain_pouw_issuance",
+        genesis,
+        EngineKind::ProofOfUsefulWork.build(""),
+    )
+    .unwrap();
+    let network = blockchain.genesis_hash();
+    let supply = blockchain.accounts.total_supply;
+    let sign = |key: &NodeKey, kind: TransactionKind, amount: u64, nonce: u64| {
+        let mut tx = Transaction {
+            sender: key.id(),
+            receiver: String::new(),
+            amount,
+            nonce,
+            kind,
+            signature: vec![],
+        };
+        tx.signature = key.sign(&tx.signing_bytes(&network)).unwrap();
+        tx
+    };
+    let task = TaskSpec {
+        id: "sum".to_string(),
+        description: "".to_string(),
+        data: vec![],
+        expected_result: Some(sha3_256(b"4")),
+        task_type: None,
+        replicas: 1,
+        claim_blocks: 10,
+        ttl_blocks: None,
+        verifier_fee: 0,
+        min_reputation: 0,
+    };
+    let registration = WorkerRegistration {
+        capabilities: vec![],
+    };
+    let blocks = vec![
+        vec![
+            sign(&creator, TransactionKind::TaskCreate(task), 20, 0),
+            sign(
+                &worker,
+                TransactionKind::WorkerRegister(registration),
+                10,
+                0,
+            ),
+        ],
+        vec![sign(
+            &worker,
+            TransactionKind::TaskClaim(TaskClaim {
+                task_id: "sum".to_string(),
+            }),
+            0,
+            1,
+        )],
+        vec![sign(
+            &worker,
+            TransactionKind::TaskResult(TaskResult {
+                task_id: "sum".to_string(),
+                result: b"4".to_vec(),
+            }),
+            0,
+            2,
+        )],
+    ];
+    for transactions in blocks {
+        let block = blockchain.propose_block(transactions, &validator).unwrap();
+        blockchain.add_block(block).unwrap();
+    }
+    // Nothing has been verified yet, so nothing was minted.
+    assert_eq!(blockchain.accounts.total_supply, supply);
+
+    // The next block verifies the result: the worker is paid the task's
+    // reward from escrow plus the block's 10_000 * 10% / 10 issuance.
+    let block = blockchain.propose_block(vec![], &validator).unwrap();
+    blockchain.add_block(block).unwrap();
+    assert_eq!(
+        blockchain.get_task("sum").unwrap().status,
+        TaskStatus::Verified
+    );
+    assert_eq!(
+        blockchain.accounts.balance(&worker.id()),
+        50 - 10 + 20 + 100
+    );
+    assert_eq!(blockchain.accounts.total_supply, supply + 100);
+    assert_eq!(blockchain.staking_state.total_rewards_paid, 100);
+
+    let block = blockchain.propose_block(vec![], &validator).unwrap();
+    blockchain.add_block(block).unwrap();
+    assert_eq!(blockchain.accounts.total_supply, supply + 100);
+}
+
+#[test]
+fn test_tasks_expire_and_cancel() {
+    let mut queue = TaskQueue::new();
+    let spec = |id: &str, ttl_blocks: Option<u64>| TaskSpec {
+        id: id.to_string(),
+        description: "".to_string(),
+        data: vec![],
+        expected_result: Some(sha3_256(b"")),
+        task_type: None,
+        replicas: 1,
+        claim_blocks: 100,
+        ttl_blocks,
+        verifier_fee: 2,
+        min_reputation: 0,
+    };
+    queue
+        .create_task("creator", &spec("ttl", Some(5)), 12, &[], 0)
+        .unwrap();
+    queue
+        .create_task("creator", &spec("drop", None), 10, &[], 0)
+        .unwrap();
+    assert_eq!(
+        queue.create_task("creator", &spec("drop", None), 10, &[], 0),
+        Err(TaskError::DuplicateTask)
+    );
+    assert_eq!(
+        queue.create_task("creator", &spec("free", None), 2, &[], 0),
+        Err(TaskError::InsufficientEscrow)
+    );
+    let unchecked = TaskSpec {
+        expected_result: None,
+        ..spec("unchecked", None)
+    };
+    assert_eq!(
+        queue.create_task("creator", &unchecked, 10, &[], 0),
+        Err(TaskError::Unverifiable)
+    );
+    assert_eq!(queue.get_task("ttl").unwrap().reward, 10);
+
+    let claim = TaskClaim {
+        task_id: "ttl".to_string(),
+    };
+    queue.claim("worker", &claim, 3).unwrap();
+    let cancel = TaskCancel {
+        task_id: "ttl".to_string(),
+    };
+    assert_eq!(
+        queue.cancel_task("creator", &cancel),
+        Err(TaskError::NotCancellable(TaskStatus::Claimed))
+    );
+    assert!(queue.process_block(5, "proposer", 0).0.is_empty());
+    assert_eq!(queue.get_task("ttl").unwrap().status, TaskStatus::Claimed);
+
+    // Expiry refunds the whole escrow, verifier fee included.
+    assert_eq!(
+        queue.process_block(6, "proposer", 0),
+        (vec![("creator".to_string(), 12)], 0)
+    );
+    let task = queue.get_task("ttl").unwrap();
+    assert_eq!((task.status, task.escrow), (TaskStatus::Expired, 0));
+
+    let cancel = TaskCancel {
+        task_id: "drop".to_string(),
+    };
+    assert_eq!(
+        queue.cancel_task("mallory", &cancel),
+        Err(TaskError::NotCreator)
+    );
+    assert_eq!(queue.cancel_task("creator", &cancel), Ok(10));
+    assert_eq!(
+        queue.cancel_task("creator", &cancel),
+        Err(TaskError::NotCancellable(TaskStatus::Cancelled))
+    );
+    let claim = TaskClaim {
+        task_id: "drop".to_string(),
+    };
+    assert_eq!(
+        queue.claim("worker", &claim, 7),
+        Err(TaskError::NotClaimable(TaskStatus::Cancelled))
+    );
+    assert!(queue.get_pending_tasks().is_empty());
+}
+
+#[test]
+fn test_unmatched_commitments_lapse() {
+    let mut queue = TaskQueue::new();
+    let spec = TaskSpec {
+        id: "pair".to_string(),
+        description: "".to_string(),
+        data: vec![],
+        expected_result: None,
+        task_type: None,
+        replicas: 2,
+        claim_blocks: 10,
+        ttl_blocks: None,
+        verifier_fee: 0,
+        min_reputation: 0,
+    };
+    queue.create_task("creator", &spec, 10, &[], 0).unwrap();
+    let claim = TaskClaim {
+        task_id: "pair".to_string(),
+    };
+    assert_eq!(queue.claim("w1", &claim, 1), Err(TaskError::UnknownWorker));
+    let registration = WorkerRegistration {
+        capabilities: vec![],
+    };
+    queue.register_worker("w1", &registration, 0, 0).unwrap();
+    assert_eq!(queue.claim("w1", &claim, 1), Err(TaskError::NoCollateral));
+    queue.register_worker("w1", &registration, 5, 0).unwrap();
+    queue.claim("w1", &claim, 1).unwrap();
+    let commit = TaskCommit {
+        task_id: "pair".to_string(),
+        commitment: result_commitment("w1", b"answer", b"salt"),
+    };
+    queue.commit_result("w1", &commit, 2).unwrap();
+    let cancel = TaskCancel {
+        task_id: "pair".to_string(),
+    };
+
+    // The commitment outlives the claim by one claim period while the
+    // second slot stays empty, then the task reopens and can be cancelled.
+    queue.process_block(21, "proposer", 0);
+    assert_eq!(queue.get_task("pair").unwrap().assignments.len(), 1);
+    assert_eq!(
+        queue.cancel_task("creator", &cancel),
+        Err(TaskError::NotCancellable(TaskStatus::Open))
+    );
+    queue.process_block(22, "proposer", 0);
+    let task = queue.get_task("pair").unwrap();
+    assert_eq!(task.status, TaskStatus::Open);
+    assert!(task.assignments.is_empty());
+    assert_eq!(queue.cancel_task("creator", &cancel), Ok(10));
+}
+
+#[test]
+fn test_replicated_results_follow_the_majority() {
+    let mut queue = TaskQueue::new();
+    let registration = WorkerRegistration {
+        capabilities: vec![],
+    };
+    for worker in ["w1", "w2", "w3"] {
+        queue.register_worker(worker, &registration, 5, 0).unwrap();
+    }
+    let run = |queue: &mut TaskQueue, id: &str, reward: u64, results: [&[u8]; 3]| {
+        let mut task = Task::new(id.into(), "".into(), vec![], reward, vec![], vec![]);
+        task.replicas = 3;
+        queue.add_task(task);
+        let workers = ["w1", "w2", "w3"];
+        for worker in workers {
+            let claim = TaskClaim {
+                task_id: id.to_string(),
+            };
+            queue.claim(worker, &claim, 1).unwrap();
+        }
+        for (worker, result) in workers.iter().zip(results) {
+            let commit = TaskCommit {
+                task_id: id.to_string(),
+                commitment: result_commitment(worker, result, b"salt"),
+            };
+            queue.commit_result(worker, &commit, 2).unwrap();
+        }
+        for (worker, result) in workers.iter().zip(results) {
+            let reveal = TaskReveal {
+                task_id: id.to_string(),
+                result: result.to_vec(),
+                salt: b"salt".to_vec(),
+            };
+            queue.reveal_result(worker, &reveal, 3).unwrap();
+        }
+        queue.process_block(4, "proposer", 0).0
+    };
+
+    // The first agreeing worker takes the remainder of the split.
+    let payouts = run(&mut queue, "split", 101, [b"a", b"b", b"a"]);
+    assert_eq!(
+        payouts,
+        vec![("w1".to_string(), 51), ("w3".to_string(), 50)]
+    );
+    let task = queue.get_task("split").unwrap();
+    assert_eq!(task.status, TaskStatus::Verified);
+    assert_eq!(task.accepted_workers(), ["w1", "w3"]);
+    assert_eq!(task.rejections[0].worker, "w2");
+    assert_eq!(task.rejections[0].reason, RejectReason::Dissent);
+
+    let payouts = run(&mut queue, "split3", 10, [b"a", b"b", b"c"]);
+    assert!(payouts.is_empty());
+    let task = queue.get_task("split3").unwrap();
+    assert_eq!(task.status, TaskStatus::Disputed);
+    assert!(task.assignments.is_empty());
+    assert_eq!(task.rejections.len(), 3);
+    assert!(task
+        .rejections
+        .iter()
+        .all(|rejection| rejection.reason == RejectReason::NoMajority));
+    assert_eq!(queue.dissent_counts().get("w2"), Some(&1));
+}
+
+#[test]
+fn test_worker_reputation_follows_task_outcomes() {
+    let mut queue = TaskQueue::new();
+    let registration = |capabilities: &[&str]| WorkerRegistration {
+        capabilities: capabilities.iter().map(|name| name.to_string()).collect(),
+    };
+    assert_eq!(
+        queue.register_worker("w1", &registration(&["echo", "echo"]), 0, 0),
+        Err(TaskError::InvalidCapabilities)
+    );
+    assert_eq!(
+        queue.register_worker("w1", &registration(&[""]), 0, 0),
+        Err(TaskError::InvalidCapabilities)
+    );
+    queue
+        .register_worker("w1", &registration(&["echo"]), 5, 0)
+        .unwrap();
+    queue
+        .register_worker("w2", &registration(&[]), 5, 0)
+        .unwrap();
+    queue
+        .register_worker("w3", &registration(&[]), 5, 0)
+        .unwrap();
+    assert_eq!(queue.get_worker("w1").unwrap().reputation(), 5_000);
+
+    let mut task = Task::new("vote".into(), "".into(), vec![], 10, vec![], vec![]);
+    task.replicas = 3;
+    queue.add_task(task);
+    let workers = ["w1", "w2", "w3"];
+    let results: [&[u8]; 3] = [b"a", b"b", b"a"];
+    for worker in workers {
+        let claim = TaskClaim {
+            task_id: "vote".to_string(),
+        };
+        queue.claim(worker, &claim, 1).unwrap();
+    }
+    for (worker, result) in workers.iter().zip(results) {
+        let commit = TaskCommit {
+            task_id: "vote".to_string(),
+            commitment: result_commitment(worker, result, b"salt"),
+        };
+        queue.commit_result(worker, &commit, 2).unwrap();
+    }
+    for (worker, result) in workers.iter().zip(results) {
+        let reveal = TaskReveal {
+            task_id: "vote".to_string(),
+            result: result.to_vec(),
+            salt: b"salt".to_vec(),
+        };
+        queue.reveal_result(worker, &reveal, 3).unwrap();
+    }
+    queue.process_block(4, "proposer", 0);
+    assert_eq!(queue.get_worker("w1").unwrap().reputation(), 6_666);
+    let w2 = queue.get_worker("w2").unwrap();
+    assert_eq!((w2.verified, w2.rejected), (0, 1));
+    assert_eq!(w2.reputation(), 3_333);
+    assert_eq!(
+        w2.history,
+        vec![WorkerEvent {
+            task_id: "vote".to_string(),
+            height: 4,
+            outcome: TaskOutcome::Rejected,
+            reason: Some(RejectReason::Dissent),
+        }]
+    );
+    assert_eq!(queue.get_worker("w3").unwrap().verified, 1);
+
+    // Only active workers with enough reputation may claim a gated task.
+    let mut task = Task::new("gated".into(), "".into(), vec![], 10, vec![], vec![]);
+    task.min_reputation = 6_000;
+    task.claim_blocks = 1;
+    queue.add_task(task);
+    let claim = TaskClaim {
+        task_id: "gated".to_string(),
+    };
+    assert_eq!(
+        queue.claim("w2", &claim, 5),
+        Err(TaskError::InsufficientReputation {
+            required: 6_000,
+            reputation: 3_333
+        })
+    );
+    assert_eq!(queue.claim("w4", &claim, 5), Err(TaskError::UnknownWorker));
+    queue.claim("w1", &claim, 5).unwrap();
+    assert_eq!(queue.unregister_worker("w1"), Err(TaskError::WorkerBusy));
+
+    // A lapsed claim counts as expired.
+    queue.process_block(7, "proposer", 0);
+    let w1 = queue.get_worker("w1").unwrap();
+    assert_eq!((w1.verified, w1.expired), (1, 1));
+    assert_eq!(w1.reputation(), 5_000);
+    assert_eq!(w1.history[1].outcome, TaskOutcome::Expired);
+
+    assert_eq!(queue.unregister_worker("w2"), Ok(5));
+    assert_eq!(queue.unregister_worker("w2"), Err(TaskError::UnknownWorker));
+    assert_eq!(queue.get_worker("w2").unwrap().rejected, 1);
+}
+
+#[test]
+fn test_task_programs_are_deterministic_and_fuel_metered() {
+    let echo = common::task_program(common::ECHO);
+    let execution = execute(&echo, b"abc", 10_000).unwrap();
+    assert_eq!(execution.output, b"abc");
+    assert!(execution.fuel_used > 0);
+    assert_eq!(execute(&echo, b"abc", 10_000), Ok(execution));
+
+    // Fuel is what stops a program that never returns.
+    let spin = common::task_program(SPIN);
+    assert_eq!(execute(&spin, b"", 10_000), Err(WasmError::OutOfFuel));
+
+    // Programs get no host functions.
+    let imports = b"\0asm\x01\0\0\0\x01\x04\x01\x60\0\0\x02\x09\x01\x03env\x01f\0\0";
+    assert_eq!(validate(imports), Err(WasmError::HasImports));
+    assert!(matches!(
+        validate(b"not wasm"),
+        Err(WasmError::InvalidModule(_))
+    ));
+
+    let mut queue = TaskQueue::new();
+    let spec = |name: &str, code: &[u8], fuel_limit: u64| TaskTypeSpec {
+        name: name.to_string(),
+        code: code.to_vec(),
+        fuel_limit,
+    };
+    assert_eq!(
+        queue.register_task_type("owner", &spec("echo", &echo, 0)),
+        Err(TaskError::InvalidFuelLimit)
+    );
+    queue
+        .register_task_type("owner", &spec("echo", &echo, 10_000))
+        .unwrap();
+    assert_eq!(
+        queue.register_task_type("other", &spec("echo", &spin, 10_000)),
+        Err(TaskError::DuplicateTaskType)
+    );
+
+    // A program that cannot finish on the task's data leaves nothing to
+    // check a result against.
+    queue
+        .register_task_type("owner", &spec("spin", &spin, 10_000))
+        .unwrap();
+    let mut task = Task::new("stuck".into(), "".into(), vec![], 10, vec![], vec![]);
+    task.task_type = Some("spin".to_string());
+    queue.add_task(task);
+    let claim = TaskClaim {
+        task_id: "stuck".to_string(),
+    };
+    queue.claim("worker", &claim, 1).unwrap();
+    let submission = TaskResult {
+        task_id: "stuck".to_string(),
+        result: b"done".to_vec(),
+    };
+    queue.submit_result("worker", &submission, 2).unwrap();
+    assert!(queue.process_block(3, "proposer", 0).0.is_empty());
+    let task = queue.get_task("stuck").unwrap();
+    assert_eq!(task.status, TaskStatus::Disputed);
+    assert_eq!(task.rejections[0].reason, RejectReason::ExecutionFailed);
+}
+
+/// `run` body that loops forever.
+const SPIN: &[u8] = &[0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x00, 0x0b];
+
+fn validator_keys(count: usize) -> Vec<std::sync::Arc<NodeKey>> {
+    (0..count)
+        .map(|_| std::sync::Arc::new(NodeKey::generate().unwrap()))
+        .collect()
+}
 
-    let block = Block::new(1, "0".to_string(), vec![]);
-    assert!(blockchain.validate_block(&block));
+/// A genesis where each of `keys` is a validator with a stake of 1,000.
+fn bft_genesis(keys: &[std::sync::Arc<NodeKey>]) -> GenesisConfig {
+    let mut genesis = common::test_genesis();
+    genesis.validators = keys
+        .iter()
+        .map(|key| GenesisValidator {
+            address: key.id(),
+            stake: 1_000,
+            ..Default::default()
+        })
+        .collect();
+    genesis
 }
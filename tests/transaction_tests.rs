@@ -0,0 +1,45 @@
+#[test]
+fn test_overdraft_is_rejected_without_touching_state() {
+    let mut accounts = AccountState::new();
+    accounts.credit("alice", 50).unwrap();
+
+    let tx = Transaction {
+        sender: "alice".to_string(),
+        receiver: "bob".to_string(),
+        amount: 80,
+        nonce: 0,
+        kind: TransactionKind::Transfer,
+        signature: vec![],
+    };
+    assert_eq!(
+        accounts.apply_transactions(&[tx]),
+        Err((
+            0,
+            TransactionError::InsufficientBalance {
+                balance: 50,
+                amount: 80
+            }
+        ))
+    );
+    assert_eq!(accounts.balance("alice"), 50);
+    assert_eq!(accounts.nonce("alice"), 0);
+}
+
+#[test]
+fn test_transfer_updates_balances_and_nonce() {
+    let mut accounts = AccountState::new();
+    accounts.credit("alice", 50).unwrap();
+
+    let tx = Transaction {
+        sender: "alice".to_string(),
+        receiver: "bob".to_string(),
+        amount: 20,
+        nonce: 0,
+        kind: TransactionKind::Transfer,
+        signature: vec![],
+    };
+    accounts.apply_transactions(&[tx]).unwrap();
+    assert_eq!(accounts.balance("alice"), 30);
+    assert_eq!(accounts.balance("bob"), 20);
+    assert_eq!(accounts.nonce("alice"), 1);
+}